@@ -2,7 +2,9 @@ use leptos::prelude::*;
 use leptos::html::Div;
 use leptos_router::hooks::use_query_map;
 use leptos_router::components::{Router, Routes, Route};
-use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::ops::RangeInclusive;
 use wasm_bindgen::prelude::*;
 use web_sys::{FileSystemFileHandle, FileSystemWritableFileStream, File, Blob};
 use js_sys::{ArrayBuffer, Uint8Array};
@@ -10,7 +12,15 @@ use regex::Regex;
 
 use gloo_storage::{Storage, LocalStorage};
 
-use crate::models::{DisassemblyInfo, DisassemblyLine, ThemeConfig};
+use crate::import::{self, cdl, tracelog, ConflictPolicy, FieldChange, Listing, MergePreview, MergeReport};
+use crate::models::{Assembler, DisassemblyInfo, DisassemblyLine, JumpTableInfo, RegionInfo, ThemeConfig};
+use crate::rom::{IdentityCheck, RomImage};
+use crate::disasm::trace::{self, TraceResult};
+use crate::disasm::validate;
+use crate::disasm::xref::XRefIndex;
+use crate::emu::{self, RunResult, RunSetup};
+use crate::search::Query;
+use crate::history::History;
 use crate::{database, disasm};
 
 #[wasm_bindgen]
@@ -26,7 +36,7 @@ extern "C" {
 struct AppState {
     db: RwSignal<Option<DisassemblyInfo>>,
     db_handle: RwSignal<Option<FileSystemFileHandle>>,
-    rom_data: RwSignal<Option<Vec<u8>>>,
+    rom: RwSignal<Option<RomImage>>,
     current_bank: RwSignal<u8>,
     active_theme: RwSignal<String>,
     themes: RwSignal<BTreeMap<String, ThemeConfig>>,
@@ -50,14 +60,37 @@ struct AppState {
     search_query: RwSignal<String>,
     search_current_idx: RwSignal<usize>,
     disassembly: Memo<Vec<DisassemblyLine>>,
-    search_results: Memo<Vec<u16>>,
+    // Search every bank and the global symbols instead of the current bank.
+    search_all_banks: RwSignal<bool>,
+    // Matching lines as (bank, address), in bank order; global lines use bank -1.
+    search_results: Memo<Vec<(i16, u16)>>,
+    // The parsed query, or why it does not parse.
+    search: Memo<Result<Query, String>>,
+    // Plain text of the query to highlight in the grid.
+    search_highlight: Memo<String>,
+
+    // Line (bank, address) whose references are listed in the xref panel.
+    xref_target: RwSignal<Option<(i16, u16)>>,
+
+    // Annotation and region edits, for undo/redo.
+    history: RwSignal<History>,
+    // Edits made since the database was last saved.
+    dirty: RwSignal<bool>,
+    // Address range of the lines selected in the current bank for region edits.
+    selection: RwSignal<Option<(u16, u16)>>,
+    // Why the last symbol edit was rejected.
+    edit_error: RwSignal<Option<String>>,
+    // Changes made to the project when it was opened, for the user to review.
+    notice: RwSignal<Option<String>>,
+    // Lines the last emulator run executed, as (bank, address).
+    executed: RwSignal<HashSet<(u8, u16)>>,
 }
 
 #[component]
 pub fn App() -> impl IntoView {
     let db = RwSignal::new(None::<DisassemblyInfo>);
     let db_handle = RwSignal::new(None::<FileSystemFileHandle>);
-    let rom_data = RwSignal::new(None::<Vec<u8>>);
+    let rom = RwSignal::new(None::<RomImage>);
     let current_bank = RwSignal::new(0u8);
     let active_theme = RwSignal::new(LocalStorage::get::<String>("activeTheme").unwrap_or_else(|_| "Dark".to_string()));
     
@@ -99,6 +132,7 @@ pub fn App() -> impl IntoView {
 
     let mut initial_widths = BTreeMap::new();
     initial_widths.insert("addr".to_string(), 100);
+    initial_widths.insert("hits".to_string(), 60);
     initial_widths.insert("hex".to_string(), 200);
     initial_widths.insert("op".to_string(), 60);
     initial_widths.insert("operand".to_string(), 150);
@@ -114,60 +148,62 @@ pub fn App() -> impl IntoView {
 
     let search_query = RwSignal::new(String::new());
     let search_current_idx = RwSignal::new(0usize);
+    let search_all_banks = RwSignal::new(false);
+    let xref_target = RwSignal::new(None::<(i16, u16)>);
+    let history = RwSignal::new(History::default());
+    let dirty = RwSignal::new(false);
+    let selection = RwSignal::new(None::<(u16, u16)>);
+    let edit_error = RwSignal::new(None::<String>);
+    let notice = RwSignal::new(None::<String>);
+    let executed = RwSignal::new(HashSet::new());
 
     let disassembly = Memo::new(move |_| {
         let bank_id = current_bank.get();
         let db = db.get();
-        let rom_data = rom_data.get();
+        let rom = rom.get();
         
-        if let (Some(db), Some(rom_data)) = (db, rom_data) {
-            let bank_targets = disasm::discover_all_targets(&db, &rom_data);
+        if let (Some(db), Some(rom)) = (db, rom) {
+            let labels = disasm::discover_all_targets(&db, &rom);
             if bank_id == 255 {
-                let mut lines = Vec::new();
-                for (addr, anno) in &db.global {
-                    lines.push(DisassemblyLine {
-                        address: *addr,
-                        address_label: format!("${:04X}", addr),
-                        bank: -1,
-                        bytes: String::new(),
-                        opcode: String::new(),
-                        operand_prefix: String::new(),
-                        operand_main: String::new(),
-                        operand_suffix: String::new(),
-                        operand_is_symbol: false,
-                        symbol: anno.symbol.clone(),
-                        comment: anno.comment.clone(),
-                        block_comment: anno.block_comment.clone(),
-                        target_bank: None,
-                        target_address: None,
-                    });
-                }
-                lines
+                global_lines(&db)
             } else {
-                let mapper_size = db.mapper_window_size as usize * 1024;
-                let rom_offset = 16 + (bank_id as usize * mapper_size);
-                let rom_end = (rom_offset + mapper_size).min(rom_data.len());
-                let bank_data = if rom_offset < rom_data.len() { &rom_data[rom_offset..rom_end] } else { &[] };
-                disasm::disassemble_bank(&db, bank_id, bank_data, &bank_targets)
+                let bank_data = disasm::bank_data(&db, &rom, bank_id);
+                disasm::disassemble_bank(&db, bank_id, bank_data, &labels)
             }
         } else {
             Vec::new()
         }
     });
 
+    // Every bank's lines followed by the global symbols, disassembled only
+    // while searching all banks.
+    let all_lines = Memo::new(move |_| {
+        if !search_all_banks.get() {
+            return Vec::new();
+        }
+        let (Some(db), Some(rom)) = (db.get(), rom.get()) else { return Vec::new() };
+        let labels = disasm::discover_all_targets(&db, &rom);
+        let mut lines = Vec::new();
+        for bank_id in 0..disasm::prg_bank_count(&db, &rom) {
+            lines.extend(disasm::disassemble_bank(&db, bank_id, disasm::bank_data(&db, &rom, bank_id), &labels));
+        }
+        lines.extend(global_lines(&db));
+        lines
+    });
+
+    let search = Memo::new(move |_| Query::parse(&search_query.get()));
+    let search_highlight = Memo::new(move |_| search.with(|q| q.as_ref().map(Query::highlight).unwrap_or_default()));
+
     let search_results = Memo::new(move |_| {
-        let query = search_query.get().to_lowercase();
-        if query.is_empty() { return Vec::new(); }
-        
-        disassembly.get().iter()
-            .filter(|line| {
-                line.symbol.as_ref().map_or(false, |s| s.to_lowercase().contains(&query)) ||
-                line.operand_main.to_lowercase().contains(&query) ||
-                line.comment.as_ref().map_or(false, |c| c.to_lowercase().contains(&query)) ||
-                line.block_comment.as_ref().map_or(false, |bc| bc.to_lowercase().contains(&query))
-            })
-            .map(|line| line.address)
-            .collect::<Vec<_>>()
+        search.with(|query| {
+            let Ok(query) = query else { return Vec::new() };
+            if query.is_empty() { return Vec::new(); }
+
+            let matching = |lines: &Vec<DisassemblyLine>| {
+                query.matching_lines(lines).into_iter().map(|i| (lines[i].bank, lines[i].address)).collect::<Vec<_>>()
+            };
+            if search_all_banks.get() { all_lines.with(matching) } else { disassembly.with(matching) }
+        })
     });
 
     // Reset search index when results change
@@ -179,7 +215,7 @@ pub fn App() -> impl IntoView {
     let state = AppState {
         db,
         db_handle,
-        rom_data,
+        rom,
         current_bank,
         active_theme,
         themes,
@@ -195,7 +231,17 @@ pub fn App() -> impl IntoView {
         search_query,
         search_current_idx,
         disassembly,
+        search_all_banks,
         search_results,
+        search,
+        search_highlight,
+        xref_target,
+        history,
+        dirty,
+        selection,
+        edit_error,
+        notice,
+        executed,
     };
     provide_context(state.clone());
 
@@ -255,6 +301,53 @@ pub fn App() -> impl IntoView {
         }
     });
     
+    // Ctrl+Z / Ctrl+Shift+Z undo and redo edits, except inside a field being
+    // edited, where they act on its text.
+    Effect::new({
+        let state = state.clone();
+        move || {
+            let state = state.clone();
+            let window = web_sys::window().unwrap();
+            let closure = Closure::wrap(Box::new(move |ev: web_sys::KeyboardEvent| {
+                if !(ev.ctrl_key() || ev.meta_key()) || !ev.key().eq_ignore_ascii_case("z") {
+                    return;
+                }
+                let editing = web_sys::window()
+                    .and_then(|w| w.document())
+                    .and_then(|d| d.active_element())
+                    .and_then(|e| e.dyn_into::<web_sys::HtmlElement>().ok())
+                    .is_some_and(|e| e.is_content_editable() || e.tag_name() == "INPUT" || e.tag_name() == "TEXTAREA");
+                if editing {
+                    return;
+                }
+                ev.prevent_default();
+                if ev.shift_key() { redo(state.clone()) } else { undo(state.clone()) }
+            }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
+            window.add_event_listener_with_callback("keydown", closure.as_ref().unchecked_ref()).unwrap();
+            closure.forget();
+        }
+    });
+
+    // Warn before leaving with unsaved edits, and snapshot them periodically
+    // so they can be recovered after a crash.
+    Effect::new({
+        let state = state.clone();
+        move || {
+            let window = web_sys::window().unwrap();
+            let closure = Closure::wrap(Box::new(move |ev: web_sys::BeforeUnloadEvent| {
+                if dirty.get_untracked() {
+                    ev.prevent_default();
+                    ev.set_return_value("You have unsaved changes.");
+                }
+            }) as Box<dyn FnMut(web_sys::BeforeUnloadEvent)>);
+            window.add_event_listener_with_callback("beforeunload", closure.as_ref().unchecked_ref()).unwrap();
+            closure.forget();
+
+            let state = state.clone();
+            gloo_timers::callback::Interval::new(AUTOSAVE_INTERVAL_MS, move || autosave(&state)).forget();
+        }
+    });
+
     let on_mousemove = move |ev: web_sys::MouseEvent| {
         if let Some(col) = resizing.get() {
             let diff = ev.page_x() - start_x.get();
@@ -328,7 +421,7 @@ fn MainContent() -> impl IntoView {
         }
     });
 
-    let is_ready = Memo::new(move |_| state.db.get().is_some() && state.rom_data.get().is_some());
+    let is_ready = Memo::new(move |_| state.db.get().is_some() && state.rom.get().is_some());
 
     view! {
         <div class="app-container">
@@ -356,11 +449,13 @@ fn ThemeStyle() -> impl IntoView {
             {move || {
                 let t = theme_memo.get();
                 let w = state.col_widths.get();
+                // The Hits column is only shown once a trace log has been imported.
+                let has_hits = state.db.with(|db| db.as_ref().is_some_and(|db| !db.coverage.is_empty()));
                 format!(
                     "body {{ background-color: {}; color: {}; 
-                            --col-addr: {}px; --col-hex: {}px; --col-op: {}px; --col-operand: {}px; }}
+                            --col-addr: {}px; --col-hits: {}px; --col-hex: {}px; --col-op: {}px; --col-operand: {}px; }}
 
-                     .header {{ background-color: {}; color: {}; border-bottom-color: {}; }}
+                     .header, .side-panel {{ background-color: {}; color: {}; border-color: {}; }}
 
                      select, input {{ background-color: {}; color: {}; border-color: {}; border-style: solid; border-width: 1px; padding: 2px 5px; }}
 
@@ -391,9 +486,13 @@ fn ThemeStyle() -> impl IntoView {
                      .search-highlight {{ background-color: {}; border-radius: 2px; }}
                      .search-highlight-active {{ background-color: {}; border-radius: 2px; }}
                      .search-match-cell {{ background-color: {}; }}
+                     .region-selected {{ box-shadow: inset 3px 0 0 {}; }}
+                     .executed .address {{ background-color: {}; }}
+                     .heat {{ background-color: {}; }}
 ",
                     t.background, t.instruction,
                     w.get("addr").unwrap_or(&100),
+                    if has_hits { *w.get("hits").unwrap_or(&60) } else { 0 },
                     w.get("hex").unwrap_or(&200),
                     w.get("op").unwrap_or(&60),
                     w.get("operand").unwrap_or(&150),
@@ -403,7 +502,8 @@ fn ThemeStyle() -> impl IntoView {
                     t.address,
                     t.address, t.hex, t.instruction, t.opcode,
                     t.instruction, t.comment, t.symbol, t.symbol,
-                    t.highlight, t.current_highlight, t.match_cell
+                    t.highlight, t.current_highlight, t.match_cell,
+                    t.current_highlight, t.highlight, t.current_highlight
                 )
             }}
         </style>
@@ -414,6 +514,39 @@ fn ThemeStyle() -> impl IntoView {
 fn SetupScreen() -> impl IntoView {
     let state = use_context::<AppState>().expect("state should be provided");
 
+    // Offer edits autosaved by an earlier session that was never saved.
+    let recovery = RwSignal::new(None::<Autosave>);
+    let recovery_checked = RwSignal::new(false);
+    let recovery_error = RwSignal::new(None::<String>);
+    Effect::new(move || {
+        if let Some(db) = state.db.get()
+            && !recovery_checked.get_untracked()
+        {
+            recovery_checked.set(true);
+            recovery.set(LocalStorage::get::<Autosave>(autosave_key(&db)).ok());
+        }
+    });
+    let restore = {
+        let state = state.clone();
+        move || {
+            let Some(saved) = recovery.get_untracked() else { return };
+            match database::parse_db(&saved.database) {
+                Ok(db) => {
+                    load_db(&state, db);
+                    state.dirty.set(true);
+                }
+                Err(e) => recovery_error.set(Some(format!("Autosaved database is unreadable: {}", e))),
+            }
+            recovery.set(None);
+        }
+    };
+    let discard = move || {
+        if let Some(db) = state.db.get_untracked() {
+            LocalStorage::delete(autosave_key(&db));
+        }
+        recovery.set(None);
+    };
+
     let open_db = {
         let state = state.clone();
         move |_| {
@@ -445,7 +578,7 @@ fn SetupScreen() -> impl IntoView {
                                 if let Ok(text_val) = wasm_bindgen_futures::JsFuture::from(text_promise).await {
                                     let text: String = text_val.as_string().unwrap();
                                     if let Ok(parsed) = database::parse_db(&text) {
-                                        state.db.set(Some(parsed));
+                                        load_db(&state, parsed);
                                     }
                                 }
                             }
@@ -456,6 +589,20 @@ fn SetupScreen() -> impl IntoView {
         }
     };
 
+    let rom_error = RwSignal::new(None::<String>);
+    // A ROM that is not known to match the project, waiting for confirmation.
+    let pending_rom = RwSignal::new(None::<(RomImage, IdentityCheck)>);
+
+    let accept_rom = {
+        let state = state.clone();
+        move |record: bool| {
+            if let Some((rom, _)) = pending_rom.get_untracked() {
+                pending_rom.set(None);
+                use_rom(&state, rom, record);
+            }
+        }
+    };
+
     let open_rom = {
         let state = state.clone();
         move |_| {
@@ -486,7 +633,22 @@ fn SetupScreen() -> impl IntoView {
                                     let buffer: ArrayBuffer = buffer_val.unchecked_into();
                                     let array = Uint8Array::new(&buffer);
                                     let bytes = array.to_vec();
-                                    state.rom_data.set(Some(bytes));
+                                    let loaded = RomImage::parse(bytes).and_then(|rom| {
+                                        state.db.with_untracked(|db| db.as_ref().map_or(Ok(()), |db| rom.validate(db)))?;
+                                        Ok(rom)
+                                    });
+                                    pending_rom.set(None);
+                                    match loaded {
+                                        Ok(rom) => {
+                                            rom_error.set(None);
+                                            let check = state.db.with_untracked(|db| db.as_ref().map(|db| rom.check_identity(db)));
+                                            match check {
+                                                Some(IdentityCheck::Match) | None => use_rom(&state, rom, false),
+                                                Some(check) => pending_rom.set(Some((rom, check))),
+                                            }
+                                        }
+                                        Err(e) => rom_error.set(Some(e)),
+                                    }
                                 }
                             }
                         }
@@ -496,6 +658,122 @@ fn SetupScreen() -> impl IntoView {
         }
     };
 
+    // The last imported listing, its merge report and how conflicts were resolved.
+    let import_result = RwSignal::new(None::<(Listing, MergeReport, ConflictPolicy)>);
+
+    let open_listing = {
+        let state = state.clone();
+        move |_| {
+            let state = state.clone();
+            leptos::task::spawn_local(async move {
+                let options = js_sys::Object::new();
+                let types = js_sys::Array::new();
+                let type_info = js_sys::Object::new();
+                let accept = js_sys::Object::new();
+                let extensions = js_sys::Array::new();
+                extensions.push(&JsValue::from_str(".s"));
+                extensions.push(&JsValue::from_str(".asm"));
+                extensions.push(&JsValue::from_str(".inc"));
+                js_sys::Reflect::set(&accept, &JsValue::from_str("text/plain"), &extensions).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("description"), &JsValue::from_str("Assembly Source")).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("accept"), &accept).unwrap();
+                types.push(&type_info);
+                js_sys::Reflect::set(&options, &JsValue::from_str("types"), &types).unwrap();
+                js_sys::Reflect::set(&options, &JsValue::from_str("multiple"), &JsValue::TRUE).unwrap();
+
+                let Ok(promise) = show_open_file_picker(&options) else { return };
+                let Ok(handles_val) = wasm_bindgen_futures::JsFuture::from(promise).await else { return };
+                let handles: js_sys::Array = handles_val.unchecked_into();
+                let mut sources = Vec::new();
+                for handle in handles.iter() {
+                    let handle: FileSystemFileHandle = handle.unchecked_into();
+                    let Ok(file_val) = wasm_bindgen_futures::JsFuture::from(handle.get_file()).await else { continue };
+                    let file: File = file_val.unchecked_into();
+                    if let Ok(text_val) = wasm_bindgen_futures::JsFuture::from(file.text()).await {
+                        sources.push(text_val.as_string().unwrap_or_default());
+                    }
+                }
+                if let (false, Some(mut db)) = (sources.is_empty(), state.db.get_untracked()) {
+                    let listing = import::ca65::import_sources(&db, &sources, &Default::default());
+                    let report = import::merge(&mut db, &listing, ConflictPolicy::KeepExisting);
+                    commit_edit(state.clone(), "Import listing", db);
+                    import_result.set(Some((listing, report, ConflictPolicy::KeepExisting)));
+                }
+            });
+        }
+    };
+
+    let overwrite_conflicts = {
+        let state = state.clone();
+        move || {
+            if let (Some((listing, _, _)), Some(mut db)) = (import_result.get_untracked(), state.db.get_untracked()) {
+                let report = import::merge(&mut db, &listing, ConflictPolicy::Overwrite);
+                commit_edit(state.clone(), "Overwrite import conflicts", db);
+                import_result.set(Some((listing, report, ConflictPolicy::Overwrite)));
+            }
+        }
+    };
+
+    // Debugger labels read but not merged yet, what merging them would do and
+    // any lines that were skipped; then the outcome of the merge.
+    let label_import = RwSignal::new(None::<(Listing, MergePreview, Vec<String>)>);
+    let label_result = RwSignal::new(None::<String>);
+
+    let open_labels = {
+        let state = state.clone();
+        move |_| {
+            let state = state.clone();
+            leptos::task::spawn_local(async move {
+                let options = js_sys::Object::new();
+                let types = js_sys::Array::new();
+                let type_info = js_sys::Object::new();
+                let accept = js_sys::Object::new();
+                let extensions = js_sys::Array::new();
+                extensions.push(&JsValue::from_str(".mlb"));
+                extensions.push(&JsValue::from_str(".nl"));
+                js_sys::Reflect::set(&accept, &JsValue::from_str("text/plain"), &extensions).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("description"), &JsValue::from_str("Debugger Labels")).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("accept"), &accept).unwrap();
+                types.push(&type_info);
+                js_sys::Reflect::set(&options, &JsValue::from_str("types"), &types).unwrap();
+                js_sys::Reflect::set(&options, &JsValue::from_str("multiple"), &JsValue::TRUE).unwrap();
+
+                let Ok(promise) = show_open_file_picker(&options) else { return };
+                let Ok(handles_val) = wasm_bindgen_futures::JsFuture::from(promise).await else { return };
+                let handles: js_sys::Array = handles_val.unchecked_into();
+                let mut files = Vec::new();
+                for handle in handles.iter() {
+                    let handle: FileSystemFileHandle = handle.unchecked_into();
+                    let Ok(file_val) = wasm_bindgen_futures::JsFuture::from(handle.get_file()).await else { continue };
+                    let file: File = file_val.unchecked_into();
+                    if let Ok(text_val) = wasm_bindgen_futures::JsFuture::from(file.text()).await {
+                        files.push((file.name(), text_val.as_string().unwrap_or_default()));
+                    }
+                }
+                if let (false, Some(db)) = (files.is_empty(), state.db.get_untracked()) {
+                    let mut warnings = Vec::new();
+                    let listing = import::labels::read_files(&db, &files, &mut warnings);
+                    let preview = import::preview(&db, &listing);
+                    label_result.set(None);
+                    label_import.set(Some((listing, preview, warnings)));
+                }
+            });
+        }
+    };
+
+    let merge_labels = {
+        let state = state.clone();
+        move |policy: ConflictPolicy| {
+            if let (Some((listing, _, _)), Some(mut db)) = (label_import.get_untracked(), state.db.get_untracked()) {
+                let report = import::merge(&mut db, &listing, policy);
+                commit_edit(state.clone(), "Import debugger labels", db);
+                let resolution = if policy == ConflictPolicy::Overwrite { "replaced" } else { "kept existing" };
+                label_import.set(None);
+                label_result.set(Some(format!("{} field(s) added, {} changed field(s) {}.", report.added.len(), report.conflicts.len(), resolution)));
+            }
+        }
+    };
+
     let load_remote_db = {
         let state = state.clone();
         move |url: String| {
@@ -504,7 +782,7 @@ fn SetupScreen() -> impl IntoView {
                 if let Ok(resp) = gloo_net::http::Request::get(&url).send().await {
                     if let Ok(text) = resp.text().await {
                         if let Ok(parsed) = database::parse_db(&text) {
-                            state.db.set(Some(parsed));
+                            load_db(&state, parsed);
                         }
                     }
                 }
@@ -550,11 +828,117 @@ fn SetupScreen() -> impl IntoView {
             } else {
                 let title = state.db.get().map(|d| d.title).unwrap_or_default();
                 let open_rom = open_rom.clone();
+                let open_listing = open_listing.clone();
+                let overwrite_conflicts = overwrite_conflicts.clone();
+                let open_labels = open_labels.clone();
+                let merge_labels = merge_labels.clone();
+                let restore = restore.clone();
+                let accept_rom = accept_rom.clone();
                 view! {
                     <div class="setup-step">
                         <p>"Database loaded: " <strong>{title}</strong></p>
+                        {move || recovery.get().map(|saved| {
+                            let restore = restore.clone();
+                            let when = js_sys::Date::new(&JsValue::from_f64(saved.saved_at)).to_locale_string("default", &JsValue::UNDEFINED);
+                            view! {
+                                <p class="setup-error">{format!("Unsaved changes from {} were found for this project.", String::from(when))}</p>
+                                <button type="button" on:click=move |e| { e.prevent_default(); restore(); }>"Restore"</button>
+                                <button type="button" on:click=move |e| { e.prevent_default(); discard(); }>"Discard"</button>
+                            }
+                        })}
+                        {move || recovery_error.get().map(|e| view! { <p class="setup-error">{e}</p> })}
                         <p>"Please provide the NES ROM file for this project."</p>
                         <button type="button" on:click=move |e| { e.prevent_default(); open_rom(e); }>"Open ROM"</button>
+                        {move || rom_error.get().map(|e| view! { <p class="setup-error">{e}</p> })}
+                        {move || pending_rom.get().map(|(rom, check)| {
+                            let accept_rom = accept_rom.clone();
+                            let hashes = rom.hashes();
+                            let hash_text = format!("CRC32 {}, SHA-1 {}", hashes.crc32, hashes.sha1);
+                            match check {
+                                IdentityCheck::Mismatch(message) => view! {
+                                    <p class="setup-error">{message}</p>
+                                    <p>"This is probably a different revision of the game; the disassembly will not line up."</p>
+                                    <button type="button" on:click=move |e| { e.prevent_default(); accept_rom(false); }>"Open Anyway"</button>
+                                }.into_any(),
+                                _ => view! {
+                                    <p>"This project is not bound to a ROM revision yet. Opened ROM: " {hash_text}</p>
+                                    <button type="button" on:click={
+                                        let accept_rom = accept_rom.clone();
+                                        move |e| { e.prevent_default(); accept_rom(true); }
+                                    }>"Record Hash and Continue"</button>
+                                    <button type="button" on:click=move |e| { e.prevent_default(); accept_rom(false); }>"Continue Without Recording"</button>
+                                }.into_any(),
+                            }
+                        })}
+                    </div>
+                    <div class="setup-step">
+                        <p>"Optionally merge labels and comments from da65/ca65 sources."</p>
+                        <button type="button" on:click=move |e| { e.prevent_default(); open_listing(e); }>"Import Listing"</button>
+                        {move || import_result.get().map(|(_, report, policy)| {
+                            let overwrite_conflicts = overwrite_conflicts.clone();
+                            let resolution = if policy == ConflictPolicy::Overwrite { "replaced" } else { "kept existing" };
+                            view! {
+                                <p>{format!("{} field(s) added, {} unchanged, {} conflict(s) ({}).", report.added.len(), report.unchanged, report.conflicts.len(), resolution)}</p>
+                                <ul class="import-conflicts">
+                                    {report.conflicts.iter().map(|c| {
+                                        let location = match c.bank {
+                                            Some(bank) => format!("${:02X}:${:04X}", bank, c.address),
+                                            None => format!("Global ${:04X}", c.address),
+                                        };
+                                        view! {
+                                            <li>{format!("{} {}: \"{}\" / \"{}\"", location, c.field, c.existing.clone().unwrap_or_default(), c.incoming)}</li>
+                                        }
+                                    }).collect_view()}
+                                </ul>
+                                {(policy == ConflictPolicy::KeepExisting && !report.conflicts.is_empty()).then(|| view! {
+                                    <button type="button" on:click=move |e| { e.prevent_default(); overwrite_conflicts(); }>"Use Imported Values"</button>
+                                })}
+                            }
+                        })}
+                    </div>
+                    <div class="setup-step">
+                        <p>"Optionally merge debugger labels from Mesen (.mlb) or FCEUX (.nl) files."</p>
+                        <button type="button" on:click=move |e| { e.prevent_default(); open_labels(e); }>"Import Labels"</button>
+                        {move || label_result.get().map(|result| view! { <p>{result}</p> })}
+                        {move || label_import.get().map(|(_, preview, warnings)| {
+                            let merge_labels = merge_labels.clone();
+                            let report = preview.report;
+                            let symbols = |changes: &[FieldChange]| changes.iter().filter(|c| c.field == "symbol").count();
+                            view! {
+                                <p>{format!(
+                                    "{} new symbol(s), {} changed, {} duplicate(s); {} other field(s) new, {} changed, {} unchanged.",
+                                    symbols(&report.added), symbols(&report.conflicts), preview.duplicates.len(),
+                                    report.added.len() - symbols(&report.added), report.conflicts.len() - symbols(&report.conflicts), report.unchanged
+                                )}</p>
+                                <ul class="import-conflicts">
+                                    {report.added.iter().filter(|c| c.field == "symbol").map(|c| view! {
+                                        <li>{format!("+ {}: {}", change_location(c), c.incoming)}</li>
+                                    }).collect_view()}
+                                    {report.conflicts.iter().map(|c| view! {
+                                        <li>{format!("{} {}: \"{}\" -> \"{}\"", change_location(c), c.field, c.existing.clone().unwrap_or_default(), c.incoming)}</li>
+                                    }).collect_view()}
+                                    {preview.duplicates.iter().map(|(c, other)| view! {
+                                        <li class="setup-error">{format!("{}: {} is also defined at {}", change_location(c), c.incoming, other)}</li>
+                                    }).collect_view()}
+                                </ul>
+                                {(!warnings.is_empty()).then(|| view! {
+                                    <details>
+                                        <summary>{format!("{} line(s) skipped", warnings.len())}</summary>
+                                        <ul class="import-conflicts">
+                                            {warnings.into_iter().map(|w| view! { <li>{w}</li> }).collect_view()}
+                                        </ul>
+                                    </details>
+                                })}
+                                <button type="button" on:click={
+                                    let merge_labels = merge_labels.clone();
+                                    move |e| { e.prevent_default(); merge_labels(ConflictPolicy::KeepExisting); }
+                                }>"Merge, Keeping Existing"</button>
+                                {(!report.conflicts.is_empty()).then(|| view! {
+                                    <button type="button" on:click=move |e| { e.prevent_default(); merge_labels(ConflictPolicy::Overwrite); }>"Merge, Using Imported Values"</button>
+                                })}
+                                <button type="button" on:click=move |e| { e.prevent_default(); label_import.set(None); }>"Cancel"</button>
+                            }
+                        })}
                     </div>
                 }.into_any()
             }}
@@ -562,6 +946,14 @@ fn SetupScreen() -> impl IntoView {
     }
 }
 
+// Where a merged field lives, as `$BB:$AAAA` or `Global $AAAA`.
+fn change_location(change: &FieldChange) -> String {
+    match change.bank {
+        Some(bank) => format!("${:02X}:${:04X}", bank, change.address),
+        None => format!("Global ${:04X}", change.address),
+    }
+}
+
 #[component]
 fn DisasmView() -> impl IntoView {
     let state = use_context::<AppState>().expect("state should be provided");
@@ -569,13 +961,8 @@ fn DisasmView() -> impl IntoView {
     let banks = move || {
         let mut b = BTreeMap::new();
         if let Some(ref d) = state.db.get() {
-            let rom_data = state.rom_data.get().unwrap();
-            let prg_banks_16k = rom_data[4];
-            let num_banks = match d.mapper_window_size {
-                8 => prg_banks_16k * 2,
-                16 => prg_banks_16k,
-                _ => prg_banks_16k,
-            };
+            let rom = state.rom.get().unwrap();
+            let num_banks = disasm::prg_bank_count(d, &rom);
             for i in 0..num_banks {
                 let title = d.bank.get(&i).and_then(|b| b.title.clone()).unwrap_or_default();
                 b.insert(i, title);
@@ -600,21 +987,89 @@ fn DisasmView() -> impl IntoView {
     let state_c2 = state.clone();
     let state_c3 = state.clone();
     let state_search = state.clone();
+    let show_trace = RwSignal::new(false);
+    let show_history = RwSignal::new(false);
+    // Open the problems panel straight away if the project has bad or
+    // duplicate symbol names.
+    let show_problems = RwSignal::new(state.db.with_untracked(|db| db.as_ref().is_some_and(|db| !validate::symbol_problems(db).is_empty())));
+    let show_run = RwSignal::new(false);
+    // A selection belongs to the bank it was made in.
+    Effect::new(move || {
+        let _ = state.current_bank.get();
+        state.selection.set(None);
+    });
+    let xref_target = state.xref_target;
+    // The trace, history, problems, run and xref panels share the right-hand
+    // side.
+    Effect::new(move || {
+        if xref_target.get().is_some() {
+            show_trace.set(false);
+            show_history.set(false);
+            show_problems.set(false);
+            show_run.set(false);
+        }
+    });
+    let tbl_error = RwSignal::new(None::<String>);
+
+    // Load a TBL character table for Text regions.
+    let load_tbl = {
+        let state = state.clone();
+        move || {
+            let state = state.clone();
+            leptos::task::spawn_local(async move {
+                let options = js_sys::Object::new();
+                let types = js_sys::Array::new();
+                let type_info = js_sys::Object::new();
+                let accept = js_sys::Object::new();
+                js_sys::Reflect::set(&accept, &JsValue::from_str("text/plain"), &JsValue::from_str(".tbl")).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("description"), &JsValue::from_str("Character Table")).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("accept"), &accept).unwrap();
+                types.push(&type_info);
+                js_sys::Reflect::set(&options, &JsValue::from_str("types"), &types).unwrap();
+
+                let Ok(promise) = show_open_file_picker(&options) else { return };
+                let Ok(handles_val) = wasm_bindgen_futures::JsFuture::from(promise).await else { return };
+                let handles: js_sys::Array = handles_val.unchecked_into();
+                let handle: FileSystemFileHandle = handles.get(0).unchecked_into();
+                let Ok(file_val) = wasm_bindgen_futures::JsFuture::from(handle.get_file()).await else { return };
+                let file: File = file_val.unchecked_into();
+                let Ok(text_val) = wasm_bindgen_futures::JsFuture::from(file.text()).await else { return };
+                match disasm::text::parse_tbl(&text_val.as_string().unwrap_or_default()) {
+                    Ok(table) => {
+                        let Some(mut db) = state.db.get_untracked() else { return };
+                        db.text_table = Some(table);
+                        commit_edit(state.clone(), "Load character table", db);
+                        tbl_error.set(None);
+                    }
+                    Err(e) => tbl_error.set(Some(format!("Character table: {}", e))),
+                }
+            });
+        }
+    };
     
-    let on_search_keydown = move |ev: web_sys::KeyboardEvent| {
-        if ev.key() == "Enter" {
-            ev.prevent_default();
-            let results = state_search.search_results.get();
-            if results.is_empty() { return; }
-            
-            let mut idx = state_search.search_current_idx.get();
-            if ev.ctrl_key() {
-                idx = if idx == 0 { results.len() - 1 } else { idx - 1 };
-            } else {
-                idx = (idx + 1) % results.len();
+    let on_search_keydown = {
+        let state = state_search.clone();
+        move |ev: web_sys::KeyboardEvent| {
+            if ev.key() == "Enter" {
+                ev.prevent_default();
+                let results = state.search_results.get();
+                if results.is_empty() { return; }
+
+                let mut idx = state.search_current_idx.get();
+                if ev.ctrl_key() {
+                    idx = if idx == 0 { results.len() - 1 } else { idx - 1 };
+                } else {
+                    idx = (idx + 1) % results.len();
+                }
+                state.search_current_idx.set(idx);
+                let (bank, addr) = results[idx];
+                let bank_id = if bank < 0 { 255 } else { bank as u8 };
+                if bank_id == state.current_bank.get_untracked() {
+                    state.nav_target.set(Some(addr));
+                } else {
+                    navigate(state.clone(), (bank >= 0).then_some(bank_id), addr);
+                }
             }
-            state_search.search_current_idx.set(idx);
-            state_search.nav_target.set(Some(results[idx]));
         }
     };
 
@@ -653,22 +1108,57 @@ fn DisasmView() -> impl IntoView {
                     <div>
                         <input 
                             type="text" 
-                            placeholder="Search..." 
+                            placeholder="Search... (sym: op: operand: comment: bytes:)" 
                             style="width: 250px;"
                             prop:value=move || state_search.search_query.get()
                             on:input=move |ev| state_search.search_query.set(event_target_value(&ev))
                             on:keydown=on_search_keydown
                         />
+                        <label style="margin-left: 5px; font-size: 0.8em;" title="Search every bank and the global symbols">
+                            <input type="checkbox"
+                                prop:checked=move || state_search.search_all_banks.get()
+                                on:change=move |ev| state_search.search_all_banks.set(event_target_checked(&ev))
+                            />
+                            "All banks"
+                        </label>
                         <span style="margin-left: 5px; font-size: 0.8em; opacity: 0.7;">
                             {move || {
                                 let results = state_search.search_results.get();
-                                if results.is_empty() {
+                                if let Err(e) = state_search.search.get() {
+                                    e
+                                } else if results.is_empty() {
                                     "".to_string()
                                 } else {
                                     format!("{} / {}", state_search.search_current_idx.get() + 1, results.len())
                                 }
                             }}
                         </span>
+                        {
+                            let state = state_search.clone();
+                            move || {
+                                let results = state.search_results.get();
+                                if !state.search_all_banks.get() || results.is_empty() {
+                                    return None;
+                                }
+                                let groups = hits_by_bank(&results);
+                                let current = results.get(state.search_current_idx.get()).map(|&(bank, _)| bank);
+                                let state = state.clone();
+                                let on_change = move |ev| {
+                                    let Ok(first) = event_target_value(&ev).parse::<usize>() else { return };
+                                    let Some(&(bank, addr)) = results.get(first) else { return };
+                                    state.search_current_idx.set(first);
+                                    navigate(state.clone(), u8::try_from(bank).ok(), addr);
+                                };
+                                Some(view! {
+                                    <select style="margin-left: 5px; font-size: 0.8em;" on:change=on_change>
+                                        {groups.into_iter().map(|(bank, first, count)| {
+                                            let name = if bank < 0 { "Global".to_string() } else { format!("${:02X}", bank) };
+                                            view! { <option value=first.to_string() selected=current == Some(bank)>{format!("{}: {} hit(s)", name, count)}</option> }
+                                        }).collect_view()}
+                                    </select>
+                                })
+                            }
+                        }
                     </div>
                     <div style="margin-left: auto;">
                         "Theme: "
@@ -686,11 +1176,27 @@ fn DisasmView() -> impl IntoView {
                             }
                         </select>
                     </div>
+                    <button type="button" on:click=move |e| { e.prevent_default(); load_tbl(); }>"Load TBL"</button>
+                    {move || tbl_error.get().map(|e| view! { <span class="setup-error">{e}</span> })}
+                    {move || state.edit_error.get().map(|e| view! { <span class="setup-error" title="Click to dismiss" on:click=move |_| state.edit_error.set(None)>{e}</span> })}
+                    {move || state.notice.get().map(|n| view! { <span class="setup-error" title="Click to dismiss" on:click=move |_| state.notice.set(None)>{n}</span> })}
+                    <button type="button" on:click=move |e| { e.prevent_default(); xref_target.set(None); show_history.set(false); show_problems.set(false); show_run.set(false); show_trace.update(|v| *v = !*v); }>"Trace Code"</button>
+                    <button type="button" on:click=move |e| { e.prevent_default(); xref_target.set(None); show_trace.set(false); show_problems.set(false); show_run.set(false); show_history.update(|v| *v = !*v); }>"History"</button>
+                    <button type="button" on:click=move |e| { e.prevent_default(); xref_target.set(None); show_trace.set(false); show_history.set(false); show_run.set(false); show_problems.update(|v| *v = !*v); }>"Problems"</button>
+                    <button type="button" on:click=move |e| { e.prevent_default(); xref_target.set(None); show_trace.set(false); show_history.set(false); show_problems.set(false); show_run.update(|v| *v = !*v); }>"Run"</button>
+                    {move || state.dirty.get().then(|| view! { <span class="setup-error" title="Edits are autosaved to this browser until you save">"Unsaved changes"</span> })}
                     <button type="button" on:click=move |e| { e.prevent_default(); save_db_logic(state_c2.clone()); }>"Save"</button>
                 </div>
+                <Show when=move || state.selection.get().is_some()>
+                    <RegionToolbar />
+                </Show>
                 <div class="grid-header">
                     <div class="grid-cell" style="width: var(--col-addr)">"Addr"</div>
                     <div class="resizer" on:mousedown={let state = state.clone(); move |ev| start_resizing(state.clone(), "addr", ev)}></div>
+                    {let state = state.clone(); move || state.db.with(|db| db.as_ref().is_some_and(|db| !db.coverage.is_empty())).then(|| view! {
+                        <div class="grid-cell" style="width: var(--col-hits)" title="Times each instruction ran in the imported trace logs">"Hits"</div>
+                        <div class="resizer" on:mousedown={let state = state.clone(); move |ev| start_resizing(state.clone(), "hits", ev)}></div>
+                    })}
                     <div class="grid-cell" style="width: var(--col-hex)">"Bytes"</div>
                     <div class="resizer" on:mousedown={let state = state.clone(); move |ev| start_resizing(state.clone(), "hex", ev)}></div>
                     <div class="grid-cell" style="width: var(--col-op)">"Op"</div>
@@ -701,6 +1207,822 @@ fn DisasmView() -> impl IntoView {
                 </div>
             </header>
             <VirtualizedDisasm />
+            <Show when=move || show_trace.get()>
+                <TracePanel show=show_trace />
+            </Show>
+            <Show when=move || show_history.get()>
+                <HistoryPanel show=show_history />
+            </Show>
+            <Show when=move || show_problems.get()>
+                <ProblemsPanel show=show_problems />
+            </Show>
+            <Show when=move || show_run.get()>
+                <RunPanel show=show_run />
+            </Show>
+            <Show when=move || xref_target.get().is_some()>
+                <XRefPanel />
+            </Show>
+        </div>
+    }
+}
+
+#[component]
+fn TracePanel(show: RwSignal<bool>) -> impl IntoView {
+    let state = use_context::<AppState>().expect("state should be provided");
+    let result = RwSignal::new(None::<TraceResult>);
+    let selected = RwSignal::new(Vec::<bool>::new());
+    let entry_input = RwSignal::new(String::new());
+    let entry_error = RwSignal::new(None::<String>);
+    // What the last imported trace log held, while its proposals are listed.
+    let log_summary = RwSignal::new(None::<String>);
+    // A code/data log compared with the regions, until merged or cancelled.
+    let cdl_diff = RwSignal::new(None::<(String, cdl::CdlDiff)>);
+    let cdl_selected = RwSignal::new(Vec::<bool>::new());
+    let cdl_error = RwSignal::new(None::<String>);
+
+    let run_trace = {
+        let state = state.clone();
+        move || {
+            let (Some(db), Some(rom)) = (state.db.get_untracked(), state.rom.get_untracked()) else { return };
+            let traced = trace::trace_code(&db, &rom, &trace::entry_points(&db, &rom));
+            selected.set(vec![true; traced.proposals.len()]);
+            result.set(Some(traced));
+            log_summary.set(None);
+        }
+    };
+    run_trace();
+
+    // Record the hit counts of an emulator trace log and list the Code
+    // regions it proposes instead of the tracer's.
+    let import_log = {
+        let state = state.clone();
+        move || {
+            let state = state.clone();
+            leptos::task::spawn_local(async move {
+                let options = js_sys::Object::new();
+                let types = js_sys::Array::new();
+                let type_info = js_sys::Object::new();
+                let accept = js_sys::Object::new();
+                let extensions = js_sys::Array::new();
+                extensions.push(&JsValue::from_str(".log"));
+                extensions.push(&JsValue::from_str(".txt"));
+                js_sys::Reflect::set(&accept, &JsValue::from_str("text/plain"), &extensions).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("description"), &JsValue::from_str("Trace Log")).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("accept"), &accept).unwrap();
+                types.push(&type_info);
+                js_sys::Reflect::set(&options, &JsValue::from_str("types"), &types).unwrap();
+
+                let Ok(promise) = show_open_file_picker(&options) else { return };
+                let Ok(handles_val) = wasm_bindgen_futures::JsFuture::from(promise).await else { return };
+                let handles: js_sys::Array = handles_val.unchecked_into();
+                let handle: FileSystemFileHandle = handles.get(0).unchecked_into();
+                let Ok(file_val) = wasm_bindgen_futures::JsFuture::from(handle.get_file()).await else { return };
+                let file: File = file_val.unchecked_into();
+                let Ok(text_val) = wasm_bindgen_futures::JsFuture::from(file.text()).await else { return };
+                let source = text_val.as_string().unwrap_or_default();
+                let (Some(mut db), Some(rom)) = (state.db.get_untracked(), state.rom.get_untracked()) else { return };
+                let log = tracelog::read_log(&db, &rom, &source);
+                tracelog::record_hits(&mut db, &log.hits);
+                commit_edit(state.clone(), "Import trace log", db);
+                selected.set(vec![true; log.proposals.len()]);
+                log_summary.set(Some(format!("{}: {} of {} instruction line(s) placed.", file.name(), log.placed, log.lines)));
+                result.set(Some(TraceResult { proposals: log.proposals, warnings: log.warnings }));
+            });
+        }
+    };
+
+    // Compare an FCEUX or Mesen code/data log with the regions. New regions
+    // start checked, changes to existing ones unchecked.
+    let import_cdl = {
+        let state = state.clone();
+        move || {
+            let state = state.clone();
+            leptos::task::spawn_local(async move {
+                let options = js_sys::Object::new();
+                let types = js_sys::Array::new();
+                let type_info = js_sys::Object::new();
+                let accept = js_sys::Object::new();
+                let extensions = js_sys::Array::new();
+                extensions.push(&JsValue::from_str(".cdl"));
+                js_sys::Reflect::set(&accept, &JsValue::from_str("application/octet-stream"), &extensions).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("description"), &JsValue::from_str("Code/Data Log")).unwrap();
+                js_sys::Reflect::set(&type_info, &JsValue::from_str("accept"), &accept).unwrap();
+                types.push(&type_info);
+                js_sys::Reflect::set(&options, &JsValue::from_str("types"), &types).unwrap();
+
+                let Ok(promise) = show_open_file_picker(&options) else { return };
+                let Ok(handles_val) = wasm_bindgen_futures::JsFuture::from(promise).await else { return };
+                let handles: js_sys::Array = handles_val.unchecked_into();
+                let handle: FileSystemFileHandle = handles.get(0).unchecked_into();
+                let Ok(file_val) = wasm_bindgen_futures::JsFuture::from(handle.get_file()).await else { return };
+                let file: File = file_val.unchecked_into();
+                let Ok(buffer_val) = wasm_bindgen_futures::JsFuture::from(file.array_buffer()).await else { return };
+                let buffer: ArrayBuffer = buffer_val.unchecked_into();
+                let data = Uint8Array::new(&buffer).to_vec();
+                let (Some(db), Some(rom)) = (state.db.get_untracked(), state.rom.get_untracked()) else { return };
+                match cdl::parse_cdl(&data, rom.prg_rom().len()) {
+                    Ok(flags) => {
+                        let diff = cdl::diff(&db, &rom, &flags);
+                        cdl_selected.set(diff.changes.iter().map(|c| c.replaces.is_none()).collect());
+                        cdl_diff.set(Some((file.name(), diff)));
+                        cdl_error.set(None);
+                    }
+                    Err(e) => {
+                        cdl_diff.set(None);
+                        cdl_error.set(Some(format!("{}: {}", file.name(), e)));
+                    }
+                }
+            });
+        }
+    };
+
+    let merge_cdl = {
+        let state = state.clone();
+        move || {
+            let (Some((_, diff)), Some(mut db)) = (cdl_diff.get_untracked(), state.db.get_untracked()) else { return };
+            let chosen = diff
+                .changes
+                .into_iter()
+                .zip(cdl_selected.get_untracked())
+                .filter(|(_, keep)| *keep)
+                .map(|(c, _)| c)
+                .collect::<Vec<_>>();
+            cdl::apply(&mut db, &chosen);
+            commit_edit(state.clone(), "Merge code/data log", db);
+            cdl_diff.set(None);
+            run_trace();
+        }
+    };
+
+    let clear_hits = {
+        let state = state.clone();
+        move || {
+            let Some(mut db) = state.db.get_untracked() else { return };
+            db.coverage.clear();
+            commit_edit(state.clone(), "Clear hit counts", db);
+        }
+    };
+    let has_hits = {
+        let state = state.clone();
+        move || state.db.with(|db| db.as_ref().is_some_and(|db| !db.coverage.is_empty()))
+    };
+
+    let add_entry = {
+        let state = state.clone();
+        move || {
+            let (bank, addr) = match parse_bank_address(&entry_input.get_untracked(), state.current_bank.get_untracked()) {
+                Ok(location) => location,
+                Err(e) => {
+                    entry_error.set(Some(e));
+                    return;
+                }
+            };
+            let (Some(mut db), Some(rom)) = (state.db.get_untracked(), state.rom.get_untracked()) else { return };
+            let num_banks = disasm::prg_bank_count(&db, &rom);
+            if bank >= num_banks {
+                entry_error.set(Some(format!("The ROM has only {} PRG banks", num_banks)));
+                return;
+            }
+            let info = db.bank.entry(bank).or_insert_with(|| crate::models::BankInfo {
+                title: None,
+                is_fixed: false,
+                mapped_at: None,
+                region: Vec::new(),
+                address: BTreeMap::new(),
+                entry_points: Vec::new(),
+            });
+            if !info.entry_points.contains(&addr) {
+                info.entry_points.push(addr);
+                info.entry_points.sort_unstable();
+            }
+            commit_edit(state.clone(), "Add entry point", db);
+            entry_error.set(None);
+            entry_input.set(String::new());
+            run_trace();
+        }
+    };
+
+    let dispatcher_input = RwSignal::new(String::new());
+    let dispatcher_width = RwSignal::new(2u8);
+    let dispatcher_split = RwSignal::new(false);
+    let dispatcher_count = RwSignal::new(String::new());
+    let dispatcher_offset = RwSignal::new(0i16);
+    let dispatcher_error = RwSignal::new(None::<String>);
+
+    let add_dispatcher = {
+        let state = state.clone();
+        move || {
+            let (bank, addr) = match parse_bank_address(&dispatcher_input.get_untracked(), state.current_bank.get_untracked()) {
+                Ok(location) => location,
+                Err(e) => {
+                    dispatcher_error.set(Some(e));
+                    return;
+                }
+            };
+            let count = dispatcher_count.get_untracked();
+            let count = match count.trim() {
+                "" => None,
+                text => match text.parse::<u16>() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => {
+                        dispatcher_error.set(Some(format!("Bad entry count \"{}\"", text)));
+                        return;
+                    }
+                },
+            };
+            let split = dispatcher_split.get_untracked();
+            if split && count.is_none() {
+                dispatcher_error.set(Some("A split table needs an entry count".to_string()));
+                return;
+            }
+            let info = JumpTableInfo { entry_width: dispatcher_width.get_untracked(), split, count, offset: dispatcher_offset.get_untracked() };
+            set_dispatcher(state.clone(), bank, addr, Some(info));
+            dispatcher_error.set(None);
+            dispatcher_input.set(String::new());
+            run_trace();
+        }
+    };
+
+    let dispatchers = {
+        let state = state.clone();
+        move || {
+            state.db.with(|db| {
+                db.as_ref().map_or_else(Vec::new, |db| {
+                    db.bank
+                        .iter()
+                        .flat_map(|(&id, bank)| bank.address.iter().filter_map(move |(&addr, a)| Some((id, addr, a.symbol.clone(), a.dispatcher.clone()?))))
+                        .collect::<Vec<_>>()
+                })
+            })
+        }
+    };
+
+    let accept = {
+        let state = state.clone();
+        move || {
+            let (Some(traced), Some(mut db)) = (result.get_untracked(), state.db.get_untracked()) else { return };
+            let chosen = traced
+                .proposals
+                .iter()
+                .zip(selected.get_untracked())
+                .filter(|(_, keep)| *keep)
+                .map(|(p, _)| p.clone())
+                .collect::<Vec<_>>();
+            trace::apply_proposals(&mut db, &chosen);
+            commit_edit(state.clone(), "Accept traced code", db);
+            run_trace();
+        }
+    };
+
+    let list_state = state.clone();
+    view! {
+        <div class="side-panel">
+            <div class="side-panel-title">
+                <strong>"Code Tracer"</strong>
+                <button type="button" on:click=move |e| { e.prevent_default(); show.set(false); }>"Close"</button>
+            </div>
+            <p>"Traces code flow from the NMI/RESET/IRQ vectors and each bank's entry points."</p>
+            <div>
+                <button type="button" title="Record hit counts from an FCEUX or Mesen trace log and propose Code regions for what it executed"
+                    on:click=move |e| { e.prevent_default(); import_log(); }>"Import Trace Log"</button>
+                {move || has_hits().then(|| {
+                    let clear_hits = clear_hits.clone();
+                    view! { <button type="button" on:click=move |e| { e.prevent_default(); clear_hits(); }>"Clear Hit Counts"</button> }
+                })}
+                {move || log_summary.get().map(|summary| view! {
+                    <p>{summary} " " <a href="#" on:click=move |e| { e.prevent_default(); run_trace(); }>"Back to traced code"</a></p>
+                })}
+            </div>
+            <div>
+                <button type="button" title="Compare an FCEUX or Mesen code/data log with the regions before merging it"
+                    on:click=move |e| { e.prevent_default(); import_cdl(); }>"Import CDL"</button>
+                {move || cdl_error.get().map(|e| view! { <p class="setup-error">{e}</p> })}
+                {
+                    let state = state.clone();
+                    move || cdl_diff.get().map(|(name, diff)| {
+                        let state = state.clone();
+                        let merge_cdl = merge_cdl.clone();
+                        let conflicts = diff.changes.iter().filter(|c| c.replaces.is_some()).count();
+                        view! {
+                            <p>{format!(
+                                "{}: {} new region(s), {} change(s) to existing regions; {} byte(s) agree, {} not logged.",
+                                name, diff.changes.len() - conflicts, conflicts, diff.agreeing, diff.unlogged
+                            )}</p>
+                            <ul class="panel-list">
+                                {diff.changes.into_iter().enumerate().map(|(i, change)| {
+                                    let state = state.clone();
+                                    let (bank, start) = (change.bank, *change.range.start());
+                                    view! {
+                                        <li>
+                                            <input type="checkbox"
+                                                prop:checked=move || cdl_selected.get().get(i).copied().unwrap_or(false)
+                                                on:change=move |ev| {
+                                                    let checked = event_target_checked(&ev);
+                                                    cdl_selected.update(|s| if let Some(v) = s.get_mut(i) { *v = checked });
+                                                }
+                                            />
+                                            <a href="#" class="symbol" on:click=move |e| { e.prevent_default(); navigate(state.clone(), Some(bank), start); }>
+                                                {change.label()}
+                                            </a>
+                                        </li>
+                                    }
+                                }).collect_view()}
+                            </ul>
+                            <button type="button" on:click=move |e| { e.prevent_default(); merge_cdl(); }>"Merge Selected"</button>
+                            <button type="button" on:click=move |e| { e.prevent_default(); cdl_diff.set(None); }>"Cancel"</button>
+                        }
+                    })
+                }
+            </div>
+            <div>
+                <input type="text" placeholder="Entry point (BB:AAAA)"
+                    prop:value=move || entry_input.get()
+                    on:input=move |ev| entry_input.set(event_target_value(&ev))
+                    on:keydown={
+                        let add_entry = add_entry.clone();
+                        move |ev: web_sys::KeyboardEvent| if ev.key() == "Enter" { add_entry(); }
+                    }
+                />
+                <button type="button" on:click=move |e| { e.prevent_default(); add_entry(); }>"Add"</button>
+                {move || entry_error.get().map(|e| view! { <p class="setup-error">{e}</p> })}
+            </div>
+            <details>
+                <summary>"Jump-table dispatchers"</summary>
+                <p>"Pointers after a JSR to a dispatcher are disassembled as a table and traced as code."</p>
+                <ul class="panel-list">
+                    {move || dispatchers().into_iter().map(|(bank, addr, symbol, info)| {
+                        let state = list_state.clone();
+                        let name = symbol.unwrap_or_else(|| format!("${:04X}", addr));
+                        let layout = match (info.split, info.count) {
+                            (true, Some(n)) => format!("split lo/hi, {} entries", n),
+                            (_, Some(n)) => format!("{}-byte entries, {} entries", info.entry_width, n),
+                            (_, None) => format!("{}-byte entries", info.entry_width),
+                        };
+                        let offset = if info.offset != 0 { format!(", target = pointer {:+}", info.offset) } else { String::new() };
+                        view! {
+                            <li>
+                                <a href="#" class="symbol" on:click={
+                                    let state = state.clone();
+                                    move |e| { e.prevent_default(); navigate(state.clone(), Some(bank), addr); }
+                                }>{format!("${:02X}: {}", bank, name)}</a>
+                                {format!(" ({}{}) ", layout, offset)}
+                                <button type="button" on:click=move |e| {
+                                    e.prevent_default();
+                                    set_dispatcher(state.clone(), bank, addr, None);
+                                    run_trace();
+                                }>"Remove"</button>
+                            </li>
+                        }
+                    }).collect_view()}
+                </ul>
+                <div>
+                    <input type="text" placeholder="Dispatcher (BB:AAAA)"
+                        prop:value=move || dispatcher_input.get()
+                        on:input=move |ev| dispatcher_input.set(event_target_value(&ev))
+                    />
+                    <label>" Entry bytes "
+                        <input type="number" min="2" max="8" style="width: 3em"
+                            prop:value=move || dispatcher_width.get().to_string()
+                            on:input=move |ev| dispatcher_width.set(event_target_value(&ev).parse().unwrap_or(2).clamp(2, 8))
+                        />
+                    </label>
+                    <label>" Split lo/hi "
+                        <input type="checkbox"
+                            prop:checked=move || dispatcher_split.get()
+                            on:change=move |ev| dispatcher_split.set(event_target_checked(&ev))
+                        />
+                    </label>
+                    <label>" Entries "
+                        <input type="text" placeholder="auto" style="width: 3em"
+                            prop:value=move || dispatcher_count.get()
+                            on:input=move |ev| dispatcher_count.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <label title="Added to each pointer; 1 for tables pushed and entered with RTS">" Offset "
+                        <input type="number" min="-8" max="8" style="width: 3em"
+                            prop:value=move || dispatcher_offset.get().to_string()
+                            on:input=move |ev| dispatcher_offset.set(event_target_value(&ev).parse().unwrap_or(0))
+                        />
+                    </label>
+                    <button type="button" on:click=move |e| { e.prevent_default(); add_dispatcher(); }>"Mark Dispatcher"</button>
+                    {move || dispatcher_error.get().map(|e| view! { <p class="setup-error">{e}</p> })}
+                </div>
+            </details>
+            {move || result.get().map(|traced| {
+                let state = state.clone();
+                let accept = accept.clone();
+                view! {
+                    <p>{format!("{} proposed Code region(s).", traced.proposals.len())}</p>
+                    <ul class="panel-list">
+                        {traced.proposals.into_iter().enumerate().map(|(i, p)| {
+                            let state = state.clone();
+                            let start = *p.range.start();
+                            view! {
+                                <li>
+                                    <input type="checkbox"
+                                        prop:checked=move || selected.get().get(i).copied().unwrap_or(false)
+                                        on:change=move |ev| {
+                                            let checked = event_target_checked(&ev);
+                                            selected.update(|s| if let Some(v) = s.get_mut(i) { *v = checked });
+                                        }
+                                    />
+                                    <a href="#" class="symbol" on:click=move |e| { e.prevent_default(); navigate(state.clone(), Some(p.bank), start); }>
+                                        {format!("${:02X}: ${:04X}-${:04X}", p.bank, p.range.start(), p.range.end())}
+                                    </a>
+                                </li>
+                            }
+                        }).collect_view()}
+                    </ul>
+                    <button type="button" on:click=move |e| { e.prevent_default(); accept(); }>"Accept Selected"</button>
+                    {(!traced.warnings.is_empty()).then(|| view! {
+                        <details>
+                            <summary>{format!("{} warning(s)", traced.warnings.len())}</summary>
+                            <ul class="panel-list">
+                                {traced.warnings.into_iter().map(|w| view! { <li>{w}</li> }).collect_view()}
+                            </ul>
+                        </details>
+                    })}
+                }
+            })}
+        </div>
+    }
+}
+
+#[component]
+fn RegionToolbar() -> impl IntoView {
+    let state = use_context::<AppState>().expect("state should be provided");
+    let struct_name = RwSignal::new(String::new());
+    let structs = move || state.db.with(|db| db.as_ref().map(|db| db.structs.keys().cloned().collect::<Vec<_>>()).unwrap_or_default());
+
+    let mark = {
+        let state = state.clone();
+        move |kind: &'static str| {
+            let Some((start, end)) = state.selection.get_untracked() else { return };
+            let range = start..=end;
+            let region = match kind {
+                "code" => Some(RegionInfo::Code(range.clone())),
+                "bytes" => Some(RegionInfo::Bytes(range.clone())),
+                "words" => Some(RegionInfo::Words(range.clone())),
+                "text" => Some(RegionInfo::Text(range.clone())),
+                "struct" => {
+                    let name = struct_name.get_untracked();
+                    if name.is_empty() {
+                        return;
+                    }
+                    Some(RegionInfo::Struct { range: range.clone(), name })
+                }
+                _ => None,
+            };
+            edit_region(state.clone(), range, region);
+        }
+    };
+
+    view! {
+        <div style="display: flex; gap: 8px; align-items: center; padding: 4px 0;">
+            {move || state.selection.get().map(|(start, end)| format!("Selected ${:04X}-${:04X}:", start, end))}
+            {
+                let mark = mark.clone();
+                view! { <button type="button" on:click=move |e| { e.prevent_default(); mark("code"); }>"Code"</button> }
+            }
+            {
+                let mark = mark.clone();
+                view! { <button type="button" on:click=move |e| { e.prevent_default(); mark("bytes"); }>"Bytes"</button> }
+            }
+            {
+                let mark = mark.clone();
+                view! { <button type="button" on:click=move |e| { e.prevent_default(); mark("words"); }>"Words"</button> }
+            }
+            {
+                let mark = mark.clone();
+                view! { <button type="button" on:click=move |e| { e.prevent_default(); mark("text"); }>"Text"</button> }
+            }
+            {
+                let mark = mark.clone();
+                move || {
+                    let names = structs();
+                    let mark = mark.clone();
+                    (!names.is_empty()).then(|| view! {
+                        <select on:change=move |ev| struct_name.set(event_target_value(&ev))>
+                            <option value="" selected=move || struct_name.get().is_empty()>"Struct..."</option>
+                            {names.into_iter().map(|name| view! { <option value=name.clone()>{name.clone()}</option> }).collect_view()}
+                        </select>
+                        <button type="button" on:click=move |e| { e.prevent_default(); mark("struct"); }>"Struct"</button>
+                    })
+                }
+            }
+            {
+                let mark = mark.clone();
+                view! { <button type="button" title="Remove regions here; the bytes are shown as .byt" on:click=move |e| { e.prevent_default(); mark("clear"); }>"Unmark"</button> }
+            }
+            <button type="button" on:click=move |e| { e.prevent_default(); state.selection.set(None); }>"Cancel"</button>
+        </div>
+    }
+}
+
+// Mark (or with `None`, unmark) an address range of the current bank.
+fn edit_region(state: AppState, range: RangeInclusive<u16>, region: Option<RegionInfo>) {
+    let bank_id = state.current_bank.get_untracked();
+    let (Some(mut db), Some(rom)) = (state.db.get_untracked(), state.rom.get_untracked()) else { return };
+    // Regions only exist in PRG banks, not in the global symbols view.
+    if bank_id >= disasm::prg_bank_count(&db, &rom) {
+        return;
+    }
+    let mapped_at = Some(disasm::bank_base_address(&db, bank_id));
+    let bank = db.bank.entry(bank_id).or_insert_with(|| crate::models::BankInfo {
+        title: None,
+        is_fixed: false,
+        mapped_at,
+        region: Vec::new(),
+        address: BTreeMap::new(),
+        entry_points: Vec::new(),
+    });
+    let location = format!("${:02X}:${:04X}-${:04X}", bank_id, range.start(), range.end());
+    let description = match &region {
+        Some(region) => format!("Mark {} as {}", location, region.label().split(' ').next().unwrap_or_default()),
+        None => format!("Unmark {}", location),
+    };
+    match region {
+        Some(region) => bank.mark_region(region),
+        None => bank.clear_region(range),
+    }
+    state.selection.set(None);
+    commit_edit(state, &description, db);
+}
+
+#[component]
+fn HistoryPanel(show: RwSignal<bool>) -> impl IntoView {
+    let state = use_context::<AppState>().expect("state should be provided");
+    let undo_state = state.clone();
+    let redo_state = state.clone();
+
+    view! {
+        <div class="side-panel">
+            <div class="side-panel-title">
+                <strong>"Edit History"</strong>
+                <button type="button" on:click=move |e| { e.prevent_default(); show.set(false); }>"Close"</button>
+            </div>
+            <div>
+                <button type="button" title="Ctrl+Z" disabled=move || state.history.with(|h| h.undo.is_empty())
+                    on:click=move |e| { e.prevent_default(); undo(undo_state.clone()); }>"Undo"</button>
+                <button type="button" title="Ctrl+Shift+Z" disabled=move || state.history.with(|h| h.redo.is_empty())
+                    on:click=move |e| { e.prevent_default(); redo(redo_state.clone()); }>"Redo"</button>
+                {move || {
+                    let count = state.history.with(|h| h.redo.len());
+                    (count > 0).then(|| format!(" {} undone edit(s) can be redone.", count))
+                }}
+            </div>
+            <ul class="panel-list">
+                {move || {
+                    let entries = state.history.with(|h| h.undo.clone());
+                    entries.into_iter().enumerate().rev().map(|(i, entry)| {
+                        let state = state.clone();
+                        view! {
+                            <li>
+                                <details>
+                                    <summary>{entry.description.clone()}</summary>
+                                    <ul class="panel-list">
+                                        {entry.changes.iter().flat_map(|change| {
+                                            let location = change.location();
+                                            change.details().into_iter().map(move |d| view! { <li>{format!("{} {}", location, d)}</li> })
+                                        }).collect_view()}
+                                    </ul>
+                                </details>
+                                <button type="button" title="Undo this edit only, keeping later ones" on:click=move |e| {
+                                    e.prevent_default();
+                                    let Some(mut db) = state.db.get_untracked() else { return };
+                                    if state.history.try_update(|h| h.revert(i, &mut db)).unwrap_or(false) {
+                                        state.db.set(Some(db));
+                                        state.dirty.set(true);
+                                    }
+                                }>"Revert"</button>
+                            </li>
+                        }
+                    }).collect_view()
+                }}
+            </ul>
+        </div>
+    }
+}
+
+#[component]
+fn ProblemsPanel(show: RwSignal<bool>) -> impl IntoView {
+    let state = use_context::<AppState>().expect("state should be provided");
+    let problems = Memo::new(move |_| match (state.db.get(), state.rom.get()) {
+        (Some(db), Some(rom)) => validate::validate(&db, &rom),
+        _ => Vec::new(),
+    });
+
+    view! {
+        <div class="side-panel">
+            <div class="side-panel-title">
+                <strong>"Problems"</strong>
+                <button type="button" on:click=move |e| { e.prevent_default(); show.set(false); }>"Close"</button>
+            </div>
+            <div>
+                "Symbol rules: "
+                <select on:change={
+                    let state = state.clone();
+                    move |ev| {
+                        let Some(mut db) = state.db.get_untracked() else { return };
+                        let Some(&assembler) = Assembler::ALL.iter().find(|a| a.label() == event_target_value(&ev)) else { return };
+                        db.assembler = assembler;
+                        commit_edit(state.clone(), "Change assembler", db);
+                    }
+                }>
+                    {Assembler::ALL.into_iter().map(|a| {
+                        let state = state.clone();
+                        view! { <option value=a.label() selected=move || state.db.with(|db| db.as_ref().is_some_and(|db| db.assembler == a))>{a.label()}</option> }
+                    }).collect_view()}
+                </select>
+            </div>
+            <p>{move || match problems.with(|p| p.len()) {
+                0 => "No problems found.".to_string(),
+                n => format!("{} problem(s).", n),
+            }}</p>
+            <ul class="panel-list">
+                {move || problems.get().into_iter().map(|p| {
+                    let state = state.clone();
+                    let location = p.location();
+                    view! {
+                        <li>
+                            <a href="#" class="symbol" on:click=move |e| { e.prevent_default(); navigate(state.clone(), p.bank, p.address); }>
+                                {location}
+                            </a>
+                            {format!(" {}: {}", p.kind.label(), p.message)}
+                        </li>
+                    }
+                }).collect_view()}
+            </ul>
+        </div>
+    }
+}
+
+// Run a routine in the emulator from a chosen address and RAM state, and list
+// the registers, memory writes and path it took.
+#[component]
+fn RunPanel(show: RwSignal<bool>) -> impl IntoView {
+    let state = use_context::<AppState>().expect("state should be provided");
+    // Start from the first selected line, if any.
+    let start_input = RwSignal::new(
+        state.selection.get_untracked().map(|(start, _)| format!("{:02X}:{:04X}", state.current_bank.get_untracked(), start)).unwrap_or_default(),
+    );
+    let state_input = RwSignal::new(String::new());
+    let banks_input = RwSignal::new(String::new());
+    let steps_input = RwSignal::new(emu::DEFAULT_MAX_STEPS.to_string());
+    let result = RwSignal::new(None::<Result<RunResult, String>>);
+
+    let run = {
+        let state = state.clone();
+        move || {
+            let (Some(db), Some(rom)) = (state.db.get_untracked(), state.rom.get_untracked()) else { return };
+            let setup = (|| {
+                let (bank, start) = parse_bank_address(&start_input.get_untracked(), state.current_bank.get_untracked())?;
+                let mut registers = emu::cpu::Registers::default();
+                let memory = emu::parse_state(&state_input.get_untracked(), &mut registers)?;
+                let banks = banks_input
+                    .get_untracked()
+                    .split_whitespace()
+                    .map(|b| u8::from_str_radix(b.trim_start_matches('$'), 16).map_err(|_| format!("Bad bank \"{}\"", b)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let max_steps = steps_input.get_untracked().trim().parse().map_err(|_| "Bad step limit".to_string())?;
+                Ok::<_, String>(RunSetup { bank, start, registers, memory, banks, max_steps })
+            })();
+            let run = setup.map(|setup| emu::run(&db, &rom, &setup));
+            state.executed.set(match &run {
+                Ok(run) => run.steps.iter().filter_map(|s| Some((s.bank?, s.registers.pc))).collect(),
+                Err(_) => HashSet::new(),
+            });
+            result.set(Some(run));
+        }
+    };
+    let close = {
+        let state = state.clone();
+        move || {
+            state.executed.set(HashSet::new());
+            show.set(false);
+        }
+    };
+    let location = |bank: Option<u8>, address: u16| match bank {
+        Some(id) => format!("${:02X}:${:04X}", id, address),
+        None => format!("${:04X}", address),
+    };
+    let registers = |r: &emu::cpu::Registers| format!("A={:02X} X={:02X} Y={:02X} S={:02X} P={}", r.a, r.x, r.y, r.s, r.flags());
+    // At most this many writes and path steps are listed.
+    const SHOWN: usize = 1000;
+
+    view! {
+        <div class="side-panel">
+            <div class="side-panel-title">
+                <strong>"Run Routine"</strong>
+                <button type="button" on:click=move |e| { e.prevent_default(); close(); }>"Close"</button>
+            </div>
+            <div>
+                <label>"Start " <input type="text" placeholder="BB:AAAA" prop:value=move || start_input.get() on:input=move |ev| start_input.set(event_target_value(&ev)) /></label>
+            </div>
+            <div>
+                <label>"State " <input type="text" placeholder="A=00 X=05 P=24 0300=FF,01" prop:value=move || state_input.get() on:input=move |ev| state_input.set(event_target_value(&ev)) /></label>
+            </div>
+            <div>
+                <label title="Banks to map in switchable windows the routine does not switch itself">"Also map banks " <input type="text" placeholder="05 06" prop:value=move || banks_input.get() on:input=move |ev| banks_input.set(event_target_value(&ev)) /></label>
+            </div>
+            <div>
+                <label>"Step limit " <input type="text" prop:value=move || steps_input.get() on:input=move |ev| steps_input.set(event_target_value(&ev)) /></label>
+                <button type="button" on:click=move |e| { e.prevent_default(); run(); }>"Run"</button>
+            </div>
+            {move || result.get().map(|run| match run {
+                Err(e) => view! { <p class="setup-error">{e}</p> }.into_any(),
+                Ok(run) => {
+                    let stop = format!(
+                        "{} at ${:04X} after {} instruction(s), {} cycle(s).",
+                        run.stop.label(), run.registers.pc, run.steps.len(), run.cycles
+                    );
+                    let global = state.db.with_untracked(|db| db.as_ref().map(|db| db.global.clone()).unwrap_or_default());
+                    let writes = run.writes.iter().take(SHOWN).map(|w| {
+                        let state = state.clone();
+                        let step = &run.steps[w.step];
+                        let (bank, pc) = (step.bank, step.registers.pc);
+                        let name = global.get(&w.address).and_then(|a| a.symbol.clone()).map(|s| format!(" ({})", s)).unwrap_or_default();
+                        view! {
+                            <li>
+                                {format!("${:04X}{} = ${:02X} at ", w.address, name, w.value)}
+                                <a href="#" class="symbol" on:click=move |e| { e.prevent_default(); navigate(state.clone(), bank, pc); }>{location(bank, pc)}</a>
+                            </li>
+                        }
+                    }).collect_view();
+                    let path = run.steps.iter().take(SHOWN).map(|step| {
+                        let state = state.clone();
+                        let (bank, pc) = (step.bank, step.registers.pc);
+                        view! {
+                            <li>
+                                <a href="#" class="symbol" on:click=move |e| { e.prevent_default(); navigate(state.clone(), bank, pc); }>{location(bank, pc)}</a>
+                                {format!(" {} cycle {}", registers(&step.registers), step.cycle)}
+                            </li>
+                        }
+                    }).collect_view();
+                    let more = |n: usize| (n > SHOWN).then(|| view! { <p>{format!("... and {} more", n - SHOWN)}</p> });
+                    view! {
+                        <p>{stop}</p>
+                        <p>{registers(&run.registers)}</p>
+                        <strong>{format!("Writes ({})", run.writes.len())}</strong>
+                        <ul class="panel-list">{writes}</ul>
+                        {more(run.writes.len())}
+                        <strong>{format!("Path ({})", run.steps.len())}</strong>
+                        <ul class="panel-list">{path}</ul>
+                        {more(run.steps.len())}
+                    }.into_any()
+                }
+            })}
+        </div>
+    }
+}
+
+#[component]
+fn XRefPanel() -> impl IntoView {
+    let state = use_context::<AppState>().expect("state should be provided");
+    let index = Memo::new(move |_| match (state.db.get(), state.rom.get()) {
+        (Some(db), Some(rom)) => XRefIndex::build(&db, &rom),
+        _ => XRefIndex::default(),
+    });
+
+    view! {
+        <div class="side-panel">
+            {move || state.xref_target.get().map(|(bank, addr)| {
+                let symbol = state.db.with(|db| {
+                    let db = db.as_ref()?;
+                    let info = if bank < 0 { db.global.get(&addr) } else { db.bank.get(&(bank as u8))?.address.get(&addr) };
+                    info?.symbol.clone()
+                });
+                let location = if bank < 0 { format!("${:04X}", addr) } else { format!("${:02X}:${:04X}", bank, addr) };
+                let title = match symbol {
+                    Some(symbol) => format!("{} ({})", symbol, location),
+                    None => location,
+                };
+                let refs = index.with(|index| {
+                    index.references_to((bank >= 0).then_some(bank as u8), addr).into_iter().cloned().collect::<Vec<_>>()
+                });
+                let state = state.clone();
+                view! {
+                    <div class="side-panel-title">
+                        <strong>{format!("References to {}", title)}</strong>
+                        <button type="button" on:click=move |e| { e.prevent_default(); state.xref_target.set(None); }>"Close"</button>
+                    </div>
+                    <p>{format!("{} reference(s).", refs.len())}</p>
+                    <ul class="panel-list">
+                        {refs.into_iter().map(|r| {
+                            let state = state.clone();
+                            view! {
+                                <li>
+                                    {format!("{} ", r.kind.label())}
+                                    <a href="#" class="symbol" on:click=move |e| { e.prevent_default(); navigate(state.clone(), Some(r.bank), r.from); }>
+                                        {format!("${:02X}:${:04X}", r.bank, r.from)}
+                                    </a>
+                                    {format!(" {}", r.mnemonic)}
+                                </li>
+                            }
+                        }).collect_view()}
+                    </ul>
+                }
+            })}
         </div>
     }
 }
@@ -720,9 +2042,11 @@ fn save_db_logic(state: AppState) {
                     let promise = handle.create_writable();
                     if let Ok(writable_val) = wasm_bindgen_futures::JsFuture::from(promise).await {
                         let writable: FileSystemWritableFileStream = writable_val.unchecked_into();
-                        let blob = Blob::new_with_str_sequence(&js_sys::Array::of1(&json.into())).unwrap();
+                        let blob = Blob::new_with_str_sequence(&js_sys::Array::of1(&json.as_str().into())).unwrap();
                         let _ = wasm_bindgen_futures::JsFuture::from(writable.write_with_blob(&blob).expect("write_with_blob failed")).await;
-                        let _ = wasm_bindgen_futures::JsFuture::from(writable.close()).await;
+                        if wasm_bindgen_futures::JsFuture::from(writable.close()).await.is_ok() {
+                            mark_saved(&state, &db, &json);
+                        }
                     }
                 } else {
                     // Save As
@@ -736,9 +2060,11 @@ fn save_db_logic(state: AppState) {
                              let writable_promise = handle.create_writable();
                              if let Ok(writable_val) = wasm_bindgen_futures::JsFuture::from(writable_promise).await {
                                 let writable: FileSystemWritableFileStream = writable_val.unchecked_into();
-                                let blob = Blob::new_with_str_sequence(&js_sys::Array::of1(&json.into())).unwrap();
+                                let blob = Blob::new_with_str_sequence(&js_sys::Array::of1(&json.as_str().into())).unwrap();
                                 let _ = wasm_bindgen_futures::JsFuture::from(writable.write_with_blob(&blob).expect("write_with_blob failed")).await;
-                                let _ = wasm_bindgen_futures::JsFuture::from(writable.close()).await;
+                                if wasm_bindgen_futures::JsFuture::from(writable.close()).await.is_ok() {
+                                    mark_saved(&state, &db, &json);
+                                }
                              }
                         }
                     }
@@ -748,6 +2074,42 @@ fn save_db_logic(state: AppState) {
     });
 }
 
+// Snapshot of unsaved edits, kept in LocalStorage under `<name>.autosave`.
+#[derive(Serialize, Deserialize, Clone)]
+struct Autosave {
+    // Milliseconds since the epoch.
+    saved_at: f64,
+    database: String,
+}
+
+// How often unsaved edits are snapshotted.
+const AUTOSAVE_INTERVAL_MS: u32 = 30_000;
+
+fn autosave_key(db: &DisassemblyInfo) -> String {
+    format!("{}.autosave", db.name)
+}
+
+fn autosave(state: &AppState) {
+    if !state.dirty.get_untracked() {
+        return;
+    }
+    let Some(db) = state.db.get_untracked() else { return };
+    if let Ok(database) = database::serialize_db(&db) {
+        let _ = LocalStorage::set(autosave_key(&db), Autosave { saved_at: js_sys::Date::now(), database });
+    }
+}
+
+// Clear the unsaved flag once a write finishes, unless the database was
+// edited while it was being written.
+fn mark_saved(state: &AppState, db: &DisassemblyInfo, json: &str) {
+    let current = state.db.get_untracked().and_then(|db| database::serialize_db(&db).ok());
+    if current.as_deref() != Some(json) {
+        return;
+    }
+    state.dirty.set(false);
+    LocalStorage::delete(autosave_key(db));
+}
+
 #[component]
 fn VirtualizedDisasm() -> impl IntoView {
     let state = use_context::<AppState>().expect("state should be provided");
@@ -1010,7 +2372,8 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
         move |_| {
             let results = state.search_results.get();
             let idx = state.search_current_idx.get();
-            results.get(idx) == Some(&line.get().address)
+            let line = line.get();
+            results.get(idx) == Some(&(line.bank, line.address))
         }
     });
 
@@ -1019,7 +2382,11 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
         move |ev: web_sys::FocusEvent| {
             let line = line.get_untracked();
             let val = event_target_inner_text(&ev);
-            update_annotation(state.clone(), line.address, line.bank, "symbol", val);
+            if update_annotation(state.clone(), line.address, line.bank, "symbol", val).is_err() {
+                let target = ev.target().unwrap().unchecked_into::<web_sys::HtmlElement>();
+                let symbol = line.symbol.clone().unwrap_or_else(|| "???".to_string());
+                target.set_inner_text(&if line.bank != -1 { label_text(&symbol) } else { symbol });
+            }
         }
     };
 
@@ -1028,7 +2395,7 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
         move |ev: web_sys::FocusEvent| {
             let line = line.get_untracked();
             let val = event_target_inner_text(&ev);
-            update_annotation(state.clone(), line.address, line.bank, "comment", val);
+            let _ = update_annotation(state.clone(), line.address, line.bank, "comment", val);
         }
     };
 
@@ -1047,7 +2414,7 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
                 let is_symbol = target.class_list().contains("symbol");
                 if is_symbol {
                     if line.bank != -1 {
-                        target.set_inner_text(&line.symbol.as_ref().map(|s| format!("{}:", s)).unwrap_or_else(|| "???".to_string()));
+                        target.set_inner_text(&line.symbol.as_deref().map(label_text).unwrap_or_else(|| "???".to_string()));
                     } else {
                         target.set_inner_text(&line.symbol.clone().unwrap_or_else(|| "???".to_string()));
                     }
@@ -1065,7 +2432,7 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
             let line = line.get_untracked();
             state.editing_block_comment.set(None);
             let val = event_target_inner_text(&ev);
-            update_annotation(state.clone(), line.address, line.bank, "block_comment", val);
+            let _ = update_annotation(state.clone(), line.address, line.bank, "block_comment", val);
         }
     };
 
@@ -1123,7 +2490,10 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
             if let Some(target_addr) = line.target_address {
                 let target_bank = line.target_bank.map(|b| b as i16).unwrap_or(-1);
                 let val = event_target_inner_text(&ev);
-                update_annotation(state.clone(), target_addr, target_bank, "symbol", val);
+                if update_annotation(state.clone(), target_addr, target_bank, "symbol", val).is_err() {
+                    let target = ev.target().unwrap().unchecked_into::<web_sys::HtmlElement>();
+                    target.set_inner_text(&line.operand_main);
+                }
             }
         }
     };
@@ -1240,14 +2610,23 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
     let on_operand_keydown_c = on_operand_keydown.clone();
 
     view! {
-        <div class="grid-row" style=move || format!("position: absolute; top: {}px; width: 100%; display: grid; grid-template-columns: var(--col-addr) var(--col-hex) var(--col-op) var(--col-operand) 1fr;", top.get())>
+        <div class="grid-row"
+            class:region-selected=move || {
+                let line = line.get();
+                line.bank >= 0 && state.selection.get().is_some_and(|(start, end)| (start..=end).contains(&line.address))
+            }
+            class:executed=move || {
+                let line = line.get();
+                line.bank >= 0 && state.executed.with(|e| e.contains(&(line.bank as u8, line.address)))
+            }
+            style=move || format!("position: absolute; top: {}px; width: 100%; display: grid; grid-template-columns: var(--col-addr) var(--col-hits) var(--col-hex) var(--col-op) var(--col-operand) 1fr;", top.get())>
             {move || {
                 let line = line.get();
                 let is_editing = state.editing_block_comment.get() == Some((line.address, line.bank));
                 let on_block_blur = on_block_blur_c.clone();
                 let on_block_keydown = on_block_keydown_c.clone();
                 let bc_ref = bc_ref_c.clone();
-                let query = state.search_query.get();
+                let query = state.search_highlight.get();
                 let is_match = !query.is_empty() && line.block_comment.as_ref().map_or(false, |bc| bc.to_lowercase().contains(&query.to_lowercase()));
                 let active = is_active_line.get();
 
@@ -1279,7 +2658,7 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
                 let on_comment_blur = on_comment_blur_c.clone();
                 let on_keydown = on_keydown_c.clone();
                 let on_click_trigger = on_click_trigger_c.clone();
-                let query = state.search_query.get();
+                let query = state.search_highlight.get();
                 let active = is_active_line.get();
                 
                 if line.bank != -1 {
@@ -1290,9 +2669,11 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
                     let on_operand_blur = on_operand_blur_c.clone();
                     let on_operand_keydown = on_operand_keydown_c.clone();
                     let is_editing_op = state.editing_operand.get() == Some((line.address, line.bank));
+                    let line_len = line.bytes.split_whitespace().count().max(1) as u16;
                     
                     let sym_match = !query.is_empty() && line.symbol.as_ref().map_or(false, |s| s.to_lowercase().contains(&query.to_lowercase()));
-                    let op_match = !query.is_empty() && line.operand_main.to_lowercase().contains(&query.to_lowercase());
+                    let op_match = !query.is_empty() && (line.operand_main.to_lowercase().contains(&query.to_lowercase())
+                        || line.text.as_ref().is_some_and(|t| t.to_lowercase().contains(&query.to_lowercase())));
                     let comm_match = !query.is_empty() && line.comment.as_ref().map_or(false, |c| c.to_lowercase().contains(&query.to_lowercase()));
 
                     view! {
@@ -1304,15 +2685,45 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
                                     <div class="symbol editable-container" contenteditable="true" 
                                         on:blur=on_symbol_blur on:keydown=on_keydown.clone()
                                     >
-                                        <Highlight text={format!("{}:", sym_c)} query=query_c active=active />
+                                        <Highlight text={label_text(&sym_c)} query=query_c active=active />
                                     </div>
                                 </div>
                             }.into_any()
                         } else { view! {}.into_any() }}
-                        <div class="grid-cell address" on:click=on_click_trigger.clone()>{line.address_label}</div>
-                        <div class="grid-cell hex" on:click=on_click_trigger.clone()>{line.bytes}</div>
+                        <div class="grid-cell address" on:click={
+                            let (bank, addr) = (line.bank, line.address);
+                            move |ev: web_sys::MouseEvent| if ev.shift_key() { on_click_trigger(ev) } else { state.xref_target.set(Some((bank, addr))) }
+                        }>{line.address_label}</div>
+                        {
+                            let hits = state.db.with(|db| db.as_ref().and_then(|db| db.coverage.get(&(line.bank as u8))?.get(&line.address).copied()));
+                            view! {
+                                <div class="grid-cell hits" title=hits.map(|n| format!("Ran {} time(s)", n))>
+                                    {hits.map(|n| view! {
+                                        <span class="heat" style=format!("opacity: {:.2}", ((n as f64 + 1.0).log10() / 6.0).min(1.0))></span>
+                                        <span class="hits-count">{hit_count(n)}</span>
+                                    })}
+                                </div>
+                            }
+                        }
+                        <div class="grid-cell hex" title="Click to select, Ctrl+Click to extend the selection" on:click={
+                            let (addr, len, bank) = (line.address, line_len, line.bank);
+                            move |ev: web_sys::MouseEvent| {
+                                if ev.shift_key() {
+                                    return on_click_trigger(ev);
+                                }
+                                // Global symbol lines have no bytes to mark.
+                                if bank < 0 {
+                                    return;
+                                }
+                                let end = addr.saturating_add(len - 1);
+                                state.selection.update(|sel| *sel = match *sel {
+                                    Some((start, last)) if ev.ctrl_key() || ev.meta_key() => Some((start.min(addr), last.max(end))),
+                                    _ => Some((addr, end)),
+                                });
+                            }
+                        }>{line.bytes}</div>
                         <div class="grid-cell opcode">{line.opcode}</div>
-                        <div class="grid-cell operand" class:search-match-cell=op_match on:click=on_operand_click>
+                        <div class="grid-cell operand" class:search-match-cell=op_match title=line.text.clone() on:click=on_operand_click>
                             <span>{line.operand_prefix}</span>
                             {if is_editing_op {
                                 view! {
@@ -1361,13 +2772,16 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
                     let comm_match = !query.is_empty() && comm_val.to_lowercase().contains(&query.to_lowercase());
 
                     view! {
-                        <div class="grid-cell address" class:search-match-cell=sym_match style="grid-column: 1 / span 4; display: flex; align-items: baseline;" on:click=on_click_trigger.clone()>
+                        <div class="grid-cell address" class:search-match-cell=sym_match style="grid-column: 1 / span 5; display: flex; align-items: baseline;" on:click=on_click_trigger>
                             <div class="symbol editable-container" contenteditable="true" 
                                 on:blur=on_symbol_blur on:keydown=on_keydown.clone()
                             >
                                 <Highlight text=sym_val query=query.clone() active=active />
                             </div>
-                            <span style="margin-left: 8px;">" = " {line.address_label.clone()}</span>
+                            <span style="margin-left: 8px;" on:click={
+                                let (bank, addr) = (line.bank, line.address);
+                                move |ev: web_sys::MouseEvent| if !ev.shift_key() { state.xref_target.set(Some((bank, addr))) }
+                            }>" = " {line.address_label.clone()}</span>
                         </div>
                         <div class="grid-cell comment-cell" class:search-match-cell=comm_match on:click=on_click_trigger.clone()>
                             <div class="comment editable-container" contenteditable="true" 
@@ -1383,12 +2797,31 @@ fn DisasmRow(#[prop(into)] line: Signal<DisassemblyLine>, #[prop(into)] top: Sig
     }
 }
 
-fn update_annotation(state: AppState, address: u16, bank_id: i16, field: &str, value: String) {
+// Store an edited field. Symbol names the project's assembler rejects, or
+// that are already defined where this one is visible, are refused.
+fn update_annotation(state: AppState, address: u16, bank_id: i16, field: &str, value: String) -> Result<(), String> {
     let mut db = state.db.get_untracked().unwrap();
     let bank_id_opt = if bank_id == -1 { None } else { Some(bank_id as u8) };
 
     let processed = strip_decorations(field, &value);
-    
+    // "???" is the placeholder shown for a line without a symbol.
+    if field == "symbol" && processed == "???" {
+        return Ok(());
+    }
+    if field == "symbol" && !processed.is_empty() {
+        let checked = validate::check_symbol(db.assembler, &processed).and_then(|()| {
+            match validate::symbol_clash(&db, bank_id_opt, address, &processed) {
+                Some(other) => Err(format!("\"{}\" is already defined at {}", processed, other)),
+                None => Ok(()),
+            }
+        });
+        if let Err(e) = checked {
+            state.edit_error.set(Some(e.clone()));
+            return Err(e);
+        }
+    }
+    state.edit_error.set(None);
+
     if let Some(id) = bank_id_opt {
         let bank = db.bank.entry(id).or_insert_with(|| crate::models::BankInfo {
             title: None,
@@ -1396,6 +2829,7 @@ fn update_annotation(state: AppState, address: u16, bank_id: i16, field: &str, v
             mapped_at: Some(0x8000),
             region: Vec::new(),
             address: std::collections::BTreeMap::new(),
+            entry_points: Vec::new(),
         });
         let section = bank.address.entry(address).or_default();
         match field {
@@ -1420,13 +2854,110 @@ fn update_annotation(state: AppState, address: u16, bank_id: i16, field: &str, v
         }
     }
 
+    let location = match bank_id_opt {
+        Some(id) => format!("${:02X}:${:04X}", id, address),
+        None => format!("${:04X}", address),
+    };
+    commit_edit(state, &format!("Edit {} at {}", field.replace('_', " "), location), db);
+    Ok(())
+}
+
+// Mark (or with `None`, unmark) the routine at a bank address as a jump-table dispatcher.
+fn set_dispatcher(state: AppState, bank_id: u8, address: u16, info: Option<JumpTableInfo>) {
+    let Some(mut db) = state.db.get_untracked() else { return };
+    let Some(bank) = db.bank.get_mut(&bank_id) else { return };
+    let section = bank.address.entry(address).or_default();
+    let description = format!("{} dispatcher at ${:02X}:${:04X}", if info.is_some() { "Mark" } else { "Remove" }, bank_id, address);
+    section.dispatcher = info;
+    if section.is_empty() {
+        bank.address.remove(&address);
+    }
+    commit_edit(state, &description, db);
+}
+
+// Start working on a ROM, first filling in the project fields its header
+// supplies. That edits the project, so it is marked unsaved and the user is
+// told what changed.
+fn use_rom(state: &AppState, rom: RomImage, record: bool) {
+    let mut note = None;
+    state.db.update(|db| {
+        if let Some(db) = db {
+            note = rom.fill_unset(db);
+            if record {
+                rom.record_identity(db);
+            }
+        }
+    });
+    if let Some(note) = note {
+        state.dirty.set(true);
+        state.notice.set(Some(format!("Opening the ROM {}. Save to keep this.", note)));
+    }
+    state.rom.set(Some(rom));
+}
+
+// Replace the database with a loaded one. Its edits start a fresh history,
+// since undoing into the previous database would mix the two.
+fn load_db(state: &AppState, db: DisassemblyInfo) {
+    state.history.set(History::default());
+    state.db.set(Some(db));
+}
+
+// Replace the database with an edited copy, recording the edit for undo.
+fn commit_edit(state: AppState, description: &str, db: DisassemblyInfo) {
+    if let Some(before) = state.db.get_untracked() {
+        state.history.update(|h| h.record(description, &before, &db));
+    }
     state.db.set(Some(db));
+    state.dirty.set(true);
+}
+
+fn undo(state: AppState) {
+    let Some(mut db) = state.db.get_untracked() else { return };
+    if state.history.try_update(|h| h.undo(&mut db)).unwrap_or(false) {
+        state.db.set(Some(db));
+        state.dirty.set(true);
+    }
+}
+
+fn redo(state: AppState) {
+    let Some(mut db) = state.db.get_untracked() else { return };
+    if state.history.try_update(|h| h.redo(&mut db)).unwrap_or(false) {
+        state.db.set(Some(db));
+        state.dirty.set(true);
+    }
+}
+
+// `BB:AAAA`, or `AAAA` in the current bank.
+fn parse_bank_address(text: &str, current_bank: u8) -> Result<(u8, u16), String> {
+    let text = text.trim().replace('$', "");
+    let (bank, addr) = match text.split_once(':') {
+        Some((bank, addr)) => (u8::from_str_radix(bank, 16).ok(), u16::from_str_radix(addr, 16).ok()),
+        None => (Some(current_bank), u16::from_str_radix(&text, 16).ok()),
+    };
+    match (bank, addr) {
+        (Some(bank), Some(addr)) if bank != 255 => Ok((bank, addr)),
+        _ => Err(format!("Expected BB:AAAA, got \"{}\"", text)),
+    }
+}
+
+// A hit count in a few characters: 950, 12.3k, 4.5M.
+fn hit_count(n: u64) -> String {
+    match n {
+        0..1000 => n.to_string(),
+        1000..1_000_000 => format!("{:.1}k", n as f64 / 1e3),
+        _ => format!("{:.1}M", n as f64 / 1e6),
+    }
+}
+
+// A symbol as written before a line; an unnamed label is a bare `:`.
+fn label_text(symbol: &str) -> String {
+    if symbol == ":" { symbol.to_string() } else { format!("{}:", symbol) }
 }
 
 fn strip_decorations(field: &str, text: &str) -> String {
     let text = text.trim();
     if field == "symbol" {
-        if text.ends_with(':') { return text[..text.len()-1].to_string(); }
+        if text.ends_with(':') && text != ":" { return text[..text.len()-1].to_string(); }
         return text.to_string();
     }
     if field == "comment" || field == "block_comment" {
@@ -1443,6 +2974,41 @@ fn strip_decorations(field: &str, text: &str) -> String {
     text.to_string()
 }
 
+fn global_lines(db: &DisassemblyInfo) -> Vec<DisassemblyLine> {
+    db.global
+        .iter()
+        .map(|(addr, anno)| DisassemblyLine {
+            address: *addr,
+            address_label: format!("${:04X}", addr),
+            bank: -1,
+            bytes: String::new(),
+            opcode: String::new(),
+            operand_prefix: String::new(),
+            operand_main: String::new(),
+            operand_suffix: String::new(),
+            operand_is_symbol: false,
+            symbol: anno.symbol.clone(),
+            comment: anno.comment.clone(),
+            block_comment: anno.block_comment.clone(),
+            target_bank: None,
+            target_address: None,
+            text: None,
+        })
+        .collect()
+}
+
+// Search hits per bank, in result order.
+fn hits_by_bank(results: &[(i16, u16)]) -> Vec<(i16, usize, usize)> {
+    let mut groups: Vec<(i16, usize, usize)> = Vec::new();
+    for (i, &(bank, _)) in results.iter().enumerate() {
+        match groups.last_mut() {
+            Some((last, _, count)) if *last == bank => *count += 1,
+            _ => groups.push((bank, i, 1)),
+        }
+    }
+    groups
+}
+
 fn navigate(state: AppState, target_bank: Option<u8>, target_address: u16) {
     let bank_id = target_bank.unwrap_or(255);
     
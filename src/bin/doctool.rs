@@ -0,0 +1,556 @@
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+use docassembler::database;
+use docassembler::disasm::{self, text, trace, validate, verify};
+use docassembler::emu::{self, RunSetup};
+use docassembler::export;
+use docassembler::import::{self, cdl, tracelog, ConflictPolicy, FieldChange};
+use docassembler::models::DisassemblyInfo;
+use docassembler::rom::{IdentityCheck, RomImage};
+
+const USAGE: &str = "\
+usage: doctool <command> [args]
+
+commands:
+  export <db.json> <rom.nes> <outdir>   Write a ca65 source tree and ld65 config
+  labels <db.json> <rom.nes> <outdir>   Write Mesen .mlb and FCEUX .nl debugger labels
+  verify <db.json> <rom.nes>            Re-assemble the disassembly and diff it against the ROM
+  import <db.json> <source.s>...        Merge labels and comments from da65/ca65 sources
+         [--overwrite] [--skip-borders] [--skip-register-notes]
+  import-labels <db.json> <rom.nes> <file>... Preview merging Mesen .mlb or FCEUX .nl labels
+         [--apply] [--overwrite]
+  bind <db.json> <rom.nes>              Record the ROM's CRC32/SHA-1 in the database
+  trace <db.json> <rom.nes>             Trace code from the vectors and propose Code regions
+         [--entry BB:AAAA]... [--apply]
+  tracelog <db.json> <rom.nes> <log>... Record hit counts from FCEUX/Mesen trace logs and propose Code regions
+         [--apply]
+  cdl <db.json> <rom.nes> <file.cdl>    Compare an FCEUX/Mesen code/data log with the regions
+         [--apply] [--replace]
+  tbl <db.json> <table.tbl>             Store a character table used to render Text regions
+  validate <db.json> <rom.nes>          Report overlapping regions, misplaced symbols and duplicate names
+  run <db.json> <rom.nes> <BB:AAAA>     Run a routine in the 6502 emulator and list its memory writes
+         [A=XX X=XX Y=XX P=XX S=XX AAAA=XX,XX...] [--bank BB]... [--steps N] [--path]";
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        Some("export") => cmd_export(&args[1..]),
+        Some("verify") => cmd_verify(&args[1..]),
+        Some("labels") => cmd_labels(&args[1..]),
+        Some("import") => cmd_import(&args[1..]),
+        Some("import-labels") => cmd_import_labels(&args[1..]),
+        Some("bind") => cmd_bind(&args[1..]),
+        Some("trace") => cmd_trace(&args[1..]),
+        Some("tracelog") => cmd_tracelog(&args[1..]),
+        Some("cdl") => cmd_cdl(&args[1..]),
+        Some("tbl") => cmd_tbl(&args[1..]),
+        Some("validate") => cmd_validate(&args[1..]),
+        Some("run") => cmd_run(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn cmd_export(args: &[String]) -> Result<(), String> {
+    let [db_path, rom_path, out_dir] = args else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+    for p in validate::symbol_problems(&db) {
+        eprintln!("warning: {}: {}", p.location(), p.message);
+    }
+
+    let result = export::ca65::export_project(&db, &rom)?;
+    for warning in &result.warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    let out_dir = Path::new(out_dir);
+    fs::create_dir_all(out_dir).map_err(|e| format!("{}: {}", out_dir.display(), e))?;
+    for file in &result.files {
+        let path = out_dir.join(&file.name);
+        fs::write(&path, &file.data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    println!("Wrote {} files to {}", result.files.len(), out_dir.display());
+    Ok(())
+}
+
+fn cmd_labels(args: &[String]) -> Result<(), String> {
+    let [db_path, rom_path, out_dir] = args else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+
+    // Named after the ROM so the emulators load them alongside it.
+    let rom_file = Path::new(rom_path);
+    let stem = rom_file.file_stem().map_or(db.name.clone(), |s| s.to_string_lossy().into_owned());
+    let file_name = rom_file.file_name().map_or(format!("{}.nes", db.name), |s| s.to_string_lossy().into_owned());
+    let mut files = Vec::new();
+    let mut warnings = Vec::new();
+    for result in [export::symbols::mesen_labels(&db, &rom, &stem), export::symbols::fceux_name_lists(&db, &rom, &file_name)] {
+        for warning in result.warnings {
+            if !warnings.contains(&warning) {
+                eprintln!("warning: {}", warning);
+                warnings.push(warning);
+            }
+        }
+        files.extend(result.files);
+    }
+
+    let out_dir = Path::new(out_dir);
+    fs::create_dir_all(out_dir).map_err(|e| format!("{}: {}", out_dir.display(), e))?;
+    for file in &files {
+        let path = out_dir.join(&file.name);
+        fs::write(&path, &file.data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    println!("Wrote {} files to {}", files.len(), out_dir.display());
+    Ok(())
+}
+
+fn cmd_verify(args: &[String]) -> Result<(), String> {
+    let [db_path, rom_path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+
+    let divergences = verify::verify_project(&db, &rom);
+    for d in &divergences {
+        let mut line = format!("${:02X}:${:04X}: {}", d.bank, d.address, d.message);
+        if !d.expected.is_empty() || !d.actual.is_empty() {
+            line.push_str(&format!(" (rom: {}, assembled: {})", hex_bytes(&d.expected), hex_bytes(&d.actual)));
+        }
+        println!("{}", line);
+    }
+    if divergences.is_empty() {
+        println!("OK: disassembly re-assembles to the original ROM");
+        Ok(())
+    } else {
+        Err(format!("{} divergence(s) found", divergences.len()))
+    }
+}
+
+fn cmd_validate(args: &[String]) -> Result<(), String> {
+    let [db_path, rom_path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+
+    let problems = validate::validate(&db, &rom);
+    for p in &problems {
+        println!("{}: {}: {}", p.location(), p.kind.label(), p.message);
+    }
+    if problems.is_empty() {
+        println!("OK: no problems found");
+        Ok(())
+    } else {
+        Err(format!("{} problem(s) found", problems.len()))
+    }
+}
+
+fn cmd_run(args: &[String]) -> Result<(), String> {
+    let mut banks = Vec::new();
+    let mut max_steps = emu::DEFAULT_MAX_STEPS;
+    let mut show_path = false;
+    let mut state = Vec::new();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" => show_path = true,
+            "--bank" => {
+                let spec = args.next().ok_or_else(|| USAGE.to_string())?;
+                banks.push(u8::from_str_radix(spec.trim_start_matches('$'), 16).map_err(|e| format!("{}: {}", spec, e))?);
+            }
+            "--steps" => {
+                let spec = args.next().ok_or_else(|| USAGE.to_string())?;
+                max_steps = spec.parse().map_err(|e| format!("{}: {}", spec, e))?;
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+            entry if entry.contains('=') => state.push(entry),
+            path => paths.push(path),
+        }
+    }
+    let [db_path, rom_path, start] = paths.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+
+    let (bank, start) = parse_bank_address(start)?;
+    let mut registers = Default::default();
+    let memory = emu::parse_state(&state.join(" "), &mut registers)?;
+    let setup = RunSetup { bank, start, registers, memory, banks, max_steps };
+    let result = emu::run(&db, &rom, &setup);
+
+    let location = |bank: Option<u8>, address: u16| match bank {
+        Some(id) => format!("${:02X}:${:04X}", id, address),
+        None => format!("${:04X}", address),
+    };
+    let registers = |r: &emu::cpu::Registers| format!("A={:02X} X={:02X} Y={:02X} S={:02X} P={}", r.a, r.x, r.y, r.s, r.flags());
+    if show_path {
+        for step in &result.steps {
+            println!("{}  {}  cycle {}", location(step.bank, step.registers.pc), registers(&step.registers), step.cycle);
+        }
+    }
+    for write in &result.writes {
+        let step = &result.steps[write.step];
+        let name = db.global.get(&write.address).and_then(|a| a.symbol.as_deref()).map(|s| format!(" ({})", s)).unwrap_or_default();
+        println!("${:04X}{} = ${:02X}  at {}", write.address, name, write.value, location(step.bank, step.registers.pc));
+    }
+    println!("{}", registers(&result.registers));
+    println!(
+        "{} at ${:04X} after {} instruction(s), {} cycle(s)",
+        result.stop.label(),
+        result.registers.pc,
+        result.steps.len(),
+        result.cycles
+    );
+    Ok(())
+}
+
+fn cmd_import(args: &[String]) -> Result<(), String> {
+    let mut options = import::ca65::ImportOptions::default();
+    let mut policy = ConflictPolicy::KeepExisting;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--overwrite" => policy = ConflictPolicy::Overwrite,
+            "--skip-borders" => options.skip_borders = true,
+            "--skip-register-notes" => options.skip_register_notes = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+            path => paths.push(path),
+        }
+    }
+    let [db_path, sources @ ..] = paths.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    if sources.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut db = load_db(db_path)?;
+    let texts = sources
+        .iter()
+        .map(|path| fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let listing = import::ca65::import_sources(&db, &texts, &options);
+    let report = import::merge(&mut db, &listing, policy);
+
+    for change in &report.conflicts {
+        let resolution = if policy == ConflictPolicy::Overwrite { "replaced" } else { "kept existing" };
+        println!("conflict {}: {:?} vs {:?} ({})", describe_change(change), change.existing.as_deref().unwrap_or_default(), change.incoming, resolution);
+    }
+    for bank_id in &report.new_banks {
+        println!("created bank ${:02X}", bank_id);
+    }
+    println!(
+        "{} field(s) added, {} unchanged, {} conflict(s)",
+        report.added.len(),
+        report.unchanged,
+        report.conflicts.len()
+    );
+    save_db(db_path, &db)
+}
+
+fn cmd_import_labels(args: &[String]) -> Result<(), String> {
+    let mut apply = false;
+    let mut policy = ConflictPolicy::KeepExisting;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--apply" => apply = true,
+            "--overwrite" => policy = ConflictPolicy::Overwrite,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+            path => paths.push(path),
+        }
+    }
+    let [db_path, rom_path, files @ ..] = paths.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    if files.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+    let files = files
+        .iter()
+        .map(|path| fs::read_to_string(path).map(|text| (path.to_string(), text)).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut warnings = Vec::new();
+    let listing = import::labels::read_files(&db, &files, &mut warnings);
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    let preview = import::preview(&db, &listing);
+    for change in &preview.report.added {
+        println!("new {}: {:?}", describe_change(change), change.incoming);
+    }
+    for change in &preview.report.conflicts {
+        println!("changed {}: {:?} -> {:?}", describe_change(change), change.existing.as_deref().unwrap_or_default(), change.incoming);
+    }
+    for (change, other) in &preview.duplicates {
+        println!("conflict {}: {} is also defined at {}", describe_change(change), change.incoming, other);
+    }
+    println!(
+        "{} new, {} changed, {} unchanged field(s); {} duplicate symbol(s)",
+        preview.report.added.len(),
+        preview.report.conflicts.len(),
+        preview.report.unchanged,
+        preview.duplicates.len()
+    );
+    if apply {
+        import::merge(&mut db, &listing, policy);
+        save_db(db_path, &db)?;
+        let resolution = if policy == ConflictPolicy::Overwrite { "replaced" } else { "kept existing" };
+        println!("Merged into {} (changed fields {})", db_path, resolution);
+    }
+    Ok(())
+}
+
+fn cmd_bind(args: &[String]) -> Result<(), String> {
+    let [db_path, rom_path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    rom.validate(&db).map_err(|e| format!("{}: {}", rom_path, e))?;
+    if let Some(note) = rom.fill_unset(&mut db) {
+        println!("{}", note);
+    }
+
+    if let IdentityCheck::Mismatch(message) = rom.check_identity(&db) {
+        println!("replacing previous binding ({})", message);
+    }
+    rom.record_identity(&mut db);
+    let hashes = rom.hashes();
+    println!("CRC32 {}\nSHA-1 {}", hashes.crc32, hashes.sha1);
+    save_db(db_path, &db)
+}
+
+fn cmd_trace(args: &[String]) -> Result<(), String> {
+    let mut apply = false;
+    let mut extra_entries = Vec::new();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--apply" => apply = true,
+            "--entry" => {
+                let spec = args.next().ok_or_else(|| USAGE.to_string())?;
+                extra_entries.push(parse_bank_address(spec)?);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+            path => paths.push(path),
+        }
+    }
+    let [db_path, rom_path] = paths.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+
+    let num_banks = disasm::prg_bank_count(&db, &rom);
+    if let Some(&(bank, addr)) = extra_entries.iter().find(|&&(bank, _)| bank >= num_banks) {
+        return Err(format!("entry ${:02X}:${:04X}: the ROM has only {} PRG banks", bank, addr, num_banks));
+    }
+    let mut entries = trace::entry_points(&db, &rom);
+    entries.extend(extra_entries);
+    let result = trace::trace_code(&db, &rom, &entries);
+    for warning in &result.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    for p in &result.proposals {
+        println!("${:02X}: Code ${:04X}-${:04X} ({} bytes)", p.bank, p.range.start(), p.range.end(), p.range.len());
+    }
+    println!("{} entry point(s), {} proposed region(s)", entries.len(), result.proposals.len());
+    if apply && !result.proposals.is_empty() {
+        trace::apply_proposals(&mut db, &result.proposals);
+        save_db(db_path, &db)?;
+        println!("Added {} Code region(s) to {}", result.proposals.len(), db_path);
+    }
+    Ok(())
+}
+
+fn cmd_tracelog(args: &[String]) -> Result<(), String> {
+    let mut apply = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--apply" => apply = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+            path => paths.push(path),
+        }
+    }
+    let [db_path, rom_path, logs @ ..] = paths.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    if logs.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+
+    let mut proposals = 0;
+    for log_path in logs {
+        let source = fs::read_to_string(log_path).map_err(|e| format!("{}: {}", log_path, e))?;
+        let log = tracelog::read_log(&db, &rom, &source);
+        for warning in &log.warnings {
+            eprintln!("warning: {}: {}", log_path, warning);
+        }
+        for p in &log.proposals {
+            println!("${:02X}: Code ${:04X}-${:04X} ({} bytes)", p.bank, p.range.start(), p.range.end(), p.range.len());
+        }
+        println!("{}: {} of {} instruction line(s) placed, {} proposed region(s)", log_path, log.placed, log.lines, log.proposals.len());
+        tracelog::record_hits(&mut db, &log.hits);
+        if apply {
+            trace::apply_proposals(&mut db, &log.proposals);
+            proposals += log.proposals.len();
+        }
+    }
+    save_db(db_path, &db)?;
+    if apply {
+        println!("Recorded hit counts and added {} Code region(s) to {}", proposals, db_path);
+    } else {
+        println!("Recorded hit counts in {}", db_path);
+    }
+    Ok(())
+}
+
+fn cmd_cdl(args: &[String]) -> Result<(), String> {
+    let mut apply = false;
+    let mut replace = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--apply" => apply = true,
+            "--replace" => replace = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+            path => paths.push(path),
+        }
+    }
+    let [db_path, rom_path, cdl_path] = paths.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let rom = load_rom(rom_path)?;
+    check_rom(&mut db, &rom, rom_path)?;
+
+    let data = fs::read(cdl_path).map_err(|e| format!("{}: {}", cdl_path, e))?;
+    let flags = cdl::parse_cdl(&data, rom.prg_rom().len()).map_err(|e| format!("{}: {}", cdl_path, e))?;
+    let diff = cdl::diff(&db, &rom, &flags);
+    for change in &diff.changes {
+        println!("{}", change.label());
+    }
+    let (new, conflicts): (Vec<_>, Vec<_>) = diff.changes.into_iter().partition(|c| c.replaces.is_none());
+    println!(
+        "{} new region(s), {} conflict(s); {} byte(s) agree, {} not logged",
+        new.len(),
+        conflicts.len(),
+        diff.agreeing,
+        diff.unlogged
+    );
+    if apply {
+        cdl::apply(&mut db, &new);
+        if replace {
+            cdl::apply(&mut db, &conflicts);
+        }
+        save_db(db_path, &db)?;
+        println!("Merged {} change(s) into {}", new.len() + if replace { conflicts.len() } else { 0 }, db_path);
+    }
+    Ok(())
+}
+
+fn cmd_tbl(args: &[String]) -> Result<(), String> {
+    let [db_path, tbl_path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = load_db(db_path)?;
+    let source = fs::read_to_string(tbl_path).map_err(|e| format!("{}: {}", tbl_path, e))?;
+    let table = text::parse_tbl(&source).map_err(|e| format!("{}: {}", tbl_path, e))?;
+    let quotable = text::charmap(&table).len();
+    println!(
+        "{} entries ({} usable in strings), {} terminator(s)",
+        table.chars.len(),
+        quotable,
+        table.terminators.len()
+    );
+    db.text_table = Some(table);
+    save_db(db_path, &db)
+}
+
+// Parse `BB:AAAA` (hex, `$` optional) into a bank and CPU address.
+fn parse_bank_address(spec: &str) -> Result<(u8, u16), String> {
+    let hex = |s: &str| s.trim_start_matches('$').to_string();
+    let (bank, addr) = spec.split_once(':').ok_or_else(|| format!("expected BB:AAAA, got {}", spec))?;
+    let bank = u8::from_str_radix(&hex(bank), 16).map_err(|e| format!("{}: {}", spec, e))?;
+    let addr = u16::from_str_radix(&hex(addr), 16).map_err(|e| format!("{}: {}", spec, e))?;
+    Ok((bank, addr))
+}
+
+// The ROM must fit the database and, once the project is bound, be the
+// same revision.
+fn check_rom(db: &mut DisassemblyInfo, rom: &RomImage, path: &str) -> Result<(), String> {
+    rom.validate(db).map_err(|e| format!("{}: {}", path, e))?;
+    if let Some(note) = rom.fill_unset(db) {
+        eprintln!("warning: {}; run `doctool bind` to record them", note);
+    }
+    match rom.check_identity(db) {
+        IdentityCheck::Mismatch(message) => Err(format!("{}: {}", path, message)),
+        IdentityCheck::Unbound => {
+            eprintln!("warning: project is not bound to a ROM revision; run `doctool bind` to record it");
+            Ok(())
+        }
+        IdentityCheck::Match => Ok(()),
+    }
+}
+
+fn describe_change(change: &FieldChange) -> String {
+    match change.bank {
+        Some(bank) => format!("${:02X}:${:04X} {}", bank, change.address, change.field),
+        None => format!("global ${:04X} {}", change.address, change.field),
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+fn load_db(path: &str) -> Result<DisassemblyInfo, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    database::parse_db(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn save_db(path: &str, db: &DisassemblyInfo) -> Result<(), String> {
+    let text = database::serialize_db(db)?;
+    fs::write(path, text).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn load_rom(path: &str) -> Result<RomImage, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    RomImage::parse(data).map_err(|e| format!("{}: {}", path, e))
+}
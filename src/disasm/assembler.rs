@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+
+use super::{AddressingMode, OPCODES};
+use crate::models::StructDef;
+
+// Operand size override, written as the ca65 `z:` / `a:` prefixes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AddressSize {
+    Auto,
+    ZeroPage,
+    Absolute,
+}
+
+// Find the opcode byte for a mnemonic in a given addressing mode.
+pub fn encode(mnemonic: &str, mode: AddressingMode) -> Option<u8> {
+    OPCODES.iter().position(|entry| {
+        entry.as_ref().is_some_and(|i| i.mnemonic.eq_ignore_ascii_case(mnemonic) && i.mode == mode)
+    }).map(|op| op as u8)
+}
+
+// Whether an encoded instruction uses an absolute mode for an address below
+// $0100, which an assembler would otherwise shrink to zero page.
+pub fn needs_absolute_prefix(bytes: &[u8]) -> bool {
+    match (bytes.first().and_then(|&op| OPCODES[op as usize].as_ref()), bytes.get(2)) {
+        (Some(instr), Some(0)) => matches!(
+            instr.mode,
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY
+        ),
+        _ => false,
+    }
+}
+
+fn has_mode(mnemonic: &str, mode: AddressingMode) -> bool {
+    encode(mnemonic, mode).is_some()
+}
+
+// Assemble one instruction in ca65 syntax at `pc`. Symbols are looked up
+// through `lookup`.
+pub fn assemble_instruction(
+    mnemonic: &str,
+    operand: &str,
+    pc: u16,
+    lookup: &dyn Fn(&str) -> Result<u16, String>,
+) -> Result<Vec<u8>, String> {
+    if !OPCODES.iter().flatten().any(|i| i.mnemonic.eq_ignore_ascii_case(mnemonic)) {
+        return Err(format!("unknown mnemonic {}", mnemonic));
+    }
+    let operand = operand.trim();
+
+    let (mode, value) = if operand.is_empty() {
+        if has_mode(mnemonic, AddressingMode::Implied) {
+            (AddressingMode::Implied, 0)
+        } else {
+            (AddressingMode::Accumulator, 0)
+        }
+    } else if operand.eq_ignore_ascii_case("A") && has_mode(mnemonic, AddressingMode::Accumulator) {
+        (AddressingMode::Accumulator, 0)
+    } else if let Some(expr) = operand.strip_prefix('#') {
+        (AddressingMode::Immediate, eval(expr, lookup)?)
+    } else if let Some(inner) = operand.strip_prefix('(') {
+        let upper = inner.to_ascii_uppercase();
+        if let Some(expr) = strip_suffix_ci(inner, &upper, ",X)") {
+            (AddressingMode::IndexedIndirect, eval(expr, lookup)?)
+        } else if let Some(expr) = strip_suffix_ci(inner, &upper, "),Y") {
+            (AddressingMode::IndirectIndexed, eval(expr, lookup)?)
+        } else if let Some(expr) = inner.strip_suffix(')') {
+            (AddressingMode::Indirect, eval(expr, lookup)?)
+        } else {
+            return Err(format!("malformed operand {}", operand));
+        }
+    } else if has_mode(mnemonic, AddressingMode::Relative) {
+        let target = eval(operand, lookup)?;
+        let offset = target as i32 - (pc as i32 + 2);
+        if !(-128..=127).contains(&offset) {
+            return Err(format!("branch target ${:04X} out of range", target));
+        }
+        (AddressingMode::Relative, offset as u8 as u32)
+    } else {
+        let upper = operand.to_ascii_uppercase();
+        let (expr, index) = if let Some(expr) = strip_suffix_ci(operand, &upper, ",X") {
+            (expr, Some('X'))
+        } else if let Some(expr) = strip_suffix_ci(operand, &upper, ",Y") {
+            (expr, Some('Y'))
+        } else {
+            (operand, None)
+        };
+        let (size, expr) = split_size_prefix(expr.trim());
+        let value = eval(expr, lookup)?;
+        let (zp, abs) = match index {
+            Some('X') => (AddressingMode::ZeroPageX, AddressingMode::AbsoluteX),
+            Some(_) => (AddressingMode::ZeroPageY, AddressingMode::AbsoluteY),
+            None => (AddressingMode::ZeroPage, AddressingMode::Absolute),
+        };
+        let use_zp = match size {
+            AddressSize::ZeroPage => true,
+            AddressSize::Absolute => false,
+            AddressSize::Auto => value < 0x100 && has_mode(mnemonic, zp),
+        };
+        (if use_zp { zp } else { abs }, value)
+    };
+
+    let opcode = encode(mnemonic, mode)
+        .ok_or_else(|| format!("{} does not support {:?} addressing", mnemonic, mode))?;
+    let mut bytes = vec![opcode];
+    match mode.operand_length() {
+        0 => {}
+        1 => {
+            if value > 0xFF {
+                return Err(format!("operand ${:X} does not fit in a byte", value));
+            }
+            bytes.push(value as u8);
+        }
+        _ => {
+            if value > 0xFFFF {
+                return Err(format!("operand ${:X} does not fit in a word", value));
+            }
+            bytes.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+// Assemble a `.byt` / `.word` / `.lobytes` / `.hibytes` data directive.
+// Quoted strings in `.byt` go through `charmap`, like ca65's `.charmap`.
+pub fn assemble_data(
+    directive: &str,
+    operand: &str,
+    lookup: &dyn Fn(&str) -> Result<u16, String>,
+    charmap: &BTreeMap<char, u8>,
+) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for expr in split_operands(operand) {
+        if let Some(string) = expr.trim().strip_prefix('"').and_then(|e| e.strip_suffix('"')) {
+            if !matches!(directive, ".byt" | ".byte") {
+                return Err(format!("string in {}", directive));
+            }
+            for c in string.chars() {
+                bytes.push(match charmap.get(&c) {
+                    Some(&byte) => byte,
+                    None if c.is_ascii() => c as u8,
+                    None => return Err(format!("character {:?} has no byte value", c)),
+                });
+            }
+            continue;
+        }
+        let value = eval(expr, lookup)?;
+        match directive {
+            ".byt" | ".byte" => {
+                if value > 0xFF {
+                    return Err(format!("${:X} does not fit in a byte", value));
+                }
+                bytes.push(value as u8);
+            }
+            ".lobytes" => bytes.push(value as u8),
+            ".hibytes" => bytes.push((value >> 8) as u8),
+            ".word" | ".addr" => {
+                if value > 0xFFFF {
+                    return Err(format!("${:X} does not fit in a word", value));
+                }
+                bytes.extend_from_slice(&(value as u16).to_le_bytes());
+            }
+            _ => return Err(format!("unknown directive {}", directive)),
+        }
+    }
+    Ok(bytes)
+}
+
+// Assemble a Struct record: one expression per field.
+pub fn assemble_record(
+    def: &StructDef,
+    operand: &str,
+    lookup: &dyn Fn(&str) -> Result<u16, String>,
+) -> Result<Vec<u8>, String> {
+    let values = split_operands(operand);
+    if values.len() != def.fields.len() {
+        return Err(format!("record has {} fields but {} values were given", def.fields.len(), values.len()));
+    }
+    let mut bytes = Vec::new();
+    for (field, expr) in def.fields.iter().zip(values) {
+        let value = eval(expr, lookup)?;
+        if field.kind.size() == 1 {
+            if value > 0xFF {
+                return Err(format!("{}: ${:X} does not fit in a byte", field.name, value));
+            }
+            bytes.push(value as u8);
+        } else {
+            if value > 0xFFFF {
+                return Err(format!("{}: ${:X} does not fit in a word", field.name, value));
+            }
+            bytes.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+// Split a data operand at commas outside quoted strings.
+fn split_operands(operand: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in operand.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(&operand[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&operand[start..]);
+    parts
+}
+
+fn strip_suffix_ci<'a>(text: &'a str, upper: &str, suffix: &str) -> Option<&'a str> {
+    if upper.ends_with(suffix) { Some(&text[..text.len() - suffix.len()]) } else { None }
+}
+
+fn split_size_prefix(expr: &str) -> (AddressSize, &str) {
+    if let Some(rest) = expr.strip_prefix("a:") {
+        (AddressSize::Absolute, rest)
+    } else if let Some(rest) = expr.strip_prefix("z:") {
+        (AddressSize::ZeroPage, rest)
+    } else {
+        (AddressSize::Auto, expr)
+    }
+}
+
+// Evaluate a small expression: terms joined by `+`/`-`, where a term is a
+// `$hex`, `%binary` or decimal number, a symbol, or `<term`/`>term` for the
+// low/high byte.
+pub fn eval(expr: &str, lookup: &dyn Fn(&str) -> Result<u16, String>) -> Result<u32, String> {
+    let expr = expr.trim();
+    if expr.is_empty() {
+        return Err("missing operand".to_string());
+    }
+    let mut total: i64 = 0;
+    let mut sign = 1;
+    let mut rest = expr;
+    loop {
+        let end = operator_position(rest).unwrap_or(rest.len());
+        total += sign * eval_term(rest[..end].trim(), lookup)? as i64;
+        if end == rest.len() {
+            break;
+        }
+        sign = if rest.as_bytes()[end] == b'+' { 1 } else { -1 };
+        rest = &rest[end + 1..];
+    }
+    if total < 0 {
+        return Err(format!("{} is negative", expr));
+    }
+    Ok(total as u32)
+}
+
+// The first `+` / `-` outside parentheses, skipping a leading sign and the
+// signs of an unnamed label reference such as `:++`.
+fn operator_position(expr: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut unnamed = false;
+    for (i, c) in expr.char_indices() {
+        match c {
+            ':' => unnamed = true,
+            '+' | '-' if unnamed => {}
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '+' | '-' if i > 0 && depth == 0 => return Some(i),
+            _ => {}
+        }
+        if !matches!(c, ':' | '+' | '-') {
+            unnamed = false;
+        }
+    }
+    None
+}
+
+fn eval_term(term: &str, lookup: &dyn Fn(&str) -> Result<u16, String>) -> Result<u32, String> {
+    if let Some(inner) = term.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        return eval(inner, lookup);
+    }
+    if let Some(inner) = term.strip_prefix('<') {
+        return Ok(eval_term(inner.trim(), lookup)? & 0xFF);
+    }
+    if let Some(inner) = term.strip_prefix('>') {
+        return Ok((eval_term(inner.trim(), lookup)? >> 8) & 0xFF);
+    }
+    let parsed = if let Some(hex) = term.strip_prefix('$') {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = term.strip_prefix('%') {
+        u32::from_str_radix(bin, 2).ok()
+    } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+        term.parse::<u32>().ok()
+    } else {
+        return lookup(term).map(|v| v as u32);
+    };
+    parsed.ok_or_else(|| format!("bad number {}", term))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Result<u16, String> {
+        match name {
+            "ptr" => Ok(0x0010),
+            "table" => Ok(0x8123),
+            ":+" => Ok(0xC010),
+            ":++" => Ok(0xC020),
+            ":-" => Ok(0xBFF0),
+            _ => Err(format!("unknown symbol {}", name)),
+        }
+    }
+
+    fn asm(mnemonic: &str, operand: &str) -> Result<Vec<u8>, String> {
+        assemble_instruction(mnemonic, operand, 0xC000, &lookup)
+    }
+
+    #[test]
+    fn addressing_modes() {
+        assert_eq!(asm("NOP", ""), Ok(vec![0xEA]));
+        assert_eq!(asm("ASL", ""), Ok(vec![0x0A]));
+        assert_eq!(asm("ROL", "A"), Ok(vec![0x2A]));
+        assert_eq!(asm("LDA", "#$12"), Ok(vec![0xA9, 0x12]));
+        assert_eq!(asm("LDA", "#<table"), Ok(vec![0xA9, 0x23]));
+        assert_eq!(asm("LDA", "#>table"), Ok(vec![0xA9, 0x81]));
+        assert_eq!(asm("LDA", "ptr"), Ok(vec![0xA5, 0x10]));
+        assert_eq!(asm("LDA", "ptr,X"), Ok(vec![0xB5, 0x10]));
+        assert_eq!(asm("LDX", "ptr,Y"), Ok(vec![0xB6, 0x10]));
+        assert_eq!(asm("LDA", "table"), Ok(vec![0xAD, 0x23, 0x81]));
+        assert_eq!(asm("LDA", "table,x"), Ok(vec![0xBD, 0x23, 0x81]));
+        assert_eq!(asm("LDA", "table+1,Y"), Ok(vec![0xB9, 0x24, 0x81]));
+        assert_eq!(asm("LDA", "(ptr,X)"), Ok(vec![0xA1, 0x10]));
+        assert_eq!(asm("LDA", "(ptr),y"), Ok(vec![0xB1, 0x10]));
+        assert_eq!(asm("JMP", "($0300)"), Ok(vec![0x6C, 0x00, 0x03]));
+        assert_eq!(asm("JSR", "table"), Ok(vec![0x20, 0x23, 0x81]));
+    }
+
+    #[test]
+    fn size_prefixes() {
+        assert_eq!(asm("LDA", "a:ptr"), Ok(vec![0xAD, 0x10, 0x00]));
+        assert_eq!(asm("STA", "a:$0010,X"), Ok(vec![0x9D, 0x10, 0x00]));
+        assert_eq!(asm("LDA", "z:ptr"), Ok(vec![0xA5, 0x10]));
+        // LDA has no zero page,Y form, so a small address stays absolute.
+        assert_eq!(asm("LDA", "ptr,Y"), Ok(vec![0xB9, 0x10, 0x00]));
+        assert!(needs_absolute_prefix(&[0xAD, 0x10, 0x00]));
+        assert!(!needs_absolute_prefix(&[0xAD, 0x10, 0x80]));
+        assert!(!needs_absolute_prefix(&[0xA5, 0x10]));
+    }
+
+    #[test]
+    fn branches() {
+        assert_eq!(asm("BNE", "$C010"), Ok(vec![0xD0, 0x0E]));
+        assert_eq!(asm("BEQ", "$BFF0"), Ok(vec![0xF0, 0xEE]));
+        assert_eq!(asm("BCC", "$C081"), Ok(vec![0x90, 0x7F]));
+        assert!(asm("BCC", "$C082").is_err());
+        assert!(asm("BCS", "$BF81").is_err());
+    }
+
+    #[test]
+    fn unnamed_labels() {
+        assert_eq!(asm("BNE", ":+"), Ok(vec![0xD0, 0x0E]));
+        assert_eq!(asm("BNE", ":++"), Ok(vec![0xD0, 0x1E]));
+        assert_eq!(asm("BCS", ":-"), Ok(vec![0xB0, 0xEE]));
+        assert_eq!(asm("JMP", ":+"), Ok(vec![0x4C, 0x10, 0xC0]));
+        // The signs of `:+` are part of the label, not an operator.
+        assert_eq!(eval(":+ + 2", &lookup), Ok(0xC012));
+        assert_eq!(eval(":++ - 1", &lookup), Ok(0xC01F));
+        assert_eq!(eval(":- - 1", &lookup), Ok(0xBFEF));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(asm("FOO", "").is_err());
+        assert!(asm("STA", "#$12").is_err());
+        assert!(asm("LDA", "#$123").is_err());
+        assert!(asm("LDA", "missing").is_err());
+        assert!(asm("LDA", "(ptr").is_err());
+        assert!(asm("JMP", "ptr,X").is_err());
+    }
+
+    #[test]
+    fn data() {
+        let charmap = BTreeMap::from([('A', 0x0A)]);
+        assert_eq!(assemble_data(".byt", "$01, %10, 3, \"AB\"", &lookup, &charmap), Ok(vec![1, 2, 3, 0x0A, b'B']));
+        assert_eq!(assemble_data(".word", "table, $1234", &lookup, &charmap), Ok(vec![0x23, 0x81, 0x34, 0x12]));
+        assert_eq!(assemble_data(".lobytes", "table", &lookup, &charmap), Ok(vec![0x23]));
+        assert_eq!(assemble_data(".hibytes", "table", &lookup, &charmap), Ok(vec![0x81]));
+        assert!(assemble_data(".byt", "$100", &lookup, &charmap).is_err());
+        assert!(assemble_data(".word", "\"AB\"", &lookup, &charmap).is_err());
+    }
+}
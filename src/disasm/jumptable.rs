@@ -0,0 +1,103 @@
+use super::has_symbol;
+use crate::mapper::{Location, MapperModel};
+use crate::models::{DisassemblyInfo, JumpTableInfo};
+
+// One pointer of an inline jump table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableEntry {
+    // Address of the entry (of its low byte in a split table).
+    pub address: u16,
+    // Address of the high byte: `address + 1`, or `address + count` when split.
+    pub hi_address: u16,
+    pub pointer: u16,
+    // `pointer + offset`: the routine the entry dispatches to.
+    pub target: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JumpTable {
+    pub info: JumpTableInfo,
+    pub entries: Vec<TableEntry>,
+    // First address after the table.
+    pub next: u32,
+}
+
+// The dispatcher annotation of the routine at `address` as seen from
+// `bank_id`: the banks the mapper can place there, then the global section.
+pub fn dispatcher_at(db: &DisassemblyInfo, mapper: &MapperModel, bank_id: u8, address: u16) -> Option<JumpTableInfo> {
+    let location = mapper.locate(bank_id, address);
+    location
+        .banks()
+        .iter()
+        .find_map(|id| db.bank.get(id)?.address.get(&address)?.dispatcher.clone())
+        .or_else(|| db.global.get(&address)?.dispatcher.clone())
+}
+
+// Decode the table that starts at `start` in a bank mapped at `base`, without
+// running past `limit`. A table without a count ends at the next label, at a
+// pointer outside ROM, or where an earlier entry points.
+#[allow(clippy::too_many_arguments)]
+pub fn decode(
+    db: &DisassemblyInfo,
+    mapper: &MapperModel,
+    bank_id: u8,
+    data: &[u8],
+    base: u16,
+    start: u16,
+    limit: u16,
+    info: &JumpTableInfo,
+) -> JumpTable {
+    let mut table = JumpTable { info: info.clone(), entries: Vec::new(), next: start as u32 };
+    let byte_at = |addr: u32| -> Option<u8> {
+        if addr < base as u32 || addr > limit as u32 {
+            return None;
+        }
+        data.get((addr - base as u32) as usize).copied()
+    };
+    let target_of = |pointer: u16| pointer.wrapping_add(info.offset as u16);
+
+    if info.split {
+        // The split halves can only be found with a known length.
+        let Some(count) = info.count else { return table };
+        let hi_start = start as u32 + count as u32;
+        for i in 0..count as u32 {
+            let (Some(lo), Some(hi)) = (byte_at(start as u32 + i), byte_at(hi_start + i)) else { break };
+            let pointer = u16::from_le_bytes([lo, hi]);
+            table.entries.push(TableEntry {
+                address: (start as u32 + i) as u16,
+                hi_address: (hi_start + i) as u16,
+                pointer,
+                target: target_of(pointer),
+            });
+        }
+        if table.entries.len() == count as usize {
+            table.next = hi_start + count as u32;
+        } else {
+            table.entries.clear();
+        }
+        return table;
+    }
+
+    let width = info.entry_width.max(2) as u32;
+    let mut addr = start as u32;
+    while info.count.is_none_or(|count| table.entries.len() < count as usize) {
+        let (Some(lo), Some(hi), Some(_)) = (byte_at(addr), byte_at(addr + 1), byte_at(addr + width - 1)) else {
+            break;
+        };
+        let pointer = u16::from_le_bytes([lo, hi]);
+        let target = target_of(pointer);
+        if info.count.is_none() {
+            let reached_code = table.entries.iter().any(|e| (addr..addr + width).contains(&(e.target as u32)));
+            if (addr != start as u32 && has_symbol(db, bank_id, addr as u16)) || reached_code {
+                break;
+            }
+            if mapper.locate(bank_id, target) == Location::NotRom {
+                break;
+            }
+        }
+        table.entries.push(TableEntry { address: addr as u16, hi_address: (addr + 1) as u16, pointer, target });
+        addr += width;
+    }
+    table.next = addr;
+    table
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::models::DisassemblyInfo;
+
+// Whether a symbol is a local label: a cheap local (`@loop`), visible only
+// between the labels around it, or an unnamed label `:` referenced as `:+`
+// (the next one) or `:-` (the previous one). Neither opens a scope.
+pub fn is_local(symbol: &str) -> bool {
+    symbol.starts_with('@') || symbol == ":"
+}
+
+// Auto-labels for referenced addresses, and the scopes local labels live in.
+// A scope runs from a label that is not local to the next one in the bank.
+#[derive(Debug, Clone, Default)]
+pub struct Labels {
+    // Referenced addresses of each bank. Those without a symbol get an
+    // auto-label.
+    pub targets: BTreeMap<u8, HashSet<u16>>,
+    // Auto-labels only referenced from inside their own scope, written as
+    // `@Lxxxx` rather than `Lxxxx`.
+    pub local: BTreeMap<u8, HashSet<u16>>,
+    // Addresses that open a scope, as ca65 sees the exported source: bank
+    // symbols that are not local and get a label line of their own, and the
+    // auto-labels that are not local either.
+    scopes: BTreeMap<u8, BTreeSet<u16>>,
+    // Unnamed `:` labels of each bank.
+    unnamed: BTreeMap<u8, BTreeSet<u16>>,
+}
+
+impl Labels {
+    // `referrers` lists, for each (bank, address) a reference may land on,
+    // the (bank, address) of every instruction or pointer referring to it.
+    // Targets and symbols in `inside` fall inside an instruction and never
+    // get a label line, so they are referenced as equates, not made local
+    // and do not open a scope.
+    pub fn new(
+        db: &DisassemblyInfo,
+        targets: BTreeMap<u8, HashSet<u16>>,
+        referrers: &HashMap<(u8, u16), Vec<(u8, u16)>>,
+        inside: &BTreeMap<u8, HashSet<u16>>,
+    ) -> Self {
+        let mut labels = Labels::default();
+        // Global names are defined in globals.inc, so a bank symbol reusing
+        // one gets no label, and neither does a repeat of a name earlier in
+        // the bank.
+        let global_names: HashSet<&str> = db.global.values().filter_map(|a| a.symbol.as_deref()).collect();
+        for (&bank_id, bank) in &db.bank {
+            let mid_line = |addr: &u16| inside.get(&bank_id).is_some_and(|i| i.contains(addr));
+            let mut seen = HashSet::new();
+            let explicit = labels.scopes.entry(bank_id).or_default();
+            for (&addr, anno) in &bank.address {
+                match anno.symbol.as_deref() {
+                    _ if mid_line(&addr) => {}
+                    Some(":") => {
+                        labels.unnamed.entry(bank_id).or_default().insert(addr);
+                    }
+                    Some(symbol) if !is_local(symbol) && !global_names.contains(symbol) && seen.insert(symbol) => {
+                        explicit.insert(addr);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for (&bank_id, bank_targets) in &targets {
+            let named = |addr: &u16| {
+                db.bank.get(&bank_id).and_then(|b| b.address.get(addr)).is_some_and(|a| a.symbol.is_some())
+                    || db.global.get(addr).is_some_and(|a| a.symbol.is_some())
+            };
+            let scopes = labels.scopes.entry(bank_id).or_default();
+            // Start with every auto-label local and turn those referenced from
+            // another scope into scope boundaries, which can leave further
+            // references outside their target's scope, until nothing changes.
+            let mid_line = |addr: &u16| inside.get(&bank_id).is_some_and(|i| i.contains(addr));
+            let mut local: HashSet<u16> = bank_targets.iter().copied().filter(|a| !named(a) && !mid_line(a)).collect();
+            loop {
+                let scope_of = |addr: u16| scopes.range(..=addr).next_back().copied();
+                let escaped: Vec<u16> = local
+                    .iter()
+                    .copied()
+                    .filter(|&to| {
+                        let scope = scope_of(to);
+                        scope.is_none()
+                            || referrers
+                                .get(&(bank_id, to))
+                                .is_some_and(|refs| refs.iter().any(|&(bank, from)| bank != bank_id || scope_of(from) != scope))
+                    })
+                    .collect();
+                if escaped.is_empty() {
+                    break;
+                }
+                for addr in escaped {
+                    local.remove(&addr);
+                    scopes.insert(addr);
+                }
+            }
+            labels.local.insert(bank_id, local);
+        }
+        labels.targets = targets;
+        labels
+    }
+
+    // The auto-label for a target address.
+    pub fn auto_name(&self, bank_id: u8, address: u16) -> String {
+        if self.local.get(&bank_id).is_some_and(|l| l.contains(&address)) {
+            format!("@L{:04X}", address)
+        } else {
+            format!("L{:04X}", address)
+        }
+    }
+
+    // The label opening the scope an address of a bank is in.
+    pub fn scope_of(&self, bank_id: u8, address: u16) -> Option<u16> {
+        self.scopes.get(&bank_id)?.range(..=address).next_back().copied()
+    }
+
+    // Whether the label line at an address opens a scope.
+    pub fn opens_scope(&self, bank_id: u8, address: u16) -> bool {
+        self.scopes.get(&bank_id).is_some_and(|s| s.contains(&address))
+    }
+
+    // Whether a local label at `to` can be named from `from` in the same bank.
+    pub fn in_scope(&self, bank_id: u8, from: u16, to: u16) -> bool {
+        let scope = self.scope_of(bank_id, from);
+        scope.is_some() && scope == self.scope_of(bank_id, to)
+    }
+
+    // `:+`, `:++`, `:-`, ... naming the unnamed label at `to` from `from`. A
+    // label on the referring line itself counts as the previous one.
+    pub fn unnamed_ref(&self, bank_id: u8, from: u16, to: u16) -> Option<String> {
+        let unnamed = self.unnamed.get(&bank_id)?;
+        if !unnamed.contains(&to) {
+            return None;
+        }
+        if to > from {
+            Some(format!(":{}", "+".repeat(unnamed.range(from + 1..=to).count())))
+        } else {
+            Some(format!(":{}", "-".repeat(unnamed.range(to..=from).count())))
+        }
+    }
+}
@@ -1,7 +1,20 @@
-use crate::models::{AnnotationInfo, DisassemblyInfo, DisassemblyLine, RegionInfo};
-use std::collections::{HashSet, BTreeMap};
-
-#[derive(Debug, Clone, Copy)]
+use crate::mapper::MapperModel;
+use crate::models::{AnnotationInfo, DisassemblyInfo, DisassemblyLine, FieldKind, RegionInfo};
+use crate::rom::RomImage;
+use std::collections::{HashMap, HashSet, BTreeMap};
+
+use labels::Labels;
+
+pub mod assembler;
+pub mod jumptable;
+pub mod labels;
+pub mod text;
+pub mod trace;
+pub mod validate;
+pub mod verify;
+pub mod xref;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressingMode {
     Implied,
     Accumulator,
@@ -311,22 +324,133 @@ pub const OPCODES: [Option<Instruction>; 256] = {
     table
 };
 
-pub fn discover_all_targets(db: &DisassemblyInfo, rom_data: &[u8]) -> BTreeMap<u8, HashSet<u16>> {
-    let mut bank_targets = BTreeMap::new();
-    let mapper_size = db.mapper_window_size as u32 * 1024;
-    let fixed_range = db.mapper_fixed_range.as_ref();
+// Number of PRG banks in the ROM at the configured mapper window size.
+// RomImage::validate rejects ROMs with more banks than a u8 bank id
+// can name, so the count always fits.
+pub fn prg_bank_count(db: &DisassemblyInfo, rom: &RomImage) -> u8 {
+    u8::try_from(rom.prg_bank_count(db.mapper_window_size)).unwrap_or(u8::MAX)
+}
+
+// Named values of Struct enum fields, which the export defines as constants.
+// A name given different values by two fields keeps the first, in struct
+// order; the other value is shown as a number.
+pub fn struct_constants(db: &DisassemblyInfo) -> BTreeMap<String, u8> {
+    let mut constants = BTreeMap::new();
+    for def in db.structs.values() {
+        for field in &def.fields {
+            if let FieldKind::Enum(values) = &field.kind {
+                for (&value, name) in values {
+                    constants.entry(name.clone()).or_insert(value);
+                }
+            }
+        }
+    }
+    constants
+}
+
+// The slice of the ROM image holding the given PRG bank.
+pub fn bank_data<'a>(db: &DisassemblyInfo, rom: &'a RomImage, bank_id: u8) -> &'a [u8] {
+    rom.prg_bank(db.mapper_window_size, bank_id)
+}
+
+// The CPU address a bank is disassembled at: BankInfo::mapped_at, the
+// window the mapper model hardwires it to, or the mapper window containing
+// its first region.
+pub fn bank_base_address(db: &DisassemblyInfo, bank_id: u8) -> u16 {
+    MapperModel::new(db).base_address(bank_id)
+}
+
+// The start and length of every instruction in a bank's Code regions, walked
+// the way disassemble_bank does, skipping the tables after dispatcher calls.
+pub fn instruction_lengths(db: &DisassemblyInfo, mapper: &MapperModel, bank_id: u8, data: &[u8]) -> BTreeMap<u32, u32> {
+    let base = mapper.base_address(bank_id) as u32;
+    let mut instructions = BTreeMap::new();
+    let Some(bank) = db.bank.get(&bank_id) else { return instructions };
+    for region in &bank.region {
+        let RegionInfo::Code(range) = region else { continue };
+        let end = *range.end() as u32;
+        let mut pc = *range.start() as u32;
+        while pc <= end {
+            let offset = pc.wrapping_sub(base) as usize;
+            if pc < base || offset >= data.len() {
+                break;
+            }
+            let instr = &OPCODES[data[offset] as usize];
+            let length = instr.as_ref().map_or(1, |i| 1 + i.mode.operand_length() as u32);
+            instructions.insert(pc, length);
+            let operand = match (data.get(offset + 1), data.get(offset + 2)) {
+                (Some(&lo), Some(&hi)) => u16::from_le_bytes([lo, hi]),
+                _ => 0,
+            };
+            pc += length;
+            if instr.as_ref().is_some_and(|i| i.mnemonic == "JSR")
+                && let Some(info) = jumptable::dispatcher_at(db, mapper, bank_id, operand)
+            {
+                pc = jumptable::decode(db, mapper, bank_id, data, base as u16, pc as u16, end as u16, &info).next;
+            }
+        }
+    }
+    instructions
+}
+
+pub fn discover_all_targets(db: &DisassemblyInfo, rom: &RomImage) -> Labels {
+    let mapper = MapperModel::new(db);
+    let mut bank_targets: BTreeMap<u8, HashSet<u16>> = db.bank.keys().map(|&id| (id, HashSet::new())).collect();
+    let mut referrers: HashMap<(u8, u16), Vec<(u8, u16)>> = HashMap::new();
+    scan_references(db, rom, &mut |r| {
+        // Indirect jumps name a vector, not a location worth a label.
+        if r.mode == Some(AddressingMode::Indirect) {
+            return;
+        }
+        // Targets are credited to the bank the mapper places them in.
+        let location = mapper.locate(r.bank, r.to);
+        if let Some(owner) = location.unique_bank() {
+            bank_targets.entry(owner).or_default().insert(r.to);
+        }
+        // A reference that may land in several banks keeps all of them from
+        // using a local label there.
+        for &id in location.banks() {
+            referrers.entry((id, r.to)).or_default().push((r.bank, r.from));
+        }
+    });
+    // Targets and symbols inside an instruction, a word or a record never
+    // start a line, so they cannot be labelled where they are.
+    let inside = bank_targets
+        .iter()
+        .map(|(&id, targets)| {
+            let mut lines = instruction_lengths(db, &mapper, id, bank_data(db, rom, id));
+            for region in db.bank.get(&id).map_or(&[][..], |b| &b.region) {
+                let (range, size) = match region {
+                    RegionInfo::Words(range) => (range, 2),
+                    RegionInfo::Struct { range, name } => match db.structs.get(name).map(|d| d.size() as u32) {
+                        Some(size) if size > 1 => (range, size),
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                lines.extend((*range.start() as u32..=*range.end() as u32).step_by(size as usize).map(|pc| (pc, size)));
+            }
+            let symbols = db.bank.get(&id).into_iter().flat_map(|b| b.address.iter().filter(|(_, a)| a.symbol.is_some()).map(|(&addr, _)| addr));
+            let inside = targets
+                .iter()
+                .copied()
+                .chain(symbols)
+                .filter(|&to| lines.range(..to as u32).next_back().is_some_and(|(&pc, &length)| (to as u32) < pc + length))
+                .collect();
+            (id, inside)
+        })
+        .collect();
+    Labels::new(db, bank_targets, &referrers, &inside)
+}
+
+// Walk every annotated region and report each address it refers to:
+// instruction operands, data pointers and jump table entries.
+pub fn scan_references(db: &DisassemblyInfo, rom: &RomImage, visit: &mut dyn FnMut(xref::Reference)) {
+    let mapper = MapperModel::new(db);
 
     for (&bank_id, bank_info) in &db.bank {
-        let mut targets = HashSet::new();
-        let base_address = bank_info.mapped_at.unwrap_or(0x8000);
-        let rom_offset = 16 + (bank_id as usize * mapper_size as usize);
-        let rom_end = (rom_offset + mapper_size as usize).min(rom_data.len());
-        
-        let bank_data = if rom_offset < rom_data.len() {
-            &rom_data[rom_offset..rom_end]
-        } else {
-            &[]
-        };
+        let base_address = mapper.base_address(bank_id);
+        let bank_data = bank_data(db, rom, bank_id);
 
         for region in &bank_info.region {
             match region {
@@ -345,16 +469,32 @@ pub fn discover_all_targets(db: &DisassemblyInfo, rom_data: &[u8]) -> BTreeMap<u
                                     op_val |= (bank_data[offset + (j as usize)] as u32) << (8 * (j - 1));
                                 }
                             }
-                            let (_, target_addr) = resolve_target(Some(instr.mode), op_val, pc as u16, db, bank_id);
-                            if let Some(addr) = target_addr {
-                                // If target is in this bank or fixed range, it's a candidate for auto-labeling
-                                let addr_32 = addr as u32;
-                                if (addr_32 >= base_address as u32 && addr_32 < base_address as u32 + mapper_size) || 
-                                   fixed_range.map_or(false, |r| r.contains(&addr)) {
-                                    targets.insert(addr);
-                                }
+                            let to = match instr.mode {
+                                AddressingMode::Indirect | AddressingMode::IndexedIndirect | AddressingMode::IndirectIndexed => Some(op_val as u16),
+                                mode => target_address(mode, op_val, pc as u16),
+                            };
+                            if let Some(to) = to {
+                                visit(xref::Reference {
+                                    bank: bank_id,
+                                    from: pc as u16,
+                                    to,
+                                    kind: xref::RefKind::of(instr.mnemonic, instr.mode),
+                                    mnemonic: instr.mnemonic,
+                                    mode: Some(instr.mode),
+                                });
                             }
                             pc += 1 + len;
+                            // The pointers after a JSR to a dispatcher are code targets.
+                            if instr.mnemonic == "JSR"
+                                && let Some(info) = jumptable::dispatcher_at(db, &mapper, bank_id, op_val as u16)
+                            {
+                                let table = jumptable::decode(db, &mapper, bank_id, bank_data, base_address, pc as u16, end as u16, &info);
+                                for entry in &table.entries {
+                                    let mnemonic = if info.split { ".lobytes" } else { ".word" };
+                                    visit(xref::Reference::pointer(bank_id, entry.address, entry.target, mnemonic));
+                                }
+                                pc = table.next;
+                            }
                         } else {
                             pc += 1;
                         }
@@ -365,31 +505,49 @@ pub fn discover_all_targets(db: &DisassemblyInfo, rom_data: &[u8]) -> BTreeMap<u
                     let end = *range.end() as u32;
                     while pc <= end {
                         let offset = (pc.wrapping_sub(base_address as u32)) as usize;
-                        if offset + 1 >= rom_data.len() { break; }
-                        let low = rom_data[offset];
-                        let high = rom_data[offset + 1];
-                        let val = (high as u16) << 8 | (low as u16);
-                        let val_32 = val as u32;
-                        if (val_32 >= base_address as u32 && val_32 < base_address as u32 + mapper_size) || 
-                           fixed_range.map_or(false, |r| r.contains(&val)) {
-                            targets.insert(val);
-                        }
+                        if offset + 1 >= bank_data.len() { break; }
+                        let low = bank_data[offset];
+                        let high = bank_data[offset + 1];
+                        visit(xref::Reference::pointer(bank_id, pc as u16, (high as u16) << 8 | (low as u16), ".word"));
                         pc += 2;
                     }
                 }
+                RegionInfo::Struct { range, name } => {
+                    let Some(def) = db.structs.get(name).filter(|d| d.size() > 0) else { continue };
+                    let size = def.size() as u32;
+                    let mut pc = *range.start() as u32;
+                    while pc + size - 1 <= *range.end() as u32 {
+                        let mut offset = pc.wrapping_sub(base_address as u32) as usize;
+                        for field in &def.fields {
+                            if field.kind == FieldKind::Pointer && offset + 1 < bank_data.len() {
+                                let to = u16::from_le_bytes([bank_data[offset], bank_data[offset + 1]]);
+                                visit(xref::Reference::pointer(bank_id, pc as u16, to, "struct"));
+                            }
+                            offset += field.kind.size() as usize;
+                        }
+                        pc += size;
+                    }
+                }
+                RegionInfo::SplitPointers { lo, hi, offset } => {
+                    let byte_at = |addr: u16| bank_data.get(addr.wrapping_sub(base_address) as usize).copied();
+                    for (lo_addr, hi_addr) in lo.clone().zip(hi.clone()) {
+                        if let (Some(low), Some(high)) = (byte_at(lo_addr), byte_at(hi_addr)) {
+                            let to = u16::from_le_bytes([low, high]).wrapping_add(*offset as u16);
+                            visit(xref::Reference::pointer(bank_id, lo_addr, to, ".lobytes"));
+                        }
+                    }
+                }
                 _ => {}
             }
         }
-        bank_targets.insert(bank_id, targets);
     }
-    bank_targets
 }
 
 pub fn disassemble_bank(
     db: &DisassemblyInfo,
     bank_id: u8,
     rom_data: &[u8],
-    bank_targets: &BTreeMap<u8, HashSet<u16>>,
+    labels: &Labels,
 ) -> Vec<DisassemblyLine> {
     let mut lines = Vec::new();
     let bank_info = match db.bank.get(&bank_id) {
@@ -397,26 +555,17 @@ pub fn disassemble_bank(
         None => return lines,
     };
 
-    let mut regions = bank_info.region.clone();
-    regions.sort_by_key(|r| match r {
-        RegionInfo::Code(range) => *range.start(),
-        RegionInfo::Bytes(range) => *range.start(),
-        RegionInfo::Words(range) => *range.start(),
-    });
+    // Each range of a region, in address order (split tables have two).
+    let mut regions = bank_info
+        .region
+        .iter()
+        .flat_map(|r| r.ranges().into_iter().map(move |range| (range.clone(), r.clone())))
+        .collect::<Vec<_>>();
+    regions.sort_by_key(|(range, _)| *range.start());
 
     // Detect base address from BankInfo::mapped_at or regions
-    let mut base_address = bank_info.mapped_at.unwrap_or(0x8000);
-    if bank_info.mapped_at.is_none() {
-        if let Some(first_region) = regions.first() {
-            let start = match first_region {
-                RegionInfo::Code(r) => *r.start(),
-                RegionInfo::Bytes(r) => *r.start(),
-                RegionInfo::Words(r) => *r.start(),
-            };
-            let window_size = db.mapper_window_size as u16 * 1024;
-            base_address = (start / window_size) * window_size;
-        }
-    }
+    let mapper = MapperModel::new(db);
+    let base_address = mapper.base_address(bank_id);
 
     let mapper_size = db.mapper_window_size as u32 * 1024;
     let bank_start = base_address as u32;
@@ -426,29 +575,27 @@ pub fn disassemble_bank(
     let mut filled_regions = Vec::new();
     let mut current_pc = bank_start;
 
-    for region in regions {
-        let (r_start, r_end) = match &region {
-            RegionInfo::Code(r) => (*r.start() as u32, *r.end() as u32),
-            RegionInfo::Bytes(r) => (*r.start() as u32, *r.end() as u32),
-            RegionInfo::Words(r) => (*r.start() as u32, *r.end() as u32),
-        };
+    for (range, region) in regions {
+        let (r_start, r_end) = (*range.start() as u32, *range.end() as u32);
 
         if r_start > current_pc {
-            filled_regions.push(RegionInfo::Bytes((current_pc as u16)..=(r_start as u16 - 1)));
+            let gap = (current_pc as u16)..=(r_start as u16 - 1);
+            filled_regions.push((gap.clone(), RegionInfo::Bytes(gap)));
         }
-        filled_regions.push(region);
+        filled_regions.push((range, region));
         current_pc = r_end + 1;
     }
 
     if current_pc <= bank_end {
-        filled_regions.push(RegionInfo::Bytes((current_pc as u16)..=(bank_end as u16)));
+        let gap = (current_pc as u16)..=(bank_end as u16);
+        filled_regions.push((gap.clone(), RegionInfo::Bytes(gap)));
     }
 
-    let current_targets = bank_targets.get(&bank_id);
+    let current_targets = labels.targets.get(&bank_id);
 
-    for region in filled_regions {
+    for (range, region) in filled_regions {
         match region {
-            RegionInfo::Code(range) => {
+            RegionInfo::Code(_) => {
                 let mut pc = *range.start() as u32;
                 let end = *range.end() as u32;
                 while pc <= end {
@@ -471,18 +618,18 @@ pub fn disassemble_bank(
                                 }
                             }
                             
-                            let (p, m, s, sym) = format_operand(i.mode, op_val, pc as u16, db, bank_id, bank_targets);
+                            let (p, m, s, sym) = format_operand(i.mode, op_val, pc as u16, db, &mapper, bank_id, labels);
                             (b, p, m, s, sym, i.mnemonic, 1 + len)
                         }
                         None => (format!("{:02X}", opcode), String::new(), String::new(), String::new(), false, "???", 1),
                     };
 
                     let annotation = get_annotation(db, bank_id, pc as u16);
-                    let (target_bank, target_addr) = resolve_target(instr.as_ref().map(|i| i.mode), op_val, pc as u16, db, bank_id);
+                    let (target_bank, target_addr) = resolve_target(instr.as_ref().map(|i| i.mode), op_val, pc as u16, db, &mapper, bank_id);
 
                     let mut line_symbol = annotation.symbol;
                     if line_symbol.is_none() && current_targets.map_or(false, |t| t.contains(&(pc as u16))) {
-                        line_symbol = Some(format!("L{:04X}", pc));
+                        line_symbol = Some(labels.auto_name(bank_id, pc as u16));
                     }
 
                     lines.push(DisassemblyLine {
@@ -500,12 +647,20 @@ pub fn disassemble_bank(
                         block_comment: annotation.block_comment,
                         target_bank,
                         target_address: target_addr,
+                        text: None,
                     });
 
                     pc += length;
+                    if mnemonic == "JSR"
+                        && let Some(info) = jumptable::dispatcher_at(db, &mapper, bank_id, op_val as u16)
+                    {
+                        let table = jumptable::decode(db, &mapper, bank_id, rom_data, base_address, pc as u16, end as u16, &info);
+                        lines.extend(table_lines(db, &mapper, bank_id, rom_data, base_address, &table, labels));
+                        pc = table.next;
+                    }
                 }
             }
-            RegionInfo::Bytes(range) => {
+            RegionInfo::Bytes(_) => {
                 let mut pc = *range.start() as u32;
                 let end = *range.end() as u32;
                 while pc <= end {
@@ -541,7 +696,7 @@ pub fn disassemble_bank(
                         let annotation = get_annotation(db, bank_id, start_pc as u16);
                         let mut line_symbol = annotation.symbol;
                         if line_symbol.is_none() && current_targets.map_or(false, |t| t.contains(&(start_pc as u16))) {
-                            line_symbol = Some(format!("L{:04X}", start_pc));
+                            line_symbol = Some(labels.auto_name(bank_id, start_pc as u16));
                         }
 
                         lines.push(DisassemblyLine {
@@ -559,13 +714,14 @@ pub fn disassemble_bank(
                             block_comment: annotation.block_comment,
                             target_bank: None,
                             target_address: None,
+                            text: None,
                         });
                     } else {
                         break; 
                     }
                 }
             }
-            RegionInfo::Words(range) => {
+            RegionInfo::Words(_) => {
                 let mut pc = *range.start() as u32;
                 let end = *range.end() as u32;
                 while pc <= end {
@@ -577,14 +733,14 @@ pub fn disassemble_bank(
                     let high = rom_data[offset + 1];
                     let val = (high as u16) << 8 | (low as u16);
 
-                    let (main, is_sym) = resolve_symbol(val, db, bank_id, false, bank_targets);
+                    let (main, is_sym) = resolve_symbol(val, start_pc as u16, db, &mapper, bank_id, false, labels);
                     let annotation = get_annotation(db, bank_id, start_pc as u16);
                     
-                    let (target_bank, target_addr) = resolve_target(Some(AddressingMode::Absolute), val as u32, pc as u16, db, bank_id);
+                    let (target_bank, target_addr) = resolve_target(Some(AddressingMode::Absolute), val as u32, pc as u16, db, &mapper, bank_id);
 
                     let mut line_symbol = annotation.symbol;
                     if line_symbol.is_none() && current_targets.map_or(false, |t| t.contains(&(start_pc as u16))) {
-                        line_symbol = Some(format!("L{:04X}", start_pc));
+                        line_symbol = Some(labels.auto_name(bank_id, start_pc as u16));
                     }
 
                     lines.push(DisassemblyLine {
@@ -602,87 +758,316 @@ pub fn disassemble_bank(
                         block_comment: annotation.block_comment,
                         target_bank,
                         target_address: target_addr,
+                        text: None,
                     });
 
                     pc += 2;
                 }
             }
+            RegionInfo::Text(_) => {
+                let table = db.text_table.clone().unwrap_or_default();
+                let charmap = text::charmap(&table);
+                let mut pc = *range.start() as u32;
+                let end = *range.end() as u32;
+                while pc <= end {
+                    let start_pc = pc;
+                    let mut bytes = Vec::new();
+                    // A line ends at a terminator, before a label, or after 32 bytes.
+                    while pc <= end && bytes.len() < 32 {
+                        let labeled = has_symbol(db, bank_id, pc as u16) || current_targets.is_some_and(|t| t.contains(&(pc as u16)));
+                        if !bytes.is_empty() && labeled {
+                            break;
+                        }
+                        let Some(&byte) = rom_data.get((pc.wrapping_sub(base_address as u32)) as usize) else { break };
+                        bytes.push(byte);
+                        pc += 1;
+                        if table.terminators.contains(&byte) {
+                            break;
+                        }
+                    }
+                    if bytes.is_empty() {
+                        break;
+                    }
+
+                    let annotation = get_annotation(db, bank_id, start_pc as u16);
+                    let mut line_symbol = annotation.symbol;
+                    if line_symbol.is_none() && current_targets.is_some_and(|t| t.contains(&(start_pc as u16))) {
+                        line_symbol = Some(labels.auto_name(bank_id, start_pc as u16));
+                    }
+                    lines.push(DisassemblyLine {
+                        address_label: format!("${:02X}:${:04X}", bank_id, start_pc),
+                        address: start_pc as u16,
+                        bank: bank_id as i16,
+                        bytes: bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "),
+                        opcode: ".byt".to_string(),
+                        operand_prefix: String::new(),
+                        operand_main: text::operand(&bytes, &table, &charmap),
+                        operand_suffix: String::new(),
+                        operand_is_symbol: false,
+                        symbol: line_symbol,
+                        comment: annotation.comment,
+                        block_comment: annotation.block_comment,
+                        target_bank: None,
+                        target_address: None,
+                        text: Some(text::decode(&bytes, &table)),
+                    });
+                }
+            }
+            RegionInfo::Struct { name, .. } => {
+                lines.extend(struct_lines(db, &mapper, bank_id, rom_data, base_address, &range, &name, labels));
+            }
+            RegionInfo::SplitPointers { lo, hi, offset } => {
+                let is_lo = range == lo;
+                let byte_at = |addr: u16| rom_data.get(addr.wrapping_sub(base_address) as usize).copied();
+                for (i, address) in range.clone().enumerate() {
+                    let Some(byte) = byte_at(address) else { break };
+                    let pointer = match (lo.clone().nth(i), hi.clone().nth(i)) {
+                        (Some(lo_addr), Some(hi_addr)) => byte_at(lo_addr).zip(byte_at(hi_addr)),
+                        _ => None,
+                    };
+                    let (opcode, target) = match pointer {
+                        Some((low, high)) => (
+                            if is_lo { ".lobytes" } else { ".hibytes" },
+                            Some((u16::from_le_bytes([low, high]).wrapping_add(offset as u16), offset)),
+                        ),
+                        // Unpaired bytes when the halves differ in length.
+                        None => (".byt", None),
+                    };
+                    lines.push(pointer_line(db, &mapper, bank_id, address, &[byte], opcode, target, labels));
+                }
+            }
         }
     }
 
     lines
 }
 
-fn resolve_target(mode: Option<AddressingMode>, value: u32, pc: u16, db: &DisassemblyInfo, bank_id: u8) -> (Option<u8>, Option<u16>) {
-    let mode = match mode {
-        Some(m) => m,
-        None => return (None, None),
+// Render a jump table: `.word` entries (plus a `.byt` line for any argument
+// bytes), or `.lobytes` / `.hibytes` halves for a split table.
+fn table_lines(
+    db: &DisassemblyInfo,
+    mapper: &MapperModel,
+    bank_id: u8,
+    rom_data: &[u8],
+    base_address: u16,
+    table: &jumptable::JumpTable,
+    labels: &Labels,
+) -> Vec<DisassemblyLine> {
+    let byte_at = |address: u16| rom_data[address.wrapping_sub(base_address) as usize];
+    let offset = table.info.offset;
+    let line = |address: u16, bytes: &[u8], opcode: &str, target: Option<u16>| {
+        pointer_line(db, mapper, bank_id, address, bytes, opcode, target.map(|t| (t, offset)), labels)
     };
 
-    let target_addr = match mode {
-        AddressingMode::ZeroPage | AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => Some(value as u16),
-        AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => Some(value as u16),
-        AddressingMode::Relative => {
-            let offset = value as i8;
-            Some(pc.wrapping_add(2).wrapping_add(offset as u16))
+    let mut lines = Vec::new();
+    if table.info.split {
+        for entry in &table.entries {
+            lines.push(line(entry.address, &[byte_at(entry.address)], ".lobytes", Some(entry.target)));
         }
-        _ => None,
-    };
-
-    if let Some(addr) = target_addr {
-        let mapper_size = db.mapper_window_size as u32 * 1024;
-        let current_bank_info = db.bank.get(&bank_id);
-        let base_address = current_bank_info.and_then(|b| b.mapped_at).unwrap_or(0x8000);
-        let bank_end = base_address as u32 + mapper_size - 1;
-
-        // 1. If target is in the fixed range, it belongs to the fixed bank (unless current is also fixed)
-        if let Some(fixed_range) = &db.mapper_fixed_range {
-            if fixed_range.contains(&addr) {
-                if let Some(fixed_id) = db.find_fixed_bank_id() {
-                    return (Some(fixed_id), Some(addr));
-                }
-            }
+        for entry in &table.entries {
+            lines.push(line(entry.hi_address, &[byte_at(entry.hi_address)], ".hibytes", Some(entry.target)));
         }
+        return lines;
+    }
+    let width = table.info.entry_width.max(2) as u16;
+    for entry in &table.entries {
+        lines.push(line(entry.address, &entry.pointer.to_le_bytes(), ".word", Some(entry.target)));
+        if width > 2 {
+            let args = (2..width).map(|i| byte_at(entry.address + i)).collect::<Vec<_>>();
+            lines.push(line(entry.address + 2, &args, ".byt", None));
+        }
+    }
+    lines
+}
 
-        // 2. Check local bank for user-defined symbol
-        if let Some(bank) = current_bank_info {
-            if bank.address.contains_key(&addr) {
-                return (Some(bank_id), Some(addr));
-            }
+// One data line of a pointer table. With a `(target, offset)` the operand
+// names the target, written as `Target-offset` so it assembles back to the
+// stored pointer; otherwise the bytes are listed.
+#[allow(clippy::too_many_arguments)]
+fn pointer_line(
+    db: &DisassemblyInfo,
+    mapper: &MapperModel,
+    bank_id: u8,
+    address: u16,
+    bytes: &[u8],
+    opcode: &str,
+    target: Option<(u16, i16)>,
+    labels: &Labels,
+) -> DisassemblyLine {
+    let annotation = get_annotation(db, bank_id, address);
+    let mut symbol = annotation.symbol;
+    if symbol.is_none() && labels.targets.get(&bank_id).is_some_and(|t| t.contains(&address)) {
+        symbol = Some(labels.auto_name(bank_id, address));
+    }
+    let (main, suffix, is_sym, target_bank, target_address) = match target {
+        Some((target, offset)) => {
+            let (main, is_sym) = resolve_symbol(target, address, db, mapper, bank_id, false, labels);
+            let (target_bank, target_address) = resolve_target(Some(AddressingMode::Absolute), target as u32, address, db, mapper, bank_id);
+            let suffix = match offset {
+                0 => String::new(),
+                n if n > 0 => format!("-{}", n),
+                n => format!("+{}", n.unsigned_abs()),
+            };
+            (main, suffix, is_sym, target_bank, target_address)
         }
+        None => {
+            let main = bytes.iter().map(|b| format!("${:02X}", b)).collect::<Vec<_>>().join(", ");
+            (main, String::new(), false, None, None)
+        }
+    };
+    DisassemblyLine {
+        address_label: format!("${:02X}:${:04X}", bank_id, address),
+        address,
+        bank: bank_id as i16,
+        bytes: bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "),
+        opcode: opcode.to_string(),
+        operand_prefix: String::new(),
+        operand_main: main,
+        operand_suffix: suffix,
+        operand_is_symbol: is_sym,
+        symbol,
+        comment: annotation.comment,
+        block_comment: annotation.block_comment,
+        target_bank,
+        target_address,
+        text: None,
+    }
+}
 
-        // 3. Resolution for cross-bank symbols
-        let current_fixed = current_bank_info.map(|b| b.is_fixed).unwrap_or(false);
-        if current_fixed {
-            // Resolution for fixed bank: Local -> Others -> Global
-            for (other_id, other_bank) in &db.bank {
-                if *other_id != bank_id && other_bank.address.contains_key(&addr) {
-                    return (Some(*other_id), Some(addr));
-                }
+// Render a Struct region: one line per record, written as an invocation of
+// the macro named after the struct, then `.byt` lines for any bytes that do
+// not fill a whole record.
+#[allow(clippy::too_many_arguments)]
+fn struct_lines(
+    db: &DisassemblyInfo,
+    mapper: &MapperModel,
+    bank_id: u8,
+    rom_data: &[u8],
+    base_address: u16,
+    range: &std::ops::RangeInclusive<u16>,
+    name: &str,
+    labels: &Labels,
+) -> Vec<DisassemblyLine> {
+    let byte_at = |addr: u32| rom_data.get(addr.wrapping_sub(base_address as u32) as usize).copied();
+    let mut lines = Vec::new();
+    let mut pc = *range.start() as u32;
+    let end = *range.end() as u32;
+
+    if let Some(def) = db.structs.get(name)
+        && def.size() > 0
+    {
+        let size = def.size() as u32;
+        let constants = struct_constants(db);
+        while pc + size - 1 <= end {
+            let Some(bytes) = (pc..pc + size).map(byte_at).collect::<Option<Vec<u8>>>() else { break };
+            let mut operands = Vec::new();
+            let mut described = Vec::new();
+            let mut pointer = None;
+            let mut offset = 0;
+            for field in &def.fields {
+                let word = || u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+                let text = match &field.kind {
+                    FieldKind::Byte => format!("${:02X}", bytes[offset]),
+                    FieldKind::Word => format!("${:04X}", word()),
+                    FieldKind::Pointer => {
+                        let (text, is_sym) = resolve_symbol(word(), pc as u16, db, mapper, bank_id, false, labels);
+                        pointer = pointer.or(Some((word(), is_sym)));
+                        text
+                    }
+                    FieldKind::Enum(values) => values
+                        .get(&bytes[offset])
+                        .filter(|name| constants.get(*name) == Some(&bytes[offset]))
+                        .cloned()
+                        .unwrap_or_else(|| format!("${:02X}", bytes[offset])),
+                };
+                described.push(match &field.comment {
+                    Some(comment) => format!("{}={} ({})", field.name, text, comment),
+                    None => format!("{}={}", field.name, text),
+                });
+                operands.push(text);
+                offset += field.kind.size() as usize;
             }
-        } else {
-            // Resolution for non-fixed bank: Local -> Fixed -> Global
-            for (other_id, other_bank) in &db.bank {
-                if other_bank.is_fixed && other_bank.address.contains_key(&addr) {
-                    return (Some(*other_id), Some(addr));
-                }
+
+            let annotation = get_annotation(db, bank_id, pc as u16);
+            let mut symbol = annotation.symbol;
+            if symbol.is_none() && labels.targets.get(&bank_id).is_some_and(|t| t.contains(&(pc as u16))) {
+                symbol = Some(labels.auto_name(bank_id, pc as u16));
             }
+            // The first pointer field is the line's link target.
+            let (target_bank, target_address) = match pointer {
+                Some((value, _)) => resolve_target(Some(AddressingMode::Absolute), value as u32, pc as u16, db, mapper, bank_id),
+                None => (None, None),
+            };
+            lines.push(DisassemblyLine {
+                address_label: format!("${:02X}:${:04X}", bank_id, pc),
+                address: pc as u16,
+                bank: bank_id as i16,
+                bytes: bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "),
+                opcode: name.to_string(),
+                operand_prefix: String::new(),
+                operand_main: operands.join(", "),
+                operand_suffix: String::new(),
+                operand_is_symbol: pointer.is_some_and(|(_, is_sym)| is_sym),
+                symbol,
+                comment: annotation.comment,
+                block_comment: annotation.block_comment,
+                target_bank,
+                target_address,
+                text: Some(described.join(", ")),
+            });
+            pc += size;
         }
+    }
 
-        // 4. Check global section
-        if db.global.contains_key(&addr) {
-             return (None, Some(addr));
+    while pc <= end {
+        let chunk = (pc..=end.min(pc + 7)).map_while(byte_at).collect::<Vec<_>>();
+        if chunk.is_empty() {
+            break;
         }
+        lines.push(pointer_line(db, mapper, bank_id, pc as u16, &chunk, ".byt", None, labels));
+        pc += chunk.len() as u32;
+    }
+    lines
+}
 
-        // 5. Fallback for auto-labels (Lxxxx): if it's in the current bank's window
-        if addr as u32 >= base_address as u32 && addr as u32 <= bank_end {
-            return (Some(bank_id), Some(addr));
+// The address an operand refers to, if any.
+fn target_address(mode: AddressingMode, value: u32, pc: u16) -> Option<u16> {
+    match mode {
+        AddressingMode::ZeroPage | AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => Some(value as u16),
+        AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => Some(value as u16),
+        AddressingMode::Relative => {
+            let offset = value as i8;
+            Some(pc.wrapping_add(2).wrapping_add(offset as u16))
         }
+        _ => None,
+    }
+}
+
+fn resolve_target(mode: Option<AddressingMode>, value: u32, pc: u16, db: &DisassemblyInfo, mapper: &MapperModel, bank_id: u8) -> (Option<u8>, Option<u16>) {
+    let addr = match mode.and_then(|m| target_address(m, value, pc)) {
+        Some(addr) => addr,
+        None => return (None, None),
+    };
+    let defines = |id: &u8| db.bank.get(id).is_some_and(|b| b.address.contains_key(&addr));
+
+    // 1. User-defined symbol in the local bank
+    if defines(&bank_id) {
+        return (Some(bank_id), Some(addr));
+    }
+
+    // 2. The bank(s) the mapper can place at this address
+    let location = mapper.locate(bank_id, addr);
+    if let Some(&id) = location.banks().iter().find(|id| defines(id)) {
+        return (Some(id), Some(addr));
+    }
 
+    // 3. Global section
+    if db.global.contains_key(&addr) {
         return (None, Some(addr));
     }
 
-    (None, None)
+    // 4. Fallback for auto-labels (Lxxxx) when only one bank can be there
+    (location.unique_bank(), Some(addr))
 }
 
 fn has_symbol(db: &DisassemblyInfo, bank_id: u8, address: u16) -> bool {
@@ -717,39 +1102,39 @@ fn get_annotation(db: &DisassemblyInfo, bank_id: u8, address: u16) -> Annotation
     result
 }
 
-fn format_operand(mode: AddressingMode, value: u32, pc: u16, db: &DisassemblyInfo, bank_id: u8, bank_targets: &BTreeMap<u8, HashSet<u16>>) -> (String, String, String, bool) {
+fn format_operand(mode: AddressingMode, value: u32, pc: u16, db: &DisassemblyInfo, mapper: &MapperModel, bank_id: u8, labels: &Labels) -> (String, String, String, bool) {
     match mode {
         AddressingMode::Implied => (String::new(), String::new(), String::new(), false),
         AddressingMode::Accumulator => (String::new(), "A".to_string(), String::new(), false),
         AddressingMode::Immediate => ("#".to_string(), format!("${:02X}", value), String::new(), false),
         AddressingMode::ZeroPage => {
-            let (m, sym) = resolve_symbol(value as u16, db, bank_id, true, bank_targets);
+            let (m, sym) = resolve_symbol(value as u16, pc, db, mapper, bank_id, true, labels);
             (String::new(), m, String::new(), sym)
         }
         AddressingMode::ZeroPageX => {
-            let (m, sym) = resolve_symbol(value as u16, db, bank_id, true, bank_targets);
+            let (m, sym) = resolve_symbol(value as u16, pc, db, mapper, bank_id, true, labels);
             (String::new(), m, ",X".to_string(), sym)
         }
         AddressingMode::ZeroPageY => {
-            let (m, sym) = resolve_symbol(value as u16, db, bank_id, true, bank_targets);
+            let (m, sym) = resolve_symbol(value as u16, pc, db, mapper, bank_id, true, labels);
             (String::new(), m, ",Y".to_string(), sym)
         }
         AddressingMode::Relative => {
             let offset = value as i8;
             let target = pc.wrapping_add(2).wrapping_add(offset as u16);
-            let (m, sym) = resolve_symbol(target, db, bank_id, false, bank_targets);
+            let (m, sym) = resolve_symbol(target, pc, db, mapper, bank_id, false, labels);
             (String::new(), m, String::new(), sym)
         }
         AddressingMode::Absolute => {
-            let (m, sym) = resolve_symbol(value as u16, db, bank_id, false, bank_targets);
+            let (m, sym) = resolve_symbol(value as u16, pc, db, mapper, bank_id, false, labels);
             (String::new(), m, String::new(), sym)
         }
         AddressingMode::AbsoluteX => {
-            let (m, sym) = resolve_symbol(value as u16, db, bank_id, false, bank_targets);
+            let (m, sym) = resolve_symbol(value as u16, pc, db, mapper, bank_id, false, labels);
             (String::new(), m, ",X".to_string(), sym)
         }
         AddressingMode::AbsoluteY => {
-            let (m, sym) = resolve_symbol(value as u16, db, bank_id, false, bank_targets);
+            let (m, sym) = resolve_symbol(value as u16, pc, db, mapper, bank_id, false, labels);
             (String::new(), m, ",Y".to_string(), sym)
         }
         AddressingMode::Indirect => {
@@ -764,78 +1149,47 @@ fn format_operand(mode: AddressingMode, value: u32, pc: u16, db: &DisassemblyInf
     }
 }
 
-fn resolve_symbol(address: u16, db: &DisassemblyInfo, bank_id: u8, is_zp: bool, bank_targets: &BTreeMap<u8, HashSet<u16>>) -> (String, bool) {
-    let current_bank_info = db.bank.get(&bank_id);
-    let current_fixed = current_bank_info.map(|b| b.is_fixed).unwrap_or(false);
+// The name an operand at `from` uses for `address`. Local labels are only
+// named from inside their scope (unnamed ones from their own bank); elsewhere
+// the address is written as a number.
+fn resolve_symbol(address: u16, from: u16, db: &DisassemblyInfo, mapper: &MapperModel, bank_id: u8, is_zp: bool, labels: &Labels) -> (String, bool) {
+    let symbol_in = |id: &u8| db.bank.get(id).and_then(|b| b.address.get(&address)).and_then(|a| a.symbol.clone());
+    let numeric = || if is_zp { (format!("${:02X}", address), false) } else { (format!("${:04X}", address), false) };
 
     // Rule 1: Check local bank (Explicit)
-    if let Some(bank) = current_bank_info {
-        if let Some(anno) = bank.address.get(&address) {
-            if let Some(ref sym) = anno.symbol {
-                return (sym.clone(), true);
-            }
+    if let Some(sym) = symbol_in(&bank_id) {
+        if sym == ":" {
+            return labels.unnamed_ref(bank_id, from, address).map_or_else(numeric, |name| (name, true));
         }
+        if labels::is_local(&sym) && !labels.in_scope(bank_id, from, address) {
+            return numeric();
+        }
+        return (sym, true);
     }
 
-    // Rule 2 & 3: Check cross-bank explicit symbols
-    if current_fixed {
-        // Resolution for fixed bank: Local -> Others -> Global
-        for (other_id, other_bank) in &db.bank {
-            if *other_id == bank_id { continue; }
-            if let Some(anno) = other_bank.address.get(&address) {
-                if let Some(ref sym) = anno.symbol {
-                    return (sym.clone(), true);
-                }
-            }
-        }
-    } else {
-        // Resolution for non-fixed bank: Local -> Fixed Banks -> Global
-        for (_other_id, other_bank) in &db.bank {
-            if !other_bank.is_fixed { continue; }
-            if let Some(anno) = other_bank.address.get(&address) {
-                if let Some(ref sym) = anno.symbol {
-                    return (sym.clone(), true);
-                }
-            }
+    // Rule 2: Check the banks the mapper can place at this address (Explicit)
+    let location = mapper.locate(bank_id, address);
+    if let Some(sym) = location.banks().iter().find_map(symbol_in) {
+        if labels::is_local(&sym) {
+            return numeric();
         }
+        return (sym, true);
     }
 
     // Check global address (Explicit)
-    if let Some(anno) = db.global.get(&address) {
-        if let Some(ref sym) = anno.symbol {
-            return (sym.clone(), true);
-        }
-    }
-    
-    // Check for auto-label in local bank
-    if let Some(targets) = bank_targets.get(&bank_id) {
-        if targets.contains(&address) {
-            return (format!("L{:04X}", address), true);
-        }
+    if let Some(sym) = db.global.get(&address).and_then(|a| a.symbol.clone()) {
+        return (sym, true);
     }
 
-    // Check for auto-label in other banks according to user rules
-    if current_fixed {
-        // Fixed bank sees auto-labels in ALL other banks
-        for (other_id, targets) in bank_targets {
-            if *other_id != bank_id && targets.contains(&address) {
-                return (format!("L{:04X}", address), true);
-            }
-        }
-    } else {
-        // Non-fixed bank sees auto-labels only in FIXED banks
-        for (other_id, targets) in bank_targets {
-            if let Some(other_bank) = db.bank.get(other_id) {
-                if other_bank.is_fixed && targets.contains(&address) {
-                    return (format!("L{:04X}", address), true);
-                }
-            }
+    // Check for auto-labels in the local bank, then the mapped banks
+    let has_auto_label = |id: &u8| labels.targets.get(id).is_some_and(|t| t.contains(&address));
+    if let Some(&id) = std::iter::once(&bank_id).chain(location.banks()).find(|id| has_auto_label(id)) {
+        let name = labels.auto_name(id, address);
+        if labels::is_local(&name) && (id != bank_id || !labels.in_scope(bank_id, from, address)) {
+            return numeric();
         }
+        return (name, true);
     }
 
-    if is_zp {
-        (format!("${:02X}", address), false)
-    } else {
-        (format!("${:04X}", address), false)
-    }
+    numeric()
 }
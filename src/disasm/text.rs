@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use crate::models::TextTable;
+
+// Parse a TBL character table: one `HH=text` entry per line. A leading `/`
+// (`/FF=<end>`) marks a byte that ends a string.
+pub fn parse_tbl(source: &str) -> Result<TextTable, String> {
+    let mut table = TextTable::default();
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| format!("line {}: expected HH=text", number + 1))?;
+        let (key, terminator) = match key.trim().strip_prefix('/') {
+            Some(key) => (key, true),
+            None => (key.trim(), false),
+        };
+        if key.len() != 2 {
+            return Err(format!("line {}: only single-byte entries are supported, got {}", number + 1, key));
+        }
+        let byte = u8::from_str_radix(key, 16).map_err(|_| format!("line {}: bad byte {}", number + 1, key))?;
+        table.chars.insert(byte, value.to_string());
+        if terminator {
+            table.terminators.push(byte);
+        }
+    }
+    table.terminators.sort_unstable();
+    table.terminators.dedup();
+    Ok(table)
+}
+
+// Bytes that can be written inside a quoted string: those mapped to a single
+// printable ASCII character. When several bytes map to the same character
+// only the lowest is used, so `.charmap` can map it back.
+pub fn charmap(table: &TextTable) -> BTreeMap<char, u8> {
+    let mut map = BTreeMap::new();
+    for (&byte, text) in &table.chars {
+        let mut chars = text.chars();
+        if let (Some(c), None) = (chars.next(), chars.next())
+            && (c == ' ' || c.is_ascii_graphic())
+            && c != '"'
+            && c != '\\'
+        {
+            map.entry(c).or_insert(byte);
+        }
+    }
+    map
+}
+
+// ca65 operand for the bytes of a text line: quoted runs of mappable
+// characters, with other bytes (control codes) written as `$XX`.
+pub fn operand(bytes: &[u8], table: &TextTable, charmap: &BTreeMap<char, u8>) -> String {
+    let quotable = |byte: u8| {
+        let text = table.chars.get(&byte)?;
+        let c = text.chars().next()?;
+        (charmap.get(&c) == Some(&byte)).then_some(c)
+    };
+    let mut parts = Vec::new();
+    let mut run = String::new();
+    for &byte in bytes {
+        match quotable(byte) {
+            Some(c) => run.push(c),
+            None => {
+                if !run.is_empty() {
+                    parts.push(format!("\"{}\"", std::mem::take(&mut run)));
+                }
+                parts.push(format!("${:02X}", byte));
+            }
+        }
+    }
+    if !run.is_empty() {
+        parts.push(format!("\"{}\"", run));
+    }
+    parts.join(", ")
+}
+
+// The text the bytes stand for, with unmapped bytes shown as `[XX]`.
+pub fn decode(bytes: &[u8], table: &TextTable) -> String {
+    bytes
+        .iter()
+        .map(|byte| match table.chars.get(byte) {
+            Some(text) => text.clone(),
+            None => format!("[{:02X}]", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ops::RangeInclusive;
+
+use super::{bank_data, jumptable, prg_bank_count, AddressingMode, OPCODES};
+use crate::mapper::MapperModel;
+use crate::models::{BankInfo, DisassemblyInfo, RegionInfo};
+use crate::rom::RomImage;
+
+// NMI, RESET and IRQ.
+const VECTORS: [u16; 3] = [0xFFFA, 0xFFFC, 0xFFFE];
+
+// A `Code` region found by the tracer that is not covered by the database yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proposal {
+    pub bank: u8,
+    pub range: RangeInclusive<u16>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TraceResult {
+    pub proposals: Vec<Proposal>,
+    // Paths the tracer had to abandon (illegal opcodes, unknown banks).
+    pub warnings: Vec<String>,
+}
+
+// Entry points: the NMI/RESET/IRQ vectors of every bank mapped at $FFFA, plus
+// BankInfo::entry_points.
+pub fn entry_points(db: &DisassemblyInfo, rom: &RomImage) -> Vec<(u8, u16)> {
+    let db = with_all_banks(db, rom);
+    let mapper = MapperModel::new(&db);
+    let mut entries = Vec::new();
+    for &bank_id in db.bank.keys() {
+        let base = mapper.base_address(bank_id);
+        let data = bank_data(&db, rom, bank_id);
+        for vector in VECTORS {
+            let offset = vector.wrapping_sub(base) as usize;
+            if vector >= base && offset + 1 < data.len() {
+                let target = u16::from_le_bytes([data[offset], data[offset + 1]]);
+                if let Some(owner) = mapper.locate(bank_id, target).unique_bank() {
+                    entries.push((owner, target));
+                }
+            }
+        }
+    }
+    for (&bank_id, bank) in &db.bank {
+        entries.extend(bank.entry_points.iter().map(|&addr| (bank_id, addr)));
+    }
+    entries.sort_unstable();
+    entries.dedup();
+    entries
+}
+
+// Follow code flow from `entries` and propose Code regions for the bytes
+// reached that no Code region covers. Tracing stops at RTS/RTI/BRK, at
+// JMP, at indirect jumps and at declared data regions. A JSR to a
+// dispatcher continues into every routine its jump table points to.
+pub fn trace_code(db: &DisassemblyInfo, rom: &RomImage, entries: &[(u8, u16)]) -> TraceResult {
+    let db = with_all_banks(db, rom);
+    let mapper = MapperModel::new(&db);
+    let mut result = TraceResult::default();
+    let mut visited: HashSet<(u8, u16)> = HashSet::new();
+    let mut code: BTreeMap<u8, BTreeSet<u16>> = BTreeMap::new();
+    let mut work = entries.to_vec();
+
+    while let Some((bank_id, start)) = work.pop() {
+        let base = mapper.base_address(bank_id) as u32;
+        let data = bank_data(&db, rom, bank_id);
+        let Some(bank) = db.bank.get(&bank_id) else {
+            result.warnings.push(format!("${:02X}:${:04X}: bank ${:02X} is not in the ROM", bank_id, start, bank_id));
+            continue;
+        };
+        let regions = &bank.region;
+        let mut pc = start;
+
+        loop {
+            if !visited.insert((bank_id, pc)) {
+                break;
+            }
+            let offset = (pc as u32).wrapping_sub(base) as usize;
+            if (pc as u32) < base || offset >= data.len() {
+                result.warnings.push(format!("${:02X}:${:04X} is outside the bank", bank_id, pc));
+                break;
+            }
+            if regions.iter().any(|r| !matches!(r, RegionInfo::Code(_)) && r.contains(pc)) {
+                break;
+            }
+            let opcode = data[offset];
+            let Some(instr) = &OPCODES[opcode as usize] else {
+                result.warnings.push(format!("${:02X}:${:04X}: illegal opcode ${:02X}; path abandoned", bank_id, pc, opcode));
+                break;
+            };
+            let len = instr.mode.operand_length() as usize;
+            if offset + len >= data.len() {
+                result.warnings.push(format!("${:02X}:${:04X}: instruction runs past the end of the bank", bank_id, pc));
+                break;
+            }
+            let bytes = code.entry(bank_id).or_default();
+            for i in 0..=len as u16 {
+                bytes.insert(pc + i);
+            }
+
+            let operand = match len {
+                1 => data[offset + 1] as u16,
+                2 => u16::from_le_bytes([data[offset + 1], data[offset + 2]]),
+                _ => 0,
+            };
+            let target = match instr.mode {
+                AddressingMode::Relative => Some(pc.wrapping_add(2).wrapping_add(operand as u8 as i8 as u16)),
+                AddressingMode::Absolute if matches!(instr.mnemonic, "JMP" | "JSR") => Some(operand),
+                _ => None,
+            };
+            if let Some(target) = target {
+                match mapper.locate(bank_id, target).unique_bank() {
+                    Some(owner) => work.push((owner, target)),
+                    None => result.warnings.push(format!(
+                        "${:02X}:${:04X}: {} ${:04X} does not resolve to a single bank",
+                        bank_id, pc, instr.mnemonic, target
+                    )),
+                }
+            }
+
+            match instr.mnemonic {
+                "RTS" | "RTI" | "BRK" | "JMP" => break,
+                _ => {}
+            }
+            // A dispatcher never returns past its table: follow each pointer
+            // instead, and cover the table with the calling Code region.
+            if instr.mnemonic == "JSR"
+                && let Some(info) = jumptable::dispatcher_at(&db, &mapper, bank_id, operand)
+            {
+                let limit = (base + data.len() as u32 - 1).min(0xFFFF) as u16;
+                let table = jumptable::decode(&db, &mapper, bank_id, data, base as u16, pc + 3, limit, &info);
+                if table.entries.is_empty() {
+                    result.warnings.push(format!("${:02X}:${:04X}: no jump table found after JSR ${:04X}", bank_id, pc, operand));
+                }
+                let bytes = code.entry(bank_id).or_default();
+                for addr in (pc as u32 + 3)..table.next {
+                    bytes.insert(addr as u16);
+                }
+                for entry in &table.entries {
+                    match mapper.locate(bank_id, entry.target).unique_bank() {
+                        Some(owner) => work.push((owner, entry.target)),
+                        None => result.warnings.push(format!(
+                            "${:02X}:${:04X}: jump table entry ${:04X} does not resolve to a single bank",
+                            bank_id, entry.address, entry.target
+                        )),
+                    }
+                }
+                break;
+            }
+            match pc.checked_add(1 + len as u16) {
+                Some(next) => pc = next,
+                None => break,
+            }
+        }
+    }
+
+    result.proposals = propose(&db, code);
+    result.warnings.dedup();
+    result
+}
+
+// Code regions for the runs of `code` bytes that no Code region covers yet.
+pub fn propose(db: &DisassemblyInfo, code: BTreeMap<u8, BTreeSet<u16>>) -> Vec<Proposal> {
+    let mut proposals = Vec::new();
+    for (bank_id, bytes) in code {
+        let regions = db.bank.get(&bank_id).map_or(&[][..], |b| &b.region);
+        let covered = |addr: u16| regions.iter().any(|r| matches!(r, RegionInfo::Code(_)) && r.contains(addr));
+        let mut run: Option<RangeInclusive<u16>> = None;
+        for addr in bytes.into_iter().filter(|&a| !covered(a)) {
+            run = match run {
+                Some(r) if *r.end() as u32 + 1 == addr as u32 => Some(*r.start()..=addr),
+                Some(r) => {
+                    proposals.push(Proposal { bank: bank_id, range: r });
+                    Some(addr..=addr)
+                }
+                None => Some(addr..=addr),
+            };
+        }
+        if let Some(r) = run {
+            proposals.push(Proposal { bank: bank_id, range: r });
+        }
+    }
+    proposals
+}
+
+// Add accepted proposals to the database as Code regions.
+pub fn apply_proposals(db: &mut DisassemblyInfo, proposals: &[Proposal]) {
+    let mapper = MapperModel::new(db);
+    for proposal in proposals {
+        let bank = db.bank.entry(proposal.bank).or_insert_with(|| BankInfo {
+            title: None,
+            is_fixed: false,
+            region: Vec::new(),
+            address: BTreeMap::new(),
+            mapped_at: Some(mapper.base_address(proposal.bank)),
+            entry_points: Vec::new(),
+        });
+        bank.region.push(RegionInfo::Code(proposal.range.clone()));
+        bank.region.sort_by_key(|r| *r.range().start());
+    }
+}
+
+// Banks the ROM has but the database does not describe are traced too.
+fn with_all_banks(db: &DisassemblyInfo, rom: &RomImage) -> DisassemblyInfo {
+    let mut db = db.clone();
+    for bank_id in 0..prg_bank_count(&db, rom) {
+        db.bank.entry(bank_id).or_insert_with(|| BankInfo {
+            title: None,
+            is_fixed: false,
+            region: Vec::new(),
+            address: BTreeMap::new(),
+            mapped_at: None,
+            entry_points: Vec::new(),
+        });
+    }
+    db
+}
@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use super::{bank_data, discover_all_targets, instruction_lengths, labels, scan_references, OPCODES};
+use crate::mapper::MapperModel;
+use crate::models::{Assembler, DisassemblyInfo, RegionInfo};
+use crate::rom::RomImage;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProblemKind {
+    // Two regions of a bank cover the same bytes.
+    Overlap,
+    // A region lies outside the CPU addresses its bank is mapped at.
+    OutsideBank,
+    // The last instruction of a Code region runs past its end.
+    StraddlesRegionEnd,
+    // A label that would fall on an operand byte, and cannot be emitted.
+    SymbolInsideInstruction,
+    // A name defined at two addresses that can be visible at the same time.
+    DuplicateSymbol,
+    // A name the project's assembler does not accept.
+    InvalidSymbol,
+    // A reference to a local label from outside its scope, which has to be
+    // written as a number.
+    LocalOutOfScope,
+}
+
+impl ProblemKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProblemKind::Overlap => "overlap",
+            ProblemKind::OutsideBank => "outside bank",
+            ProblemKind::StraddlesRegionEnd => "straddles region end",
+            ProblemKind::SymbolInsideInstruction => "symbol inside instruction",
+            ProblemKind::DuplicateSymbol => "duplicate symbol",
+            ProblemKind::InvalidSymbol => "invalid symbol",
+            ProblemKind::LocalOutOfScope => "local label out of scope",
+        }
+    }
+}
+
+// An inconsistency in the database. `bank` is None for the global section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub bank: Option<u8>,
+    pub address: u16,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn location(&self) -> String {
+        match self.bank {
+            Some(id) => format!("${:02X}:${:04X}", id, self.address),
+            None => format!("${:04X}", self.address),
+        }
+    }
+}
+
+// Check the regions and symbols of every bank.
+pub fn validate(db: &DisassemblyInfo, rom: &RomImage) -> Vec<Problem> {
+    let mapper = MapperModel::new(db);
+    let window = db.mapper_window_size as u32 * 1024;
+    let mut problems = Vec::new();
+
+    for (&bank_id, bank) in &db.bank {
+        let problem = |kind, address, message| Problem { kind, bank: Some(bank_id), address, message };
+        let base = mapper.base_address(bank_id) as u32;
+        let bank_end = base + window - 1;
+
+        let mut ranges = bank.region.iter().flat_map(|r| r.ranges().into_iter().map(move |range| (range, r))).collect::<Vec<_>>();
+        ranges.sort_by_key(|(range, _)| *range.start());
+
+        let mut furthest: Option<(&RangeInclusive<u16>, String)> = None;
+        for &(range, region) in &ranges {
+            if (*range.start() as u32) < base || *range.end() as u32 > bank_end {
+                problems.push(problem(
+                    ProblemKind::OutsideBank,
+                    *range.start(),
+                    format!("{} is outside the bank's addresses ${:04X}-${:04X}", region.label(), base, bank_end.min(0xFFFF)),
+                ));
+            }
+            if let Some((previous, label)) = &furthest
+                && range.start() <= previous.end()
+            {
+                problems.push(problem(ProblemKind::Overlap, *range.start(), format!("{} overlaps {}", region.label(), label)));
+            }
+            if furthest.as_ref().is_none_or(|(previous, _)| range.end() > previous.end()) {
+                furthest = Some((range, region.label()));
+            }
+        }
+
+        let data = bank_data(db, rom, bank_id);
+        let instructions = instruction_lengths(db, &mapper, bank_id, data);
+        for &(range, region) in &ranges {
+            if !matches!(region, RegionInfo::Code(_)) {
+                continue;
+            }
+            let end = *range.end() as u32;
+            for (&pc, &length) in instructions.range(*range.start() as u32..=end) {
+                if pc + length - 1 > end {
+                    let mnemonic = OPCODES[data[(pc - base) as usize] as usize].as_ref().map_or("???", |i| i.mnemonic);
+                    problems.push(problem(
+                        ProblemKind::StraddlesRegionEnd,
+                        pc as u16,
+                        format!("{} runs past the end of {}", mnemonic, region.label()),
+                    ));
+                }
+            }
+        }
+
+        for (&addr, anno) in &bank.address {
+            let Some(symbol) = &anno.symbol else { continue };
+            if instructions.contains_key(&(addr as u32)) {
+                continue;
+            }
+            if let Some((&start, &length)) = instructions.range(..addr as u32).next_back()
+                && (addr as u32) < start + length
+            {
+                problems.push(problem(
+                    ProblemKind::SymbolInsideInstruction,
+                    addr,
+                    format!("{} is inside the instruction at ${:04X}", symbol, start),
+                ));
+            }
+        }
+    }
+
+    let labels = discover_all_targets(db, rom);
+    scan_references(db, rom, &mut |r| {
+        let Some(owner) = mapper.locate(r.bank, r.to).unique_bank() else { return };
+        let symbol = db.bank.get(&owner).and_then(|b| b.address.get(&r.to)).and_then(|a| a.symbol.as_deref());
+        if let Some(symbol) = symbol
+            && symbol.starts_with('@')
+            && (owner != r.bank || !labels.in_scope(owner, r.from, r.to))
+        {
+            problems.push(Problem {
+                kind: ProblemKind::LocalOutOfScope,
+                bank: Some(r.bank),
+                address: r.from,
+                message: format!("{} at ${:02X}:${:04X} is not in scope here", symbol, owner, r.to),
+            });
+        }
+    });
+
+    problems.extend(symbol_problems(db));
+    problems.sort_by_key(|p| (p.bank, p.address));
+    problems
+}
+
+// Symbol names the assembler rejects and names defined twice. Unlike the
+// region checks these need no ROM, so they are also run when a project loads.
+pub fn symbol_problems(db: &DisassemblyInfo) -> Vec<Problem> {
+    let sections = std::iter::once((None, &db.global)).chain(db.bank.iter().map(|(&id, bank)| (Some(id), &bank.address)));
+    let mut problems = Vec::new();
+    for (bank, section) in sections {
+        for (&address, anno) in section {
+            if let Some(symbol) = &anno.symbol
+                && let Err(message) = check_symbol(db.assembler, symbol)
+            {
+                problems.push(Problem { kind: ProblemKind::InvalidSymbol, bank, address, message });
+            }
+        }
+    }
+    problems.extend(duplicate_symbols(db, &MapperModel::new(db)));
+    problems.sort_by_key(|p| (p.bank, p.address));
+    problems
+}
+
+// Check a symbol name against the identifier rules of `assembler`. A leading
+// `@` (ca65, asm6) or `.` (NESASM) makes a local label and is allowed.
+pub fn check_symbol(assembler: Assembler, name: &str) -> Result<(), String> {
+    if name == ":" {
+        return match assembler {
+            Assembler::Ca65 => Ok(()),
+            _ => Err(format!("{} has no unnamed \":\" labels", assembler.label())),
+        };
+    }
+    let local = match assembler {
+        Assembler::Ca65 | Assembler::Asm6 => '@',
+        Assembler::Nesasm => '.',
+    };
+    let body = name.strip_prefix(local).unwrap_or(name);
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || (assembler == Assembler::Nesasm && c == '.');
+    if body.is_empty() {
+        return Err(format!("\"{}\" is not a name", name));
+    }
+    if let Some(c) = body.chars().find(|&c| !allowed(c)) {
+        return Err(format!("\"{}\": {} does not allow {:?} in symbol names", name, assembler.label(), c));
+    }
+    if body.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!("\"{}\" starts with a digit", name));
+    }
+    if ["A", "X", "Y"].iter().any(|r| body.eq_ignore_ascii_case(r)) {
+        return Err(format!("\"{}\" is a register name", name));
+    }
+    if OPCODES.iter().flatten().any(|i| body.eq_ignore_ascii_case(i.mnemonic)) {
+        return Err(format!("\"{}\" is an instruction mnemonic", name));
+    }
+    if assembler == Assembler::Nesasm && name.len() > 31 {
+        return Err(format!("\"{}\" is longer than NESASM's 31 characters", name));
+    }
+    Ok(())
+}
+
+// Where else `name` is defined such that giving it to `address` would make
+// a duplicate, as `$BB:$AAAA` or `$AAAA`.
+pub fn symbol_clash(db: &DisassemblyInfo, bank: Option<u8>, address: u16, name: &str) -> Option<String> {
+    let mapper = MapperModel::new(db);
+    let sections = std::iter::once((None, &db.global)).chain(db.bank.iter().map(|(&id, bank)| (Some(id), &bank.address)));
+    for (other, section) in sections {
+        for (&addr, anno) in section {
+            if anno.symbol.as_deref() == Some(name) && (other, addr) != (bank, address) && clashes(db, &mapper, name, (bank, address), (other, addr)) {
+                return Some(match other {
+                    Some(id) => format!("${:02X}:${:04X}", id, addr),
+                    None => format!("${:04X}", addr),
+                });
+            }
+        }
+    }
+    None
+}
+
+// Whether two definitions of `name`, at (bank, address) with None for the
+// global section, can clash. Names are visible in any section mapped at the
+// same time; cheap locals only within one scope of one bank, and unnamed
+// labels never clash.
+fn clashes(db: &DisassemblyInfo, mapper: &MapperModel, name: &str, a: (Option<u8>, u16), b: (Option<u8>, u16)) -> bool {
+    if name == ":" {
+        return false;
+    }
+    if labels::is_local(name) {
+        return a.0.is_some() && a.0 == b.0 && explicit_scope(db, a) == explicit_scope(db, b);
+    }
+    match (a.0, b.0) {
+        (Some(a), Some(b)) => a == b || mapper.can_coexist(a, b),
+        _ => true,
+    }
+}
+
+// The symbol a cheap local at (bank, address) belongs to: the closest named
+// label before it. Auto-labels can split this further once the ROM is known.
+fn explicit_scope(db: &DisassemblyInfo, (bank, address): (Option<u8>, u16)) -> Option<u16> {
+    let section = &db.bank.get(&bank?)?.address;
+    section.range(..=address).rev().find(|(_, a)| a.symbol.as_deref().is_some_and(|s| !labels::is_local(s))).map(|(&addr, _)| addr)
+}
+
+// Names defined at more than one place where both definitions can be seen
+// together: twice in one section, in global and a bank, or in two banks that
+// can be mapped at the same time.
+fn duplicate_symbols(db: &DisassemblyInfo, mapper: &MapperModel) -> Vec<Problem> {
+    let mut definitions: BTreeMap<&str, Vec<(Option<u8>, u16)>> = BTreeMap::new();
+    for (&addr, anno) in &db.global {
+        if let Some(symbol) = &anno.symbol {
+            definitions.entry(symbol).or_default().push((None, addr));
+        }
+    }
+    for (&id, bank) in &db.bank {
+        for (&addr, anno) in &bank.address {
+            if let Some(symbol) = &anno.symbol {
+                definitions.entry(symbol).or_default().push((Some(id), addr));
+            }
+        }
+    }
+
+    let mut problems = Vec::new();
+    for (symbol, places) in definitions {
+        for (i, &(bank, address)) in places.iter().enumerate() {
+            let clash = places[..i].iter().find(|&&other| clashes(db, mapper, symbol, (bank, address), other));
+            if let Some(&(other_bank, other_address)) = clash {
+                let other = match other_bank {
+                    Some(id) => format!("${:02X}:${:04X}", id, other_address),
+                    None => format!("${:04X}", other_address),
+                };
+                problems.push(Problem {
+                    kind: ProblemKind::DuplicateSymbol,
+                    bank,
+                    address,
+                    message: format!("{} is also defined at {}", symbol, other),
+                });
+            }
+        }
+    }
+    problems
+}
@@ -0,0 +1,291 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::labels::{self, Labels};
+use super::{assembler, bank_base_address, text, bank_data, disassemble_bank, discover_all_targets, prg_bank_count, struct_constants};
+use crate::mapper::MapperModel;
+use crate::models::{DisassemblyInfo, DisassemblyLine, StructDef};
+use crate::rom::RomImage;
+
+// A place where re-assembling the disassembly does not reproduce the ROM.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub bank: u8,
+    pub address: u16,
+    pub message: String,
+    // Bytes in the ROM and the bytes the line assembles to.
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+// Names visible from one bank, highest priority first, following the same
+// lookup order as symbol resolution in the disassembler. `None` marks a name
+// defined at more than one address.
+struct SymbolScope {
+    layers: Vec<HashMap<String, Option<u16>>>,
+    // Local labels of the bank's lines by the scope they are in, and the
+    // addresses of the labels that open a scope, as ca65 sees them.
+    locals: HashMap<(Option<u16>, String), u16>,
+    scopes: BTreeSet<u16>,
+    unnamed: BTreeSet<u16>,
+}
+
+impl SymbolScope {
+    fn new(db: &DisassemblyInfo, bank_id: u8, lines: &[DisassemblyLine], labels: &Labels) -> Self {
+        let mapper = MapperModel::new(db);
+        let visible = |other_id: u8| mapper.can_coexist(bank_id, other_id);
+
+        let mut lines_layer = HashMap::new();
+        let mut locals = HashMap::new();
+        let mut scopes = BTreeSet::new();
+        let mut unnamed = BTreeSet::new();
+        for line in lines {
+            let Some(ref sym) = line.symbol else { continue };
+            if sym == ":" {
+                unnamed.insert(line.address);
+            } else if labels::is_local(sym) {
+                locals.insert((labels.scope_of(bank_id, line.address), sym.clone()), line.address);
+            } else {
+                define(&mut lines_layer, sym, line.address);
+                if labels.opens_scope(bank_id, line.address) {
+                    scopes.insert(line.address);
+                }
+            }
+        }
+
+        let mut local = HashMap::new();
+        let mut others = HashMap::new();
+        for (&id, bank) in &db.bank {
+            let layer = if id == bank_id {
+                &mut local
+            } else if visible(id) {
+                &mut others
+            } else {
+                continue;
+            };
+            for (&addr, anno) in &bank.address {
+                if let Some(ref sym) = anno.symbol
+                    && !labels::is_local(sym)
+                {
+                    define(layer, sym, addr);
+                }
+            }
+        }
+
+        let mut globals = HashMap::new();
+        for (&addr, anno) in &db.global {
+            if let Some(ref sym) = anno.symbol {
+                define(&mut globals, sym, addr);
+            }
+        }
+
+        let mut local_auto = HashMap::new();
+        let mut other_auto = HashMap::new();
+        for (&id, targets) in &labels.targets {
+            let layer = if id == bank_id {
+                &mut local_auto
+            } else if visible(id) {
+                &mut other_auto
+            } else {
+                continue;
+            };
+            for &addr in targets {
+                define(layer, &labels.auto_name(id, addr), addr);
+            }
+        }
+
+        // Named values of Struct enum fields, exported as constants.
+        let mut constants = HashMap::new();
+        for (name, value) in struct_constants(db) {
+            define(&mut constants, &name, value as u16);
+        }
+
+        SymbolScope { layers: vec![lines_layer, local, others, globals, local_auto, other_auto, constants], locals, scopes, unnamed }
+    }
+
+    // Look up a name used by the line at `from`.
+    fn lookup(&self, name: &str, from: u16) -> Result<u16, String> {
+        if let Some(steps) = name.strip_prefix(':') {
+            let found = match steps.chars().next() {
+                Some('+') if steps.chars().all(|c| c == '+') => from.checked_add(1).and_then(|next| self.unnamed.range(next..).nth(steps.len() - 1)),
+                Some('-') if steps.chars().all(|c| c == '-') => self.unnamed.range(..=from).rev().nth(steps.len() - 1),
+                _ => return Err(format!("bad unnamed label reference {}", name)),
+            };
+            return found.copied().ok_or_else(|| format!("no unnamed label for {}", name));
+        }
+        if labels::is_local(name) {
+            let scope = self.scopes.range(..=from).next_back().copied();
+            return self.locals.get(&(scope, name.to_string())).copied().ok_or_else(|| format!("local label {} is not defined in this scope", name));
+        }
+        for layer in &self.layers {
+            match layer.get(name) {
+                Some(Some(addr)) => return Ok(*addr),
+                Some(None) => return Err(format!("symbol {} is defined at more than one address", name)),
+                None => {}
+            }
+        }
+        Err(format!("undefined symbol {}", name))
+    }
+}
+
+fn define(layer: &mut HashMap<String, Option<u16>>, name: &str, addr: u16) {
+    match layer.entry(name.to_string()) {
+        Entry::Vacant(e) => {
+            e.insert(Some(addr));
+        }
+        Entry::Occupied(mut e) => {
+            if *e.get() != Some(addr) {
+                e.insert(None);
+            }
+        }
+    }
+}
+
+// Re-assemble every annotated bank and compare the result with the ROM.
+pub fn verify_project(db: &DisassemblyInfo, rom: &RomImage) -> Vec<Divergence> {
+    let labels = discover_all_targets(db, rom);
+    (0..prg_bank_count(db, rom))
+        .filter(|id| db.bank.contains_key(id))
+        .flat_map(|id| verify_bank(db, id, rom, &labels))
+        .collect()
+}
+
+pub fn verify_bank(
+    db: &DisassemblyInfo,
+    bank_id: u8,
+    rom: &RomImage,
+    labels: &Labels,
+) -> Vec<Divergence> {
+    let data = bank_data(db, rom, bank_id);
+    let lines = disassemble_bank(db, bank_id, data, labels);
+    let scope = SymbolScope::new(db, bank_id, &lines, labels);
+    let charmap = db.text_table.as_ref().map(text::charmap).unwrap_or_default();
+    let base = bank_base_address(db, bank_id) as u32;
+
+    let mut divergences = Vec::new();
+    let mut report = |address: u32, message: String, expected: &[u8], actual: &[u8]| {
+        divergences.push(Divergence {
+            bank: bank_id,
+            address: address as u16,
+            message,
+            expected: expected.to_vec(),
+            actual: actual.to_vec(),
+        });
+    };
+
+    let mut pc = base;
+    for line in &lines {
+        let address = line.address as u32;
+        if address < pc {
+            report(address, format!("line starts {} byte(s) inside the previous line; a region boundary splits an instruction", pc - address), &[], &[]);
+        } else if address > pc {
+            report(pc, format!("{} byte(s) before ${:04X} are not covered by any line", address - pc, address), &[], &[]);
+        }
+        pc = address;
+
+        let lookup = |name: &str| scope.lookup(name, line.address);
+        let assembled = assemble_line(line, &lookup, &charmap, &db.structs);
+        let len = match assembled {
+            Ok(ref bytes) => bytes.len(),
+            Err(_) => line.bytes.split_whitespace().count(),
+        };
+        let offset = (pc - base) as usize;
+        let expected = &data[offset.min(data.len())..(offset + len).min(data.len())];
+        match assembled {
+            Ok(ref actual) if actual.as_slice() == expected => {}
+            Ok(ref actual) => report(pc, "assembles to different bytes".to_string(), expected, actual),
+            Err(e) => report(pc, e, expected, &[]),
+        }
+        pc += len as u32;
+    }
+
+    let end = base + data.len() as u32;
+    if pc > end {
+        report(end, format!("disassembly runs {} byte(s) past the end of the bank", pc - end), &[], &[]);
+    } else if pc < end {
+        report(pc, format!("{} byte(s) at the end of the bank are not covered by any line", end - pc), &[], &[]);
+    }
+    divergences
+}
+
+fn assemble_line(
+    line: &DisassemblyLine,
+    lookup: &dyn Fn(&str) -> Result<u16, String>,
+    charmap: &BTreeMap<char, u8>,
+    structs: &BTreeMap<String, StructDef>,
+) -> Result<Vec<u8>, String> {
+    match line.opcode.as_str() {
+        "???" => Err(format!("illegal opcode ${} in a code region", line.bytes)),
+        name if structs.contains_key(name) => assembler::assemble_record(&structs[name], &line.operand_main, lookup),
+        directive if directive.starts_with('.') => {
+            let operand = format!("{}{}{}", line.operand_prefix, line.operand_main, line.operand_suffix);
+            assembler::assemble_data(directive, &operand, lookup, charmap)
+        }
+        mnemonic => {
+            let rom_bytes = line.bytes.split_whitespace().filter_map(|b| u8::from_str_radix(b, 16).ok()).collect::<Vec<_>>();
+            let size = if assembler::needs_absolute_prefix(&rom_bytes) { "a:" } else { "" };
+            let operand = format!("{}{}{}{}", line.operand_prefix, size, line.operand_main, line.operand_suffix);
+            assembler::assemble_instruction(mnemonic, &operand, line.address, lookup)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unnamed_references_at_the_end_of_the_address_space() {
+        let scope = SymbolScope { layers: Vec::new(), locals: HashMap::new(), scopes: BTreeSet::new(), unnamed: BTreeSet::from([0xFFF0, 0xFFFF]) };
+        assert!(scope.lookup(":+", 0xFFFF).is_err());
+        assert_eq!(scope.lookup(":-", 0xFFFF), Ok(0xFFFF));
+        assert_eq!(scope.lookup(":--", 0xFFFF), Ok(0xFFF0));
+        assert_eq!(scope.lookup(":+", 0xFFF0), Ok(0xFFFF));
+    }
+
+    // A global named at a line inside a routine gets no label of its own in
+    // the bank, so the routine's local labels stay in scope past it.
+    #[test]
+    fn globals_do_not_open_a_scope() {
+        let db: DisassemblyInfo = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {"49155": {"symbol": "Mid"}},
+            "bank": {"0": {
+                "is_fixed": true,
+                "region": [{"Code": {"start": 0xC000, "end": 0xC006}}],
+                "address": {
+                    "49152": {"symbol": "Routine"},
+                    "49154": {"symbol": "@loop"},
+                    "49155": {"symbol": "Mid"},
+                },
+                "mapped_at": 0xC000,
+            }},
+            "mapper_window_size": 16,
+            "mapper": 0,
+        }))
+        .unwrap();
+        let mut data = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend([
+            0xA2, 0x05, // Routine: LDX #$05
+            0xCA, // @loop: DEX
+            0xEA, // Mid: NOP
+            0xD0, 0xFC, // BNE @loop
+            0x60, // RTS
+        ]);
+        data.resize(16 + 0x4000, 0);
+        let rom = RomImage::parse(data).unwrap();
+
+        let labels = discover_all_targets(&db, &rom);
+        assert!(labels.opens_scope(0, 0xC000));
+        assert!(!labels.opens_scope(0, 0xC003));
+        assert!(labels.in_scope(0, 0xC004, 0xC002));
+        assert!(verify_project(&db, &rom).is_empty());
+
+        let export = crate::export::ca65::export_project(&db, &rom).unwrap();
+        let bank = export.files.iter().find(|f| f.name == "prg00.s").unwrap();
+        let text = String::from_utf8_lossy(&bank.data);
+        assert!(text.contains("BNE @loop"), "{}", text);
+        assert!(!text.contains("Mid:"), "{}", text);
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use super::{scan_references, AddressingMode};
+use crate::mapper::MapperModel;
+use crate::models::DisassemblyInfo;
+use crate::rom::RomImage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RefKind {
+    Call,
+    Jump,
+    Branch,
+    Read,
+    Write,
+    ReadModifyWrite,
+    // A data word, split table entry, jump table entry or struct field.
+    Pointer,
+}
+
+impl RefKind {
+    // Classify an instruction's memory operand. For indirect modes the
+    // operand is the pointer, which is only read whatever the instruction
+    // does with the address it holds.
+    pub fn of(mnemonic: &str, mode: AddressingMode) -> Self {
+        use AddressingMode::*;
+        match (mnemonic, mode) {
+            (_, Relative) => RefKind::Branch,
+            (_, Indirect | IndexedIndirect | IndirectIndexed) => RefKind::Read,
+            ("JSR", _) => RefKind::Call,
+            ("JMP", Absolute) => RefKind::Jump,
+            ("STA" | "STX" | "STY", _) => RefKind::Write,
+            ("ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC", _) => RefKind::ReadModifyWrite,
+            _ => RefKind::Read,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RefKind::Call => "call",
+            RefKind::Jump => "jump",
+            RefKind::Branch => "branch",
+            RefKind::Read => "read",
+            RefKind::Write => "write",
+            RefKind::ReadModifyWrite => "read-modify-write",
+            RefKind::Pointer => "pointer",
+        }
+    }
+}
+
+// One place in the ROM that refers to an address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    // Bank and address of the referencing line.
+    pub bank: u8,
+    pub from: u16,
+    pub to: u16,
+    pub kind: RefKind,
+    // Instruction or directive making the reference.
+    pub mnemonic: &'static str,
+    // Addressing mode for instructions; None for data.
+    pub mode: Option<AddressingMode>,
+}
+
+impl Reference {
+    pub fn pointer(bank: u8, from: u16, to: u16, mnemonic: &'static str) -> Self {
+        Reference { bank, from, to, kind: RefKind::Pointer, mnemonic, mode: None }
+    }
+}
+
+// References by target address, each with the banks the mapper can place
+// at the target as seen from the referencing bank (empty for RAM and I/O).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct XRefIndex {
+    by_target: BTreeMap<u16, Vec<(Reference, Vec<u8>)>>,
+}
+
+impl XRefIndex {
+    pub fn build(db: &DisassemblyInfo, rom: &RomImage) -> Self {
+        let mapper = MapperModel::new(db);
+        let mut index = XRefIndex::default();
+        scan_references(db, rom, &mut |r| {
+            let banks = mapper.locate(r.bank, r.to).banks().to_vec();
+            index.by_target.entry(r.to).or_default().push((r, banks));
+        });
+        for refs in index.by_target.values_mut() {
+            refs.sort_by_key(|(r, _)| (r.bank, r.from));
+        }
+        index
+    }
+
+    // References to `address` in `bank`, or with `None` to a global
+    // (non-ROM) address.
+    pub fn references_to(&self, bank: Option<u8>, address: u16) -> Vec<&Reference> {
+        let Some(refs) = self.by_target.get(&address) else { return Vec::new() };
+        refs.iter()
+            .filter(|(_, banks)| match bank {
+                Some(id) => banks.contains(&id),
+                None => banks.is_empty(),
+            })
+            .map(|(r, _)| r)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds() {
+        assert_eq!(RefKind::of("BNE", AddressingMode::Relative), RefKind::Branch);
+        assert_eq!(RefKind::of("JSR", AddressingMode::Absolute), RefKind::Call);
+        assert_eq!(RefKind::of("JMP", AddressingMode::Absolute), RefKind::Jump);
+        assert_eq!(RefKind::of("LDA", AddressingMode::AbsoluteX), RefKind::Read);
+        assert_eq!(RefKind::of("STA", AddressingMode::ZeroPage), RefKind::Write);
+        assert_eq!(RefKind::of("STX", AddressingMode::ZeroPageY), RefKind::Write);
+        assert_eq!(RefKind::of("INC", AddressingMode::Absolute), RefKind::ReadModifyWrite);
+        assert_eq!(RefKind::of("ROR", AddressingMode::ZeroPageX), RefKind::ReadModifyWrite);
+    }
+
+    #[test]
+    fn indirect_operands_are_pointer_reads() {
+        assert_eq!(RefKind::of("STA", AddressingMode::IndirectIndexed), RefKind::Read);
+        assert_eq!(RefKind::of("STA", AddressingMode::IndexedIndirect), RefKind::Read);
+        assert_eq!(RefKind::of("LDA", AddressingMode::IndirectIndexed), RefKind::Read);
+        assert_eq!(RefKind::of("JMP", AddressingMode::Indirect), RefKind::Read);
+    }
+}
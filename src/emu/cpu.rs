@@ -0,0 +1,465 @@
+use crate::disasm::{AddressingMode, OPCODES};
+
+// Status flags.
+pub const FLAG_C: u8 = 0x01;
+pub const FLAG_Z: u8 = 0x02;
+pub const FLAG_I: u8 = 0x04;
+pub const FLAG_D: u8 = 0x08;
+pub const FLAG_B: u8 = 0x10;
+pub const FLAG_U: u8 = 0x20;
+pub const FLAG_V: u8 = 0x40;
+pub const FLAG_N: u8 = 0x80;
+
+// What the CPU reads from and writes to.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+}
+
+impl Default for Registers {
+    // The state after reset, with the stack pointer where RESET leaves it.
+    fn default() -> Self {
+        Registers { a: 0, x: 0, y: 0, s: 0xFD, p: FLAG_I | FLAG_U, pc: 0 }
+    }
+}
+
+impl Registers {
+    // The status flags as `NV-BDIZC`, with clear flags shown as `.`.
+    pub fn flags(&self) -> String {
+        "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if self.p & (0x80 >> i) != 0 { c } else { '.' })
+            .collect()
+    }
+}
+
+// The 2A03's 6502 core: official opcodes only, with no decimal mode.
+#[derive(Debug, Clone, Default)]
+pub struct Cpu {
+    pub regs: Registers,
+    pub cycles: u64,
+}
+
+impl Cpu {
+    pub fn new(regs: Registers) -> Self {
+        Cpu { regs, cycles: 0 }
+    }
+
+    // Execute one instruction and return the cycles it took. An unofficial
+    // opcode is an error and leaves the CPU unchanged.
+    pub fn step(&mut self, bus: &mut dyn Bus) -> Result<u8, String> {
+        let pc = self.regs.pc;
+        let opcode = bus.read(pc);
+        let Some(instr) = &OPCODES[opcode as usize] else {
+            return Err(format!("illegal opcode ${:02X} at ${:04X}", opcode, pc));
+        };
+        let length = instr.mode.operand_length();
+        let lo = if length > 0 { bus.read(pc.wrapping_add(1)) } else { 0 };
+        let hi = if length > 1 { bus.read(pc.wrapping_add(2)) } else { 0 };
+        let word = u16::from_le_bytes([lo, hi]);
+        self.regs.pc = pc.wrapping_add(1 + length);
+
+        let indexed = |base: u16, index: u8| {
+            let address = base.wrapping_add(index as u16);
+            (address, address & 0xFF00 != base & 0xFF00)
+        };
+        let (address, crossed) = match instr.mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => (0, false),
+            AddressingMode::Immediate => (pc.wrapping_add(1), false),
+            AddressingMode::ZeroPage => (lo as u16, false),
+            AddressingMode::ZeroPageX => (lo.wrapping_add(self.regs.x) as u16, false),
+            AddressingMode::ZeroPageY => (lo.wrapping_add(self.regs.y) as u16, false),
+            AddressingMode::Relative => {
+                let target = self.regs.pc.wrapping_add(lo as i8 as u16);
+                (target, target & 0xFF00 != self.regs.pc & 0xFF00)
+            }
+            AddressingMode::Absolute => (word, false),
+            AddressingMode::AbsoluteX => indexed(word, self.regs.x),
+            AddressingMode::AbsoluteY => indexed(word, self.regs.y),
+            // JMP ($xxFF) takes the high byte from $xx00.
+            AddressingMode::Indirect => {
+                let hi_address = (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF);
+                (u16::from_le_bytes([bus.read(word), bus.read(hi_address)]), false)
+            }
+            AddressingMode::IndexedIndirect => {
+                let pointer = lo.wrapping_add(self.regs.x);
+                (u16::from_le_bytes([bus.read(pointer as u16), bus.read(pointer.wrapping_add(1) as u16)]), false)
+            }
+            AddressingMode::IndirectIndexed => {
+                let base = u16::from_le_bytes([bus.read(lo as u16), bus.read(lo.wrapping_add(1) as u16)]);
+                indexed(base, self.regs.y)
+            }
+        };
+
+        let (mut cycles, page_penalty) = base_cycles(instr.mnemonic, instr.mode);
+        if crossed && page_penalty {
+            cycles += 1;
+        }
+        let accumulator = instr.mode == AddressingMode::Accumulator;
+
+        match instr.mnemonic {
+            "LDA" => self.regs.a = self.set_nz(bus.read(address)),
+            "LDX" => self.regs.x = self.set_nz(bus.read(address)),
+            "LDY" => self.regs.y = self.set_nz(bus.read(address)),
+            "STA" => bus.write(address, self.regs.a),
+            "STX" => bus.write(address, self.regs.x),
+            "STY" => bus.write(address, self.regs.y),
+            "TAX" => self.regs.x = self.set_nz(self.regs.a),
+            "TAY" => self.regs.y = self.set_nz(self.regs.a),
+            "TXA" => self.regs.a = self.set_nz(self.regs.x),
+            "TYA" => self.regs.a = self.set_nz(self.regs.y),
+            "TSX" => self.regs.x = self.set_nz(self.regs.s),
+            "TXS" => self.regs.s = self.regs.x,
+            "PHA" => self.push(bus, self.regs.a),
+            "PHP" => self.push(bus, self.regs.p | FLAG_B | FLAG_U),
+            "PLA" => {
+                let value = self.pull(bus);
+                self.regs.a = self.set_nz(value);
+            }
+            "PLP" => {
+                let value = self.pull(bus);
+                self.regs.p = (value & !FLAG_B) | FLAG_U;
+            }
+            "AND" => self.regs.a = self.set_nz(self.regs.a & bus.read(address)),
+            "ORA" => self.regs.a = self.set_nz(self.regs.a | bus.read(address)),
+            "EOR" => self.regs.a = self.set_nz(self.regs.a ^ bus.read(address)),
+            "ADC" => {
+                let value = bus.read(address);
+                self.add(value);
+            }
+            "SBC" => {
+                let value = bus.read(address);
+                self.add(!value);
+            }
+            "CMP" => self.compare(self.regs.a, bus.read(address)),
+            "CPX" => self.compare(self.regs.x, bus.read(address)),
+            "CPY" => self.compare(self.regs.y, bus.read(address)),
+            "BIT" => {
+                let value = bus.read(address);
+                self.set_flag(FLAG_Z, self.regs.a & value == 0);
+                self.regs.p = (self.regs.p & !(FLAG_N | FLAG_V)) | (value & (FLAG_N | FLAG_V));
+            }
+            "INC" | "DEC" | "ASL" | "LSR" | "ROL" | "ROR" => {
+                let value = if accumulator { self.regs.a } else { bus.read(address) };
+                let carry = self.regs.p & FLAG_C;
+                let result = match instr.mnemonic {
+                    "INC" => value.wrapping_add(1),
+                    "DEC" => value.wrapping_sub(1),
+                    "ASL" => {
+                        self.set_flag(FLAG_C, value & 0x80 != 0);
+                        value << 1
+                    }
+                    "LSR" => {
+                        self.set_flag(FLAG_C, value & 0x01 != 0);
+                        value >> 1
+                    }
+                    "ROL" => {
+                        self.set_flag(FLAG_C, value & 0x80 != 0);
+                        (value << 1) | carry
+                    }
+                    _ => {
+                        self.set_flag(FLAG_C, value & 0x01 != 0);
+                        (value >> 1) | (carry << 7)
+                    }
+                };
+                self.set_nz(result);
+                if accumulator {
+                    self.regs.a = result;
+                } else {
+                    bus.write(address, result);
+                }
+            }
+            "INX" => self.regs.x = self.set_nz(self.regs.x.wrapping_add(1)),
+            "INY" => self.regs.y = self.set_nz(self.regs.y.wrapping_add(1)),
+            "DEX" => self.regs.x = self.set_nz(self.regs.x.wrapping_sub(1)),
+            "DEY" => self.regs.y = self.set_nz(self.regs.y.wrapping_sub(1)),
+            "CLC" => self.set_flag(FLAG_C, false),
+            "SEC" => self.set_flag(FLAG_C, true),
+            "CLI" => self.set_flag(FLAG_I, false),
+            "SEI" => self.set_flag(FLAG_I, true),
+            "CLD" => self.set_flag(FLAG_D, false),
+            "SED" => self.set_flag(FLAG_D, true),
+            "CLV" => self.set_flag(FLAG_V, false),
+            "BCC" | "BCS" | "BEQ" | "BNE" | "BMI" | "BPL" | "BVC" | "BVS" => {
+                let (flag, set) = match instr.mnemonic {
+                    "BCC" => (FLAG_C, false),
+                    "BCS" => (FLAG_C, true),
+                    "BNE" => (FLAG_Z, false),
+                    "BEQ" => (FLAG_Z, true),
+                    "BPL" => (FLAG_N, false),
+                    "BMI" => (FLAG_N, true),
+                    "BVC" => (FLAG_V, false),
+                    _ => (FLAG_V, true),
+                };
+                if (self.regs.p & flag != 0) == set {
+                    cycles += if crossed { 2 } else { 1 };
+                    self.regs.pc = address;
+                }
+            }
+            "JMP" => self.regs.pc = address,
+            "JSR" => {
+                let ret = self.regs.pc.wrapping_sub(1);
+                self.push(bus, (ret >> 8) as u8);
+                self.push(bus, ret as u8);
+                self.regs.pc = address;
+            }
+            "RTS" => {
+                let lo = self.pull(bus);
+                let hi = self.pull(bus);
+                self.regs.pc = u16::from_le_bytes([lo, hi]).wrapping_add(1);
+            }
+            "RTI" => {
+                let p = self.pull(bus);
+                self.regs.p = (p & !FLAG_B) | FLAG_U;
+                let lo = self.pull(bus);
+                let hi = self.pull(bus);
+                self.regs.pc = u16::from_le_bytes([lo, hi]);
+            }
+            "BRK" => {
+                let ret = pc.wrapping_add(2);
+                self.push(bus, (ret >> 8) as u8);
+                self.push(bus, ret as u8);
+                self.push(bus, self.regs.p | FLAG_B | FLAG_U);
+                self.set_flag(FLAG_I, true);
+                self.regs.pc = u16::from_le_bytes([bus.read(0xFFFE), bus.read(0xFFFF)]);
+            }
+            _ => {}
+        }
+        self.cycles += cycles as u64;
+        Ok(cycles)
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.regs.p |= flag;
+        } else {
+            self.regs.p &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) -> u8 {
+        self.set_flag(FLAG_Z, value == 0);
+        self.set_flag(FLAG_N, value & 0x80 != 0);
+        value
+    }
+
+    // ADC; SBC adds the complement. The D flag is ignored, as on the 2A03.
+    fn add(&mut self, value: u8) {
+        let a = self.regs.a;
+        let sum = a as u16 + value as u16 + (self.regs.p & FLAG_C) as u16;
+        let result = sum as u8;
+        self.set_flag(FLAG_C, sum > 0xFF);
+        self.set_flag(FLAG_V, (a ^ result) & (value ^ result) & 0x80 != 0);
+        self.regs.a = self.set_nz(result);
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(FLAG_C, register >= value);
+        self.set_nz(register.wrapping_sub(value));
+    }
+
+    fn push(&mut self, bus: &mut dyn Bus, value: u8) {
+        bus.write(0x0100 | self.regs.s as u16, value);
+        self.regs.s = self.regs.s.wrapping_sub(1);
+    }
+
+    fn pull(&mut self, bus: &mut dyn Bus) -> u8 {
+        self.regs.s = self.regs.s.wrapping_add(1);
+        bus.read(0x0100 | self.regs.s as u16)
+    }
+}
+
+// Cycles an instruction takes before branch and page-crossing penalties, and
+// whether crossing a page while indexing adds one. Stores and
+// read-modify-write instructions always take the extra cycle.
+fn base_cycles(mnemonic: &str, mode: AddressingMode) -> (u8, bool) {
+    use AddressingMode::*;
+    match mnemonic {
+        "BRK" => (7, false),
+        "JSR" | "RTS" | "RTI" => (6, false),
+        "JMP" => (if mode == Indirect { 5 } else { 3 }, false),
+        "PHA" | "PHP" => (3, false),
+        "PLA" | "PLP" => (4, false),
+        "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" => match mode {
+            Accumulator => (2, false),
+            ZeroPage => (5, false),
+            ZeroPageX | Absolute => (6, false),
+            _ => (7, false),
+        },
+        "STA" | "STX" | "STY" => match mode {
+            ZeroPage => (3, false),
+            ZeroPageX | ZeroPageY | Absolute => (4, false),
+            AbsoluteX | AbsoluteY => (5, false),
+            _ => (6, false),
+        },
+        _ => match mode {
+            ZeroPage => (3, false),
+            ZeroPageX | ZeroPageY | Absolute => (4, false),
+            AbsoluteX | AbsoluteY => (4, true),
+            IndexedIndirect => (6, false),
+            IndirectIndexed => (5, true),
+            _ => (2, false),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 64K of RAM.
+    struct Ram(Vec<u8>);
+
+    impl Bus for Ram {
+        fn read(&mut self, address: u16) -> u8 {
+            self.0[address as usize]
+        }
+        fn write(&mut self, address: u16, value: u8) {
+            self.0[address as usize] = value;
+        }
+    }
+
+    // A CPU at $0200 with `code` loaded there.
+    fn setup(code: &[u8]) -> (Cpu, Ram) {
+        let mut ram = Ram(vec![0; 0x10000]);
+        ram.0[0x0200..0x0200 + code.len()].copy_from_slice(code);
+        (Cpu::new(Registers { pc: 0x0200, ..Default::default() }), ram)
+    }
+
+    fn flag(cpu: &Cpu, flag: u8) -> bool {
+        cpu.regs.p & flag != 0
+    }
+
+    #[test]
+    fn adc_overflow_and_carry() {
+        // (A, operand, carry in) -> (result, C, V)
+        for (a, value, carry, result, c, v) in [
+            (0x50, 0x10, false, 0x60, false, false),
+            (0x50, 0x50, false, 0xA0, false, true),
+            (0xD0, 0x90, false, 0x60, true, true),
+            (0xFF, 0x00, true, 0x00, true, false),
+            (0x7F, 0x00, true, 0x80, false, true),
+        ] {
+            let (mut cpu, mut ram) = setup(&[0x69, value]);
+            cpu.regs.a = a;
+            cpu.set_flag(FLAG_C, carry);
+            assert_eq!(cpu.step(&mut ram), Ok(2));
+            assert_eq!(cpu.regs.a, result, "{:02X} + {:02X}", a, value);
+            assert_eq!(flag(&cpu, FLAG_C), c, "carry of {:02X} + {:02X}", a, value);
+            assert_eq!(flag(&cpu, FLAG_V), v, "overflow of {:02X} + {:02X}", a, value);
+            assert_eq!(flag(&cpu, FLAG_Z), result == 0);
+            assert_eq!(flag(&cpu, FLAG_N), result & 0x80 != 0);
+        }
+    }
+
+    #[test]
+    fn sbc_overflow_and_borrow() {
+        // (A, operand, carry in) -> (result, C, V); a clear carry borrows.
+        for (a, value, carry, result, c, v) in [
+            (0x50, 0x10, true, 0x40, true, false),
+            (0x50, 0xB0, true, 0xA0, false, true),
+            (0xD0, 0x70, true, 0x60, true, true),
+            (0x00, 0x01, true, 0xFF, false, false),
+            (0x10, 0x10, false, 0xFF, false, false),
+        ] {
+            let (mut cpu, mut ram) = setup(&[0xE9, value]);
+            cpu.regs.a = a;
+            cpu.set_flag(FLAG_C, carry);
+            cpu.step(&mut ram).unwrap();
+            assert_eq!(cpu.regs.a, result, "{:02X} - {:02X}", a, value);
+            assert_eq!(flag(&cpu, FLAG_C), c, "carry of {:02X} - {:02X}", a, value);
+            assert_eq!(flag(&cpu, FLAG_V), v, "overflow of {:02X} - {:02X}", a, value);
+        }
+    }
+
+    #[test]
+    fn bit_copies_n_and_v() {
+        let (mut cpu, mut ram) = setup(&[0x24, 0x10, 0x24, 0x11]);
+        ram.0[0x10] = 0xC0;
+        ram.0[0x11] = 0x01;
+        cpu.regs.a = 0x01;
+        assert_eq!(cpu.step(&mut ram), Ok(3));
+        assert!(flag(&cpu, FLAG_N) && flag(&cpu, FLAG_V) && flag(&cpu, FLAG_Z));
+        cpu.step(&mut ram).unwrap();
+        assert!(!flag(&cpu, FLAG_N) && !flag(&cpu, FLAG_V) && !flag(&cpu, FLAG_Z));
+        assert_eq!(cpu.regs.a, 0x01);
+    }
+
+    #[test]
+    fn jmp_indirect_wraps_in_page() {
+        let (mut cpu, mut ram) = setup(&[0x6C, 0xFF, 0x03]);
+        ram.0[0x03FF] = 0x34;
+        ram.0[0x0300] = 0x12;
+        ram.0[0x0400] = 0x56;
+        assert_eq!(cpu.step(&mut ram), Ok(5));
+        assert_eq!(cpu.regs.pc, 0x1234);
+    }
+
+    #[test]
+    fn branch_cycles() {
+        // Not taken.
+        let (mut cpu, mut ram) = setup(&[0xD0, 0x10]);
+        cpu.set_flag(FLAG_Z, true);
+        assert_eq!(cpu.step(&mut ram), Ok(2));
+        assert_eq!(cpu.regs.pc, 0x0202);
+
+        // Taken, same page.
+        let (mut cpu, mut ram) = setup(&[0xD0, 0x10]);
+        assert_eq!(cpu.step(&mut ram), Ok(3));
+        assert_eq!(cpu.regs.pc, 0x0212);
+
+        // Taken backwards into the previous page.
+        let (mut cpu, mut ram) = setup(&[0xD0, 0xFC]);
+        assert_eq!(cpu.step(&mut ram), Ok(4));
+        assert_eq!(cpu.regs.pc, 0x01FE);
+
+        // Taken forwards across a page, counted from the next instruction.
+        let mut ram = Ram(vec![0; 0x10000]);
+        ram.0[0x02F0..0x02F2].copy_from_slice(&[0xF0, 0x10]);
+        let mut cpu = Cpu::new(Registers { pc: 0x02F0, p: FLAG_Z, ..Default::default() });
+        assert_eq!(cpu.step(&mut ram), Ok(4));
+        assert_eq!(cpu.regs.pc, 0x0302);
+    }
+
+    #[test]
+    fn indexed_page_cross_cycles() {
+        let (mut cpu, mut ram) = setup(&[0xBD, 0x80, 0x03, 0xBD, 0xF0, 0x03, 0x9D, 0x80, 0x03]);
+        cpu.regs.x = 0x20;
+        assert_eq!(cpu.step(&mut ram), Ok(4));
+        assert_eq!(cpu.step(&mut ram), Ok(5));
+        assert_eq!(cpu.step(&mut ram), Ok(5));
+        assert_eq!(cpu.cycles, 14);
+    }
+
+    #[test]
+    fn jsr_and_rts() {
+        let (mut cpu, mut ram) = setup(&[0x20, 0x00, 0x03]);
+        ram.0[0x0300] = 0x60;
+        assert_eq!(cpu.step(&mut ram), Ok(6));
+        assert_eq!(cpu.regs.pc, 0x0300);
+        assert_eq!(cpu.regs.s, 0xFB);
+        assert_eq!(&ram.0[0x01FC..=0x01FD], &[0x02, 0x02]);
+        assert_eq!(cpu.step(&mut ram), Ok(6));
+        assert_eq!(cpu.regs.pc, 0x0203);
+        assert_eq!(cpu.regs.s, 0xFD);
+    }
+
+    #[test]
+    fn illegal_opcode() {
+        let (mut cpu, mut ram) = setup(&[0x02]);
+        assert!(cpu.step(&mut ram).is_err());
+        assert_eq!(cpu.regs.pc, 0x0200);
+        assert_eq!(cpu.cycles, 0);
+    }
+}
@@ -0,0 +1,426 @@
+pub mod cpu;
+
+use cpu::{Bus, Cpu, Registers};
+
+use crate::mapper::{Location, MapperModel};
+use crate::models::DisassemblyInfo;
+use crate::rom::RomImage;
+
+// Instructions a run executes before giving up, unless told otherwise.
+pub const DEFAULT_MAX_STEPS: usize = 100_000;
+
+// Where and how to start running a routine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSetup {
+    // The bank the routine is in, mapped where the database places it.
+    pub bank: u8,
+    pub start: u16,
+    // Initial registers; `pc` is replaced by `start`.
+    pub registers: Registers,
+    // RAM (or PRG-RAM) bytes to set before starting.
+    pub memory: Vec<(u16, u8)>,
+    // Further banks to map at their database addresses, for switchable
+    // windows the routine does not switch itself.
+    pub banks: Vec<u8>,
+    pub max_steps: usize,
+}
+
+// One executed instruction: where it was and the registers before it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    // The PRG bank the instruction was read from, None for RAM.
+    pub bank: Option<u8>,
+    pub registers: Registers,
+    // CPU cycles elapsed before the instruction.
+    pub cycle: u64,
+}
+
+// A byte written by the instruction at `steps[step]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryWrite {
+    pub step: usize,
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    // An RTS or RTI popped the return address the routine was called with.
+    Returned,
+    Brk,
+    IllegalOpcode(u8),
+    // A jump or branch to itself, such as a loop waiting for NMI.
+    Stuck,
+    StepLimit,
+}
+
+impl StopReason {
+    pub fn label(&self) -> String {
+        match self {
+            StopReason::Returned => "returned".to_string(),
+            StopReason::Brk => "hit BRK".to_string(),
+            StopReason::IllegalOpcode(opcode) => format!("illegal opcode ${:02X}", opcode),
+            StopReason::Stuck => "stuck in a loop".to_string(),
+            StopReason::StepLimit => "step limit reached".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    pub steps: Vec<Step>,
+    pub writes: Vec<MemoryWrite>,
+    // Registers when the run stopped; `pc` is where it stopped.
+    pub registers: Registers,
+    pub cycles: u64,
+    pub stop: StopReason,
+}
+
+// Run a routine until it returns, stops or reaches the step limit.
+pub fn run(db: &DisassemblyInfo, rom: &RomImage, setup: &RunSetup) -> RunResult {
+    let mut bus = NesBus::new(db, rom, setup);
+    for &(address, value) in &setup.memory {
+        bus.write(address, value);
+    }
+    bus.writes.clear();
+
+    let mut cpu = Cpu::new(Registers { pc: setup.start, ..setup.registers });
+    let start_stack = cpu.regs.s;
+    let mut steps = Vec::new();
+    let mut writes = Vec::new();
+    let stop = loop {
+        if steps.len() >= setup.max_steps {
+            break StopReason::StepLimit;
+        }
+        let pc = cpu.regs.pc;
+        let opcode = bus.read(pc);
+        if opcode == 0x00 {
+            break StopReason::Brk;
+        }
+        let step = Step { bank: bus.bank_at(pc), registers: cpu.regs, cycle: cpu.cycles };
+        if cpu.step(&mut bus).is_err() {
+            break StopReason::IllegalOpcode(opcode);
+        }
+        writes.extend(bus.writes.drain(..).map(|(address, value)| MemoryWrite { step: steps.len(), address, value }));
+        steps.push(step);
+        // RTS (0x60) and RTI (0x40) leaving the routine pull the stack above
+        // where it started.
+        if matches!(opcode, 0x40 | 0x60) && (cpu.regs.s.wrapping_sub(start_stack) as i8) > 0 {
+            break StopReason::Returned;
+        }
+        if cpu.regs.pc == pc {
+            break StopReason::Stuck;
+        }
+    };
+    RunResult { steps, writes, registers: cpu.regs, cycles: cpu.cycles, stop }
+}
+
+// Initial state as `A=00 X=05 P=24 0300=FF,01`: registers A, X, Y, S and P,
+// and bytes stored from an address on. Values are hex, `$` optional.
+pub fn parse_state(text: &str, registers: &mut Registers) -> Result<Vec<(u16, u8)>, String> {
+    let hex = |s: &str| s.trim().trim_start_matches('$').to_string();
+    let byte = |s: &str| u8::from_str_radix(&hex(s), 16).map_err(|_| format!("bad byte \"{}\"", s));
+    let mut memory = Vec::new();
+    for entry in text.split_whitespace() {
+        let (key, value) = entry.split_once('=').ok_or_else(|| format!("expected NAME=XX, got \"{}\"", entry))?;
+        let register = match key.to_ascii_uppercase().as_str() {
+            "A" => Some(&mut registers.a),
+            "X" => Some(&mut registers.x),
+            "Y" => Some(&mut registers.y),
+            "S" => Some(&mut registers.s),
+            "P" => Some(&mut registers.p),
+            _ => None,
+        };
+        match register {
+            Some(register) => *register = byte(value)?,
+            None => {
+                let address = u16::from_str_radix(&hex(key), 16).map_err(|_| format!("bad address \"{}\"", key))?;
+                for (i, value) in value.split(',').enumerate() {
+                    memory.push((address.wrapping_add(i as u16), byte(value)?));
+                }
+            }
+        }
+    }
+    Ok(memory)
+}
+
+// 8K slots of $8000-$FFFF.
+const SLOT_SIZE: u32 = 0x2000;
+
+// The NES as seen from the CPU: 2K of RAM mirrored to $1FFF, PPU and APU
+// registers, 8K of PRG-RAM at $6000 and PRG ROM switched by the mapper.
+struct NesBus<'a> {
+    ram: [u8; 0x800],
+    prg_ram: [u8; 0x2000],
+    prg: &'a [u8],
+    mapper: Option<u16>,
+    window_size: u32,
+    // PRG ROM offset mapped at each 8K slot.
+    slots: [Option<u32>; 4],
+    // MMC1 shift register, its write count, control and PRG bank registers.
+    mmc1: (u8, u8, u8, u8),
+    // MMC3 bank select and the R6/R7 PRG bank registers.
+    mmc3: (u8, Option<u8>, Option<u8>),
+    // PPUSTATUS reads alternate between vblank/sprite 0 set and clear, so
+    // loops waiting on either finish.
+    ppu_status: bool,
+    writes: Vec<(u16, u8)>,
+}
+
+impl<'a> NesBus<'a> {
+    fn new(db: &DisassemblyInfo, rom: &'a RomImage, setup: &RunSetup) -> Self {
+        let mapper = MapperModel::new(db);
+        let window_size = db.mapper_window_size.max(1) as u32 * 1024;
+        let mut bus = NesBus {
+            ram: [0; 0x800],
+            prg_ram: [0; 0x2000],
+            prg: rom.prg_rom(),
+            mapper: db.mapper,
+            window_size,
+            slots: [None; 4],
+            mmc1: (0, 0, db.mapper_prg_mode.unwrap_or(3) << 2, 0),
+            mmc3: (db.mapper_prg_mode.unwrap_or(0) << 6, None, None),
+            ppu_status: false,
+            writes: Vec::new(),
+        };
+        // The chosen banks where the database places them, then whatever is
+        // mapped alongside the routine's bank.
+        let place = |bank: u8, address: u32| {
+            let base = mapper.base_address(bank) as u32;
+            (address >= base && address < base + window_size).then(|| bank as u32 * window_size + address - base)
+        };
+        for (slot, entry) in bus.slots.iter_mut().enumerate() {
+            let address = 0x8000 + slot as u32 * SLOT_SIZE;
+            *entry = std::iter::once(setup.bank).chain(setup.banks.iter().copied()).find_map(|bank| place(bank, address));
+            if entry.is_none()
+                && let Location::Bank(bank) = mapper.locate(setup.bank, address as u16)
+            {
+                let base = mapper.base_address(bank) as u32;
+                *entry = Some(bank as u32 * window_size + address.wrapping_sub(base) % window_size);
+            }
+        }
+        bus
+    }
+
+    // The database bank an address is read from.
+    fn bank_at(&self, address: u16) -> Option<u8> {
+        let offset = self.rom_offset(address)?;
+        u8::try_from(offset / self.window_size).ok()
+    }
+
+    fn rom_offset(&self, address: u16) -> Option<u32> {
+        if address < 0x8000 {
+            return None;
+        }
+        let slot = (address as u32 - 0x8000) / SLOT_SIZE;
+        self.slots[slot as usize].map(|base| base + address as u32 % SLOT_SIZE)
+    }
+
+    // Map PRG ROM `size` bytes at `offset` (wrapped to the ROM size) from
+    // CPU address `address` on.
+    fn map(&mut self, address: u16, offset: u32, size: u32) {
+        let len = (self.prg.len() as u32).max(1);
+        for i in 0..size / SLOT_SIZE {
+            let slot = (address as u32 - 0x8000) / SLOT_SIZE + i;
+            self.slots[slot as usize] = Some((offset + i * SLOT_SIZE) % len);
+        }
+    }
+
+    // PRG bank switching of the mappers the database models.
+    fn mapper_write(&mut self, address: u16, value: u8) {
+        let last = |size: u32, n: u32| (self.prg.len() as u32).saturating_sub(n * size);
+        match self.mapper {
+            Some(2) => self.map(0x8000, value as u32 * 0x4000, 0x4000),
+            Some(7) => self.map(0x8000, (value & 0x07) as u32 * 0x8000, 0x8000),
+            Some(1) => {
+                let (shift, count, control, bank) = &mut self.mmc1;
+                if value & 0x80 != 0 {
+                    (*shift, *count) = (0, 0);
+                    *control |= 0x0C;
+                } else {
+                    *shift |= (value & 1) << *count;
+                    *count += 1;
+                    if *count < 5 {
+                        return;
+                    }
+                    match address {
+                        0x8000..=0x9FFF => *control = *shift,
+                        0xE000..=0xFFFF => *bank = *shift & 0x0F,
+                        _ => {}
+                    }
+                    (*shift, *count) = (0, 0);
+                }
+                let (control, bank) = (self.mmc1.2, self.mmc1.3 as u32);
+                match (control >> 2) & 3 {
+                    0 | 1 => self.map(0x8000, (bank & 0x0E) * 0x4000, 0x8000),
+                    2 => {
+                        self.map(0x8000, 0, 0x4000);
+                        self.map(0xC000, bank * 0x4000, 0x4000);
+                    }
+                    _ => {
+                        self.map(0x8000, bank * 0x4000, 0x4000);
+                        self.map(0xC000, last(0x4000, 1), 0x4000);
+                    }
+                }
+            }
+            Some(4) if address < 0xA000 => {
+                if address & 1 == 0 {
+                    self.mmc3.0 = value;
+                } else {
+                    match self.mmc3.0 & 7 {
+                        6 => self.mmc3.1 = Some(value),
+                        7 => self.mmc3.2 = Some(value),
+                        _ => return,
+                    }
+                }
+                let (select, r6, r7) = self.mmc3;
+                let (switched, fixed) = if select & 0x40 == 0 { (0x8000, 0xC000) } else { (0xC000, 0x8000) };
+                if let Some(r6) = r6 {
+                    self.map(switched, r6 as u32 * SLOT_SIZE, SLOT_SIZE);
+                }
+                if let Some(r7) = r7 {
+                    self.map(0xA000, r7 as u32 * SLOT_SIZE, SLOT_SIZE);
+                }
+                self.map(fixed, last(SLOT_SIZE, 2), SLOT_SIZE);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Bus for NesBus<'_> {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[address as usize & 0x7FF],
+            0x2000..=0x3FFF if address & 7 == 2 => {
+                self.ppu_status = !self.ppu_status;
+                if self.ppu_status { 0xC0 } else { 0x00 }
+            }
+            0x6000..=0x7FFF => self.prg_ram[address as usize - 0x6000],
+            0x8000..=0xFFFF => self.rom_offset(address).and_then(|offset| self.prg.get(offset as usize)).copied().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.writes.push((address, value));
+        match address {
+            0x0000..=0x1FFF => self.ram[address as usize & 0x7FF] = value,
+            0x6000..=0x7FFF => self.prg_ram[address as usize - 0x6000] = value,
+            0x8000..=0xFFFF => self.mapper_write(address, value),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An MMC1 project with four 16K banks, the last fixed at $C000. Each
+    // bank is filled with $10 plus its number, with `code` at the start of
+    // the fixed bank.
+    fn setup(code: &[u8]) -> (DisassemblyInfo, RomImage, RunSetup) {
+        let db: DisassemblyInfo = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {},
+            "bank": {},
+            "mapper_window_size": 16,
+            "mapper": 1,
+            "prg_rom_size": 0x10000,
+        }))
+        .unwrap();
+        let mut data = vec![b'N', b'E', b'S', 0x1A, 4, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        for bank in 0..4u8 {
+            data.extend(std::iter::repeat_n(0x10 + bank, 0x4000));
+        }
+        data[16 + 3 * 0x4000..][..code.len()].copy_from_slice(code);
+        let rom = RomImage::parse(data).unwrap();
+        let setup = RunSetup {
+            bank: 3,
+            start: 0xC000,
+            registers: Registers::default(),
+            memory: Vec::new(),
+            banks: vec![0],
+            max_steps: DEFAULT_MAX_STEPS,
+        };
+        (db, rom, setup)
+    }
+
+    #[test]
+    fn stops_at_the_routines_own_return() {
+        let (db, rom, setup) = setup(&[
+            0x20, 0x06, 0xC0, // JSR $C006
+            0xA9, 0x01, // LDA #$01
+            0x60, // RTS
+            0xA2, 0x02, // $C006: LDX #$02
+            0x60, // RTS
+        ]);
+        let result = run(&db, &rom, &setup);
+        assert_eq!(result.stop, StopReason::Returned);
+        assert_eq!(result.steps.len(), 5);
+        assert_eq!((result.registers.a, result.registers.x), (0x01, 0x02));
+        assert_eq!(result.registers.s, 0xFF);
+        assert_eq!(result.cycles, 6 + 2 + 6 + 2 + 6);
+        assert!(result.steps.iter().all(|step| step.bank == Some(3)));
+        // The JSR's return address.
+        assert_eq!(result.writes.len(), 2);
+        assert_eq!((result.writes[0].address, result.writes[0].value), (0x01FD, 0xC0));
+    }
+
+    #[test]
+    fn stop_reasons() {
+        let (db, rom, mut setup) = setup(&[
+            0x4C, 0x00, 0xC0, // JMP $C000
+            0x18, // $C003: CLC
+            0x90, 0xFD, // BCC $C003
+        ]);
+        assert_eq!(run(&db, &rom, &setup).stop, StopReason::Stuck);
+        setup.start = 0xC003;
+        setup.max_steps = 10;
+        let result = run(&db, &rom, &setup);
+        assert_eq!(result.stop, StopReason::StepLimit);
+        assert_eq!(result.steps.len(), 10);
+        // $13 is SLO (zp),Y, an unofficial opcode.
+        setup.start = 0xC006;
+        assert_eq!(run(&db, &rom, &setup).stop, StopReason::IllegalOpcode(0x13));
+    }
+
+    #[test]
+    fn mmc1_serial_writes() {
+        let (db, rom, setup) = setup(&[
+            0xA9, 0x80, // LDA #$80
+            0x8D, 0x00, 0x80, // STA $8000: reset
+            0xA9, 0x02, // LDA #$02
+            0x8D, 0x00, 0xE0, // STA $E000
+            0x4A, // LSR A
+            0x8D, 0x00, 0xE0, // STA $E000
+            0x4A, // LSR A
+            0x8D, 0x00, 0xE0, // STA $E000
+            0x4A, // LSR A
+            0x8D, 0x00, 0xE0, // STA $E000
+            0xAC, 0x00, 0x80, // LDY $8000: still bank 0
+            0x4A, // LSR A
+            0x8D, 0x00, 0xE0, // STA $E000: fifth write selects bank 2
+            0xAD, 0x00, 0x80, // LDA $8000
+            0xAE, 0x00, 0xC0, // LDX $C000: the fixed bank
+            0x60, // RTS
+        ]);
+        let result = run(&db, &rom, &setup);
+        assert_eq!(result.stop, StopReason::Returned);
+        assert_eq!(result.registers.y, 0x10);
+        assert_eq!(result.registers.a, 0x12);
+        assert_eq!(result.registers.x, 0xA9);
+    }
+
+    #[test]
+    fn state() {
+        let mut registers = Registers::default();
+        let memory = parse_state("A=01 x=$02 P=24 0300=FF,$01", &mut registers).unwrap();
+        assert_eq!((registers.a, registers.x, registers.p), (0x01, 0x02, 0x24));
+        assert_eq!(memory, vec![(0x0300, 0xFF), (0x0301, 0x01)]);
+        assert!(parse_state("A=100", &mut registers).is_err());
+        assert!(parse_state("Q", &mut registers).is_err());
+    }
+}
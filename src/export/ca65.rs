@@ -0,0 +1,583 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::btree_map::Entry;
+use std::fmt::Write;
+
+use super::{ExportFile, ExportResult};
+use crate::disasm::labels::Labels;
+use crate::disasm::{self, assembler, AddressingMode, OPCODES};
+use crate::models::{BankInfo, DisassemblyInfo, DisassemblyLine, FieldKind, StructDef};
+use crate::rom::RomImage;
+
+const INDENT: &str = "    ";
+const COMMENT_COLUMN: usize = 40;
+
+struct Operand {
+    prefix: String,
+    main: String,
+    suffix: String,
+    // The address `main` names when it is a symbol.
+    symbol_value: Option<u16>,
+    // Spelling of the operand if the symbol can't be used.
+    numeric: String,
+    // Absolute operand below $0100 that ca65 would otherwise shrink to zero page.
+    force_absolute: bool,
+}
+
+impl Operand {
+    fn plain(main: String) -> Self {
+        Operand {
+            prefix: String::new(),
+            main: main.clone(),
+            suffix: String::new(),
+            symbol_value: None,
+            numeric: main,
+            force_absolute: false,
+        }
+    }
+}
+
+enum Item {
+    BlockComment(String),
+    Label(String),
+    Statement {
+        address: u16,
+        bytes: Vec<u8>,
+        op: String,
+        // Comma-separated; usually one.
+        operands: Vec<Operand>,
+        comment: Option<String>,
+    },
+    Reserve(u32),
+}
+
+struct BankSource {
+    items: Vec<Item>,
+    labels: BTreeMap<String, u16>,
+    // Addresses given a local or unnamed label line.
+    locals: BTreeSet<u16>,
+}
+
+// Export the project as a ca65/ld65 source tree which rebuilds the ROM.
+pub fn export_project(db: &DisassemblyInfo, rom: &RomImage) -> Result<ExportResult, String> {
+    rom.validate(db)?;
+
+    let num_banks = disasm::prg_bank_count(db, rom);
+    let window_size = db.mapper_window_size as u32 * 1024;
+
+    // Banks without any annotations still need to be emitted.
+    let mut db = db.clone();
+    for bank_id in 0..num_banks {
+        db.bank.entry(bank_id).or_insert_with(|| BankInfo {
+            title: None,
+            is_fixed: false,
+            region: Vec::new(),
+            address: BTreeMap::new(),
+            mapped_at: None,
+            entry_points: Vec::new(),
+        });
+    }
+
+    let mut result = ExportResult::default();
+    let globals = global_symbols(&db, &mut result.warnings);
+    check_struct_constants(&db, &mut result.warnings);
+    result.files.push(ExportFile::text("globals.inc", globals_source(&db, &globals)));
+    result.files.push(ExportFile::text("header.s", header_source(rom.header_bytes(), rom.trainer().is_some())));
+    if let Some(trainer) = rom.trainer() {
+        result.files.push(ExportFile::binary("trainer.bin", trainer.to_vec()));
+    }
+
+    let labels = disasm::discover_all_targets(&db, rom);
+    let mut bank_starts = Vec::new();
+    for bank_id in 0..num_banks {
+        let base = disasm::bank_base_address(&db, bank_id);
+        let bank_data = disasm::bank_data(&db, rom, bank_id);
+        let lines = disasm::disassemble_bank(&db, bank_id, bank_data, &labels);
+        let file_offset = rom.prg_bank_offset(db.mapper_window_size, bank_id) as u32;
+        let source = bank_source(bank_id, &lines, base, &globals, &labels, &db.structs, &mut result.warnings);
+        let text = render_bank(&db, bank_id, &source, &globals, file_offset, base, &mut result.warnings);
+        result.files.push(ExportFile::text(format!("prg{:02}.s", bank_id), text));
+        bank_starts.push(base);
+    }
+
+    // Everything after PRG ROM (CHR ROM and any trailing data) is carried verbatim.
+    let chr = rom.data[rom.chr_offset()..].to_vec();
+    let chr_size = chr.len();
+    if chr_size > 0 {
+        result.files.push(ExportFile::text("chr.s", "; CHR ROM\n\n.segment \"CHR\"\n.incbin \"chr.bin\"\n".to_string()));
+        result.files.push(ExportFile::binary("chr.bin", chr));
+    }
+
+    result.files.push(ExportFile::text(format!("{}.cfg", db.name), linker_config(&db, rom.prg_offset(), &bank_starts, window_size, chr_size)));
+    result.files.push(ExportFile::text("Makefile", makefile(&db, num_banks, chr_size > 0)));
+    Ok(result)
+}
+
+fn global_symbols(db: &DisassemblyInfo, warnings: &mut Vec<String>) -> BTreeMap<String, u16> {
+    let mut globals = BTreeMap::new();
+    for (&addr, anno) in &db.global {
+        if let Some(ref sym) = anno.symbol {
+            match globals.entry(sym.clone()) {
+                Entry::Vacant(e) => {
+                    e.insert(addr);
+                }
+                Entry::Occupied(e) => {
+                    warnings.push(format!("global symbol {} at ${:04X} duplicates ${:04X}; skipped", sym, addr, e.get()));
+                }
+            }
+        }
+    }
+    globals
+}
+
+// Report enum value names that two struct fields give different values; the
+// records holding the later value are written with a number instead.
+fn check_struct_constants(db: &DisassemblyInfo, warnings: &mut Vec<String>) {
+    let constants = disasm::struct_constants(db);
+    for (name, def) in &db.structs {
+        for field in &def.fields {
+            if let FieldKind::Enum(values) = &field.kind {
+                for (&value, constant) in values {
+                    if constants[constant] != value {
+                        warnings.push(format!(
+                            "{}.{}: {} is ${:02X} here but ${:02X} in an earlier struct; records use the number",
+                            name, field.name, constant, value, constants[constant]
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn globals_source(db: &DisassemblyInfo, globals: &BTreeMap<String, u16>) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "; {}: global symbols\n", db.title);
+    for (&addr, anno) in &db.global {
+        let sym = match anno.symbol {
+            Some(ref sym) if globals.get(sym) == Some(&addr) => sym,
+            _ => continue,
+        };
+        if let Some(ref bc) = anno.block_comment {
+            push_block_comment(&mut out, bc);
+        }
+        let mut line = format!("{} = {}", sym, hex_address(addr));
+        if let Some(ref comment) = anno.comment {
+            pad_to(&mut line, COMMENT_COLUMN);
+            let _ = write!(line, "; {}", single_line(comment));
+        }
+        out.push_str(&line);
+        out.push('\n');
+    }
+    // Struct records are written as macro invocations; enum values as constants.
+    for (name, def) in &db.structs {
+        let params = def.fields.iter().map(|f| f.name.as_str()).collect::<Vec<_>>().join(", ");
+        let _ = writeln!(out, "\n.macro {} {}", name, params);
+        for field in &def.fields {
+            let directive = if field.kind.size() == 1 { ".byte" } else { ".word" };
+            let mut line = format!("{}{} {}", INDENT, directive, field.name);
+            if let Some(ref comment) = field.comment {
+                pad_to(&mut line, COMMENT_COLUMN);
+                let _ = write!(line, "; {}", single_line(comment));
+            }
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out.push_str(".endmacro\n");
+    }
+    let constants = disasm::struct_constants(db);
+    if !constants.is_empty() {
+        out.push_str("\n; Struct field values\n");
+        for (constant, value) in constants {
+            let _ = writeln!(out, "{} = ${:02X}", constant, value);
+        }
+    }
+
+    // Text regions are written as strings in the game's encoding.
+    let charmap = db.text_table.as_ref().map(disasm::text::charmap).unwrap_or_default();
+    if !charmap.is_empty() {
+        out.push_str("\n; Character table for Text regions\n");
+        for (c, byte) in charmap {
+            let mut line = format!(".charmap ${:02X}, ${:02X}", c as u32, byte);
+            pad_to(&mut line, COMMENT_COLUMN);
+            let _ = writeln!(out, "{}; '{}'", line, c);
+        }
+    }
+    out
+}
+
+fn header_source(header: &[u8], has_trainer: bool) -> String {
+    let bytes = header.iter().map(|b| format!("${:02X}", b)).collect::<Vec<_>>();
+    let mut out = format!(
+        "; iNES header\n\n.segment \"HEADER\"\n{}.byt {}\n{}.byt {}\n",
+        INDENT,
+        bytes[0..8].join(", "),
+        INDENT,
+        bytes[8..16].join(", ")
+    );
+    if has_trainer {
+        let _ = writeln!(out, "\n; Trainer\n{}.incbin \"trainer.bin\"", INDENT);
+    }
+    out
+}
+
+// Walk the disassembly and decide what to emit so every byte of the bank
+// is covered exactly once, even if regions overlap or split instructions.
+fn bank_source(
+    bank_id: u8,
+    lines: &[DisassemblyLine],
+    base: u16,
+    globals: &BTreeMap<String, u16>,
+    scopes: &Labels,
+    structs: &BTreeMap<String, StructDef>,
+    warnings: &mut Vec<String>,
+) -> BankSource {
+    let mut items = Vec::new();
+    let mut labels = BTreeMap::new();
+    let mut locals = BTreeSet::new();
+    let mut pc = base as u32;
+
+    for line in lines {
+        let bytes = parse_hex_bytes(&line.bytes);
+        let start = line.address as u32;
+        let stop = start + bytes.len() as u32;
+        if stop <= pc {
+            warnings.push(format!("${:02X}:${:04X} is covered by the previous line; skipped", bank_id, start));
+            continue;
+        }
+        if start > pc {
+            warnings.push(format!("${:02X}:${:04X}: {} bytes missing from the ROM image", bank_id, pc, start - pc));
+            items.push(Item::Reserve(start - pc));
+            pc = start;
+        }
+
+        if start < pc {
+            // The line's annotations belong to an address that is no longer a
+            // line of its own, so they are left out.
+            warnings.push(format!(
+                "${:02X}:${:04X} overlaps the previous line; emitting its remaining bytes as data",
+                bank_id, start
+            ));
+            let tail = bytes[(pc - start) as usize..].to_vec();
+            items.push(byte_statement(pc as u16, tail, None));
+        } else {
+            if let Some(ref bc) = line.block_comment {
+                items.push(Item::BlockComment(bc.clone()));
+            }
+            if let Some(ref sym) = line.symbol {
+                match globals.get(sym).or(labels.get(sym)) {
+                    // Local labels repeat from scope to scope; the disassembler
+                    // only names them where they are in scope.
+                    _ if disasm::labels::is_local(sym) => {
+                        locals.insert(line.address);
+                        items.push(Item::Label(sym.clone()));
+                    }
+                    // The labels that open a scope are decided with the
+                    // disassembly, so its local labels resolve the same way.
+                    _ if scopes.opens_scope(bank_id, line.address) => {
+                        labels.insert(sym.clone(), line.address);
+                        items.push(Item::Label(sym.clone()));
+                    }
+                    // Globals such as mapper registers are already defined in globals.inc.
+                    Some(&addr) if addr == line.address => {}
+                    Some(&addr) => {
+                        warnings.push(format!(
+                            "${:02X}:${:04X}: label {} is already defined at ${:04X}; skipped",
+                            bank_id, line.address, sym, addr
+                        ));
+                    }
+                    None => {
+                        labels.insert(sym.clone(), line.address);
+                        items.push(Item::Label(sym.clone()));
+                    }
+                }
+            }
+            items.push(line_statement(line, bytes, structs));
+        }
+        pc = stop;
+    }
+
+    BankSource { items, labels, locals }
+}
+
+fn line_statement(line: &DisassemblyLine, bytes: Vec<u8>, structs: &BTreeMap<String, StructDef>) -> Item {
+    let comment = line.comment.clone();
+    match line.opcode.as_str() {
+        name if structs.contains_key(name) => record_statement(line, bytes, &structs[name]),
+        ".byt" if line.text.is_some() => {
+            let operand = Operand::plain(line.operand_main.clone());
+            Item::Statement { address: line.address, bytes, op: ".byt".to_string(), operands: vec![operand], comment }
+        }
+        ".byt" => byte_statement(line.address, bytes, comment),
+        // One half of a split pointer table, e.g. `.lobytes Handler-1`.
+        ".lobytes" | ".hibytes" if bytes.len() == 1 => match line.target_address {
+            Some(value) => {
+                let operand = Operand {
+                    suffix: line.operand_suffix.clone(),
+                    symbol_value: if line.operand_is_symbol { Some(value) } else { None },
+                    numeric: format!("${:04X}", value),
+                    ..Operand::plain(line.operand_main.clone())
+                };
+                Item::Statement { address: line.address, bytes, op: line.opcode.clone(), operands: vec![operand], comment }
+            }
+            None => byte_statement(line.address, bytes, comment),
+        },
+        ".word" if bytes.len() == 2 => {
+            // Jump table entries name the target, which may differ from the stored value.
+            let value = line.target_address.unwrap_or(u16::from_le_bytes([bytes[0], bytes[1]]));
+            let operand = Operand {
+                prefix: line.operand_prefix.clone(),
+                suffix: line.operand_suffix.clone(),
+                symbol_value: if line.operand_is_symbol { Some(value) } else { None },
+                numeric: format!("${:04X}", value),
+                ..Operand::plain(line.operand_main.clone())
+            };
+            Item::Statement { address: line.address, bytes, op: ".word".to_string(), operands: vec![operand], comment }
+        }
+        _ => {
+            let instr = bytes.first().and_then(|&b| OPCODES[b as usize].as_ref());
+            let instr = match instr {
+                Some(i) if bytes.len() == 1 + i.mode.operand_length() as usize => i,
+                _ => return byte_statement(line.address, bytes, comment),
+            };
+            let value = match bytes.len() {
+                2 => bytes[1] as u16,
+                3 => u16::from_le_bytes([bytes[1], bytes[2]]),
+                _ => 0,
+            };
+            let operand = match instr.mode {
+                AddressingMode::Implied => None,
+                mode => {
+                    let (symbol_value, numeric) = match mode {
+                        AddressingMode::Relative => {
+                            let target = line.address.wrapping_add(2).wrapping_add(value as u8 as i8 as u16);
+                            (Some(target), format!("${:04X}", target))
+                        }
+                        AddressingMode::ZeroPage | AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                            (Some(value), format!("${:02X}", value))
+                        }
+                        _ => (Some(value), format!("${:04X}", value)),
+                    };
+                    Some(Operand {
+                        prefix: line.operand_prefix.clone(),
+                        main: line.operand_main.clone(),
+                        suffix: line.operand_suffix.clone(),
+                        symbol_value: if line.operand_is_symbol { symbol_value } else { None },
+                        numeric,
+                        force_absolute: assembler::needs_absolute_prefix(&bytes),
+                    })
+                }
+            };
+            Item::Statement { address: line.address, bytes, op: instr.mnemonic.to_string(), operands: operand.into_iter().collect(), comment }
+        }
+    }
+}
+
+// A Struct record as a macro invocation, with pointer fields resolved like
+// any other symbolic operand.
+fn record_statement(line: &DisassemblyLine, bytes: Vec<u8>, def: &StructDef) -> Item {
+    let comment = line.comment.clone();
+    let values = line.operand_main.split(", ").collect::<Vec<_>>();
+    if values.len() != def.fields.len() || bytes.len() != def.size() as usize {
+        return byte_statement(line.address, bytes, comment);
+    }
+    let mut offset = 0;
+    let mut operands = Vec::new();
+    for (field, value) in def.fields.iter().zip(values) {
+        let mut operand = Operand::plain(value.to_string());
+        if field.kind == FieldKind::Pointer && !value.starts_with('$') {
+            let pointer = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+            operand.symbol_value = Some(pointer);
+            operand.numeric = format!("${:04X}", pointer);
+        }
+        operands.push(operand);
+        offset += field.kind.size() as usize;
+    }
+    Item::Statement { address: line.address, bytes, op: line.opcode.clone(), operands, comment }
+}
+
+fn byte_statement(address: u16, bytes: Vec<u8>, comment: Option<String>) -> Item {
+    let main = bytes.iter().map(|b| format!("${:02X}", b)).collect::<Vec<_>>().join(", ");
+    Item::Statement { address, bytes, op: ".byt".to_string(), operands: vec![Operand::plain(main)], comment }
+}
+
+fn render_bank(
+    db: &DisassemblyInfo,
+    bank_id: u8,
+    source: &BankSource,
+    globals: &BTreeMap<String, u16>,
+    file_offset: u32,
+    base: u16,
+    warnings: &mut Vec<String>,
+) -> String {
+    // Symbols from other banks are referenced through equates so each bank
+    // assembles on its own.
+    let mut externals = BTreeMap::new();
+    let mut body = String::new();
+
+    for item in &source.items {
+        match item {
+            Item::BlockComment(bc) => {
+                body.push('\n');
+                push_block_comment(&mut body, bc);
+            }
+            Item::Label(sym) if sym == ":" => body.push_str(":\n"),
+            Item::Label(sym) => {
+                let _ = writeln!(body, "{}:", sym);
+            }
+            Item::Reserve(count) => {
+                let _ = writeln!(body, "{}.res {}", INDENT, count);
+            }
+            Item::Statement { address, bytes, op, operands, comment } => {
+                let mut line = format!("{}{}", INDENT, op);
+                for (i, operand) in operands.iter().enumerate() {
+                    let main = match operand.symbol_value {
+                        Some(value) => {
+                            resolve_operand_symbol(bank_id, *address, operand, value, source, globals, &mut externals, warnings)
+                        }
+                        None => operand.main.clone(),
+                    };
+                    let _ = write!(
+                        line,
+                        "{}{}{}{}{}",
+                        if i == 0 { " " } else { ", " },
+                        operand.prefix,
+                        if operand.force_absolute { "a:" } else { "" },
+                        main,
+                        operand.suffix
+                    );
+                }
+                pad_to(&mut line, COMMENT_COLUMN);
+                let offset = file_offset + address.wrapping_sub(base) as u32;
+                let hex = bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+                let _ = write!(line, "; 0x{:05X} ${:04X} {}", offset, address, hex);
+                if let Some(comment) = comment {
+                    let _ = write!(line, " ; {}", single_line(comment));
+                }
+                body.push_str(&line);
+                body.push('\n');
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let title = db.bank.get(&bank_id).and_then(|b| b.title.clone()).unwrap_or_default();
+    let _ = writeln!(out, "; {}", db.title);
+    let _ = writeln!(out, "; Bank ${:02X}: {}\n", bank_id, title);
+    let _ = writeln!(out, ".include \"globals.inc\"\n");
+    if !externals.is_empty() {
+        out.push_str("; Symbols defined in other banks\n");
+        for (sym, addr) in &externals {
+            let _ = writeln!(out, "{} = {}", sym, hex_address(*addr));
+        }
+        out.push('\n');
+    }
+    let _ = writeln!(out, ".segment \"PRG{:02}\"", bank_id);
+    out.push_str(&body);
+    out
+}
+
+#[allow(clippy::too_many_arguments)]
+fn resolve_operand_symbol(
+    bank_id: u8,
+    address: u16,
+    operand: &Operand,
+    value: u16,
+    source: &BankSource,
+    globals: &BTreeMap<String, u16>,
+    externals: &mut BTreeMap<String, u16>,
+    warnings: &mut Vec<String>,
+) -> String {
+    let name = &operand.main;
+    // A local label that was not written (one inside an instruction, or on a
+    // line that overlaps the one before) cannot be named.
+    if name.starts_with(':') || disasm::labels::is_local(name) {
+        return if source.locals.contains(&value) { name.clone() } else { operand.numeric.clone() };
+    }
+    let defined = source.labels.get(name).or_else(|| globals.get(name));
+    let usable = match defined {
+        Some(&addr) => addr == value,
+        None => match externals.entry(name.clone()) {
+            Entry::Vacant(e) => {
+                e.insert(value);
+                true
+            }
+            Entry::Occupied(e) => *e.get() == value,
+        },
+    };
+    if usable {
+        name.clone()
+    } else {
+        warnings.push(format!(
+            "${:02X}:${:04X}: symbol {} is ambiguous; using {}",
+            bank_id, address, name, operand.numeric
+        ));
+        operand.numeric.clone()
+    }
+}
+
+fn linker_config(db: &DisassemblyInfo, header_size: usize, bank_starts: &[u16], window_size: u32, chr_size: usize) -> String {
+    let mut memory = String::new();
+    let mut segments = String::new();
+    let _ = writeln!(memory, "{}HEADER: start = $0000, size = ${:04X}, file = %O, fill = yes;", INDENT, header_size);
+    let _ = writeln!(segments, "{}HEADER: load = HEADER, type = ro;", INDENT);
+    for (bank_id, start) in bank_starts.iter().enumerate() {
+        let _ = writeln!(
+            memory,
+            "{}PRG{:02}:  start = ${:04X}, size = ${:04X}, file = %O, fill = yes, fillval = $FF;",
+            INDENT, bank_id, start, window_size
+        );
+        let _ = writeln!(segments, "{}PRG{:02}:  load = PRG{:02}, type = ro;", INDENT, bank_id, bank_id);
+    }
+    if chr_size > 0 {
+        let _ = writeln!(memory, "{}CHR:    start = $0000, size = ${:04X}, file = %O, fill = yes;", INDENT, chr_size);
+        let _ = writeln!(segments, "{}CHR:    load = CHR, type = ro;", INDENT);
+    }
+    format!(
+        "# ld65 configuration for {}\n# Mapper window: {}K\n\nMEMORY {{\n{}}}\n\nSEGMENTS {{\n{}}}\n",
+        db.title, db.mapper_window_size, memory, segments
+    )
+}
+
+fn makefile(db: &DisassemblyInfo, num_banks: u8, has_chr: bool) -> String {
+    let mut objs = vec!["header.o".to_string()];
+    objs.extend((0..num_banks).map(|id| format!("prg{:02}.o", id)));
+    if has_chr {
+        objs.push("chr.o".to_string());
+    }
+    format!(
+        "OBJS = {objs}\n\n\
+         {name}.nes: $(OBJS) {name}.cfg\n\tld65 -C {name}.cfg -o $@ $(OBJS)\n\n\
+         %.o: %.s globals.inc\n\tca65 -o $@ $<\n\n\
+         clean:\n\trm -f $(OBJS) {name}.nes\n",
+        objs = objs.join(" "),
+        name = db.name
+    )
+}
+
+fn push_block_comment(out: &mut String, text: &str) {
+    for line in text.lines() {
+        if line.is_empty() {
+            out.push_str(";\n");
+        } else {
+            let _ = writeln!(out, "; {}", line);
+        }
+    }
+}
+
+fn pad_to(line: &mut String, column: usize) {
+    let len = line.chars().count();
+    let pad = if len < column { column - len } else { 1 };
+    line.extend(std::iter::repeat_n(' ', pad));
+}
+
+fn single_line(text: &str) -> String {
+    text.lines().collect::<Vec<_>>().join(" ")
+}
+
+fn hex_address(addr: u16) -> String {
+    if addr < 0x100 { format!("${:02X}", addr) } else { format!("${:04X}", addr) }
+}
+
+fn parse_hex_bytes(text: &str) -> Vec<u8> {
+    text.split_whitespace().filter_map(|b| u8::from_str_radix(b, 16).ok()).collect()
+}
@@ -0,0 +1,26 @@
+pub mod ca65;
+pub mod symbols;
+
+// A file produced by an exporter, named relative to the output directory.
+#[derive(Debug, Clone)]
+pub struct ExportFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl ExportFile {
+    pub fn text(name: impl Into<String>, text: String) -> Self {
+        ExportFile { name: name.into(), data: text.into_bytes() }
+    }
+
+    pub fn binary(name: impl Into<String>, data: Vec<u8>) -> Self {
+        ExportFile { name: name.into(), data }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportResult {
+    pub files: Vec<ExportFile>,
+    // Problems that were worked around but that the user should know about.
+    pub warnings: Vec<String>,
+}
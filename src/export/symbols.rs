@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Bound;
+
+use super::{ExportFile, ExportResult};
+use crate::disasm::prg_bank_count;
+use crate::mapper::MapperModel;
+use crate::models::{AnnotationInfo, DisassemblyInfo, RegionInfo};
+use crate::rom::RomImage;
+
+// FCEUX name lists are split into 16K banks whatever the mapper's window.
+const FCEUX_BANK_SIZE: u32 = 0x4000;
+
+// Where an emulator finds a symbol.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Place {
+    // Offset into PRG ROM, and the CPU address the database maps it at.
+    Prg { offset: u32, address: u16 },
+    // Internal RAM, cartridge RAM at $6000-$7FFF, or a register, by CPU
+    // address.
+    Ram(u16),
+    SaveRam(u16),
+    Register(u16),
+}
+
+struct Entry {
+    place: Place,
+    // Bytes the symbol names: more than one for a labelled array.
+    size: u32,
+    name: Option<String>,
+    comment: Option<String>,
+}
+
+// Symbols and comments in the order they should be written: globals, then
+// each PRG bank.
+fn entries(db: &DisassemblyInfo, rom: &RomImage, warnings: &mut Vec<String>) -> Vec<Entry> {
+    let mapper = MapperModel::new(db);
+    let window = db.mapper_window_size as u32 * 1024;
+    let mut entries = Vec::new();
+
+    for (&addr, anno) in &db.global {
+        let (name, comment) = (symbol(anno), anno.joined_comment());
+        if name.is_none() && comment.is_none() {
+            continue;
+        }
+        let place = match addr {
+            0x0000..=0x1FFF => Place::Ram(addr & 0x07FF),
+            0x2000..=0x5FFF => Place::Register(addr),
+            0x6000..=0x7FFF => Place::SaveRam(addr),
+            _ => {
+                warnings.push(format!("global ${:04X} is in ROM and has no bank; skipped", addr));
+                continue;
+            }
+        };
+        entries.push(Entry { place, size: 1, name, comment });
+    }
+
+    let num_banks = prg_bank_count(db, rom);
+    for (&bank_id, bank) in &db.bank {
+        if bank_id >= num_banks {
+            warnings.push(format!("bank ${:02X} is beyond the ROM's {} banks; skipped", bank_id, num_banks));
+            continue;
+        }
+        let base = mapper.base_address(bank_id) as u32;
+        for (&addr, anno) in &bank.address {
+            let (name, comment) = (symbol(anno), anno.joined_comment());
+            if name.is_none() && comment.is_none() {
+                continue;
+            }
+            let Some(offset) = (addr as u32).checked_sub(base).filter(|&o| o < window) else {
+                warnings.push(format!("${:02X}:${:04X} is outside the bank's addresses; skipped", bank_id, addr));
+                continue;
+            };
+            // A name at the start of data runs to the next name or the end
+            // of its region.
+            let size = match bank.region.iter().find(|r| !matches!(r, RegionInfo::Code(_)) && r.contains(addr)) {
+                Some(region) if name.is_some() => {
+                    let region_end = region.ranges().into_iter().find(|r| r.contains(&addr)).map_or(addr, |r| *r.end());
+                    let next_name = bank.address.range((Bound::Excluded(addr), Bound::Included(region_end))).find(|(_, a)| a.symbol.is_some()).map(|(&a, _)| a - 1);
+                    next_name.unwrap_or(region_end) as u32 - addr as u32 + 1
+                }
+                _ => 1,
+            };
+            let offset = bank_id as u32 * window + offset;
+            entries.push(Entry { place: Place::Prg { offset, address: addr }, size, name, comment });
+        }
+    }
+    entries
+}
+
+// The symbol at an annotation, leaving out ca65's unnamed `:` labels, which
+// no debugger can look up.
+fn symbol(anno: &AnnotationInfo) -> Option<String> {
+    anno.symbol.clone().filter(|s| s != ":")
+}
+
+// Mesen debugger labels: one `P:offset:name:comment` line per PRG symbol,
+// `R:` for internal RAM, `S:` for cartridge RAM and `G:` for registers, with
+// `start-end` for arrays. Names must be unique, so repeats are reported.
+pub fn mesen_labels(db: &DisassemblyInfo, rom: &RomImage, name: &str) -> ExportResult {
+    let mut result = ExportResult::default();
+    let entries = entries(db, rom, &mut result.warnings);
+    let mut seen: BTreeMap<&str, Place> = BTreeMap::new();
+    let mut text = String::new();
+    for entry in &entries {
+        let (kind, start) = match entry.place {
+            Place::Prg { offset, .. } => ('P', offset),
+            Place::Ram(addr) => ('R', addr as u32),
+            Place::SaveRam(addr) => ('S', addr as u32 - 0x6000),
+            Place::Register(addr) => ('G', addr as u32),
+        };
+        if let Some(name) = &entry.name
+            && let Some(first) = seen.insert(name, entry.place)
+        {
+            result.warnings.push(format!("{} is defined at {} and {}; Mesen keeps only the last", name, describe(first), describe(entry.place)));
+        }
+        let span = match entry.size {
+            1 => format!("{:04X}", start),
+            size => format!("{:04X}-{:04X}", start, start + size - 1),
+        };
+        let comment = entry.comment.as_deref().unwrap_or("").replace('\n', "\\n");
+        let _ = writeln!(text, "{}:{}:{}:{}", kind, span, entry.name.as_deref().unwrap_or(""), comment);
+    }
+    result.files.push(ExportFile::text(format!("{}.mlb", name), text));
+    result
+}
+
+// FCEUX name lists: `<rom>.ram.nl` for addresses below $8000 and
+// `<rom>.<bank>.nl` per 16K of PRG ROM, with `$addr#name#comment` lines,
+// `/size` after the address of arrays, and `\` starting each further line of
+// a comment.
+pub fn fceux_name_lists(db: &DisassemblyInfo, rom: &RomImage, rom_name: &str) -> ExportResult {
+    let mut result = ExportResult::default();
+    let entries = entries(db, rom, &mut result.warnings);
+    let mut files: BTreeMap<Option<u32>, String> = BTreeMap::new();
+    for entry in &entries {
+        let (file, address) = match entry.place {
+            Place::Prg { offset, address } => (Some(offset / FCEUX_BANK_SIZE), address),
+            Place::Ram(addr) | Place::SaveRam(addr) | Place::Register(addr) => (None, addr),
+        };
+        let text = files.entry(file).or_default();
+        let _ = write!(text, "${:04X}", address);
+        if entry.size > 1 {
+            let _ = write!(text, "/{:X}", entry.size);
+        }
+        let comment = entry.comment.as_deref().unwrap_or("").replace('\n', "\n\\");
+        let _ = writeln!(text, "#{}#{}", entry.name.as_deref().unwrap_or(""), comment);
+    }
+    for (file, text) in files {
+        let name = match file {
+            Some(bank) => format!("{}.{:X}.nl", rom_name, bank),
+            None => format!("{}.ram.nl", rom_name),
+        };
+        result.files.push(ExportFile::text(name, text));
+    }
+    result
+}
+
+fn describe(place: Place) -> String {
+    match place {
+        Place::Prg { offset, address } => format!("${:04X} (PRG ${:05X})", address, offset),
+        Place::Ram(addr) | Place::SaveRam(addr) | Place::Register(addr) => format!("${:04X}", addr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{self, labels, ConflictPolicy};
+
+    // A UxROM project with four 16K banks, or an MMC3 one with eight 8K
+    // banks, annotated in RAM, registers, cartridge RAM, a switchable bank
+    // and the last bank.
+    fn setup(mmc3: bool) -> (DisassemblyInfo, RomImage) {
+        let (mapper, window, last, at) = if mmc3 { (4, 8, 7, 0xE000) } else { (2, 16, 3, 0xC000) };
+        let db = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {
+                "16": {"symbol": "temp", "comment": "scratch"},
+                "8192": {"symbol": "PPUCTRL"},
+                "24832": {"symbol": "save_slot", "block_comment": "Battery RAM"},
+            },
+            "bank": {
+                "1": {
+                    "region": [{"Bytes": {"start": 0x8010, "end": 0x801F}}],
+                    "address": {
+                        "32768": {"symbol": "Start", "comment": "two\nlines"},
+                        "32771": {"symbol": ":"},
+                        "32773": {"comment": "no name"},
+                        "32774": {"symbol": "Loop", "comment": "line", "block_comment": "Block\n\nwith a gap"},
+                        "32784": {"symbol": "Table"},
+                        "32792": {"symbol": "Table2"},
+                    },
+                },
+                (last.to_string()): {"is_fixed": true, "region": [], "address": {(at.to_string()): {"symbol": "Reset"}, "65530": {"symbol": "Vectors"}}},
+            },
+            "mapper_window_size": window,
+            "mapper": mapper,
+            "prg_rom_size": 0x10000,
+        }))
+        .unwrap();
+        let mut data = vec![b'N', b'E', b'S', 0x1A, 4, 0, (mapper as u8) << 4, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 0x10000, 0);
+        (db, RomImage::parse(data).unwrap())
+    }
+
+    // Export both formats, import each back and check that merging it would
+    // change nothing.
+    fn round_trip(db: &DisassemblyInfo, rom: &RomImage) {
+        for export in [mesen_labels(db, rom, "game.nes"), fceux_name_lists(db, rom, "game.nes")] {
+            assert!(export.warnings.is_empty(), "{:?}", export.warnings);
+            let files: Vec<_> = export.files.iter().map(|f| (f.name.clone(), String::from_utf8(f.data.clone()).unwrap())).collect();
+            let mut warnings = Vec::new();
+            let listing = labels::read_files(db, &files, &mut warnings);
+            assert!(warnings.is_empty(), "{:?}", warnings);
+            let report = import::merge(&mut db.clone(), &listing, ConflictPolicy::KeepExisting);
+            assert!(report.added.is_empty() && report.conflicts.is_empty(), "{:?}\n{:?}", files, report);
+            assert_eq!(report.unchanged, 12);
+        }
+    }
+
+    #[test]
+    fn round_trip_16k() {
+        let (db, rom) = setup(false);
+        round_trip(&db, &rom);
+        let mesen = String::from_utf8(mesen_labels(&db, &rom, "game").files[0].data.clone()).unwrap();
+        assert!(mesen.contains("P:4010-4017:Table:\nP:4018-401F:Table2:\n"), "{}", mesen);
+        assert!(mesen.contains("S:0100:save_slot:Battery RAM\n"), "{}", mesen);
+    }
+
+    #[test]
+    fn round_trip_8k() {
+        let (db, rom) = setup(true);
+        round_trip(&db, &rom);
+        let names: Vec<_> = fceux_name_lists(&db, &rom, "game.nes").files.into_iter().map(|f| f.name).collect();
+        assert_eq!(names, ["game.nes.ram.nl", "game.nes.0.nl", "game.nes.3.nl"]);
+    }
+}
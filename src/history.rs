@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::models::{AnnotationInfo, BankInfo, DisassemblyInfo, RegionInfo};
+
+// Edits kept for undo.
+const MAX_ENTRIES: usize = 200;
+
+// What an edit did at one place in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    // The annotation at an address of a bank, or of the global section
+    // when `bank` is None. `None` annotations are absent.
+    Annotation {
+        bank: Option<u8>,
+        address: u16,
+        before: Option<AnnotationInfo>,
+        after: Option<AnnotationInfo>,
+    },
+    Regions {
+        bank: u8,
+        before: Vec<RegionInfo>,
+        after: Vec<RegionInfo>,
+    },
+}
+
+impl Change {
+    // Put the `after` state (or with `forward` false, the `before` state)
+    // into the database.
+    pub fn apply(&self, db: &mut DisassemblyInfo, forward: bool) {
+        match self {
+            Change::Annotation { bank, address, before, after } => {
+                let value = if forward { after } else { before };
+                let section = match bank {
+                    Some(id) => &mut bank_entry(db, *id).address,
+                    None => &mut db.global,
+                };
+                match value {
+                    Some(info) => section.insert(*address, info.clone()),
+                    None => section.remove(address),
+                };
+            }
+            Change::Regions { bank, before, after } => {
+                bank_entry(db, *bank).region = if forward { after } else { before }.clone();
+            }
+        }
+    }
+
+    // Where the change is, as `$BB:$AAAA`, `$AAAA` (global) or `$BB`.
+    pub fn location(&self) -> String {
+        match self {
+            Change::Annotation { bank: Some(id), address, .. } => format!("${:02X}:${:04X}", id, address),
+            Change::Annotation { bank: None, address, .. } => format!("${:04X}", address),
+            Change::Regions { bank, .. } => format!("${:02X}", bank),
+        }
+    }
+
+    // One line per changed field, e.g. `comment: "old" -> "new"`.
+    pub fn details(&self) -> Vec<String> {
+        match self {
+            Change::Annotation { before, after, .. } => {
+                let empty = AnnotationInfo::default();
+                let (b, a) = (before.as_ref().unwrap_or(&empty), after.as_ref().unwrap_or(&empty));
+                let mut lines = Vec::new();
+                for (name, old, new) in [
+                    ("symbol", &b.symbol, &a.symbol),
+                    ("comment", &b.comment, &a.comment),
+                    ("block comment", &b.block_comment, &a.block_comment),
+                ] {
+                    if old != new {
+                        lines.push(format!("{}: {} -> {}", name, quoted(old), quoted(new)));
+                    }
+                }
+                match (&b.dispatcher, &a.dispatcher) {
+                    (None, Some(_)) => lines.push("dispatcher added".to_string()),
+                    (Some(_), None) => lines.push("dispatcher removed".to_string()),
+                    (old, new) if old != new => lines.push("dispatcher changed".to_string()),
+                    _ => {}
+                }
+                lines
+            }
+            Change::Regions { before, after, .. } => {
+                let removed = before.iter().filter(|r| !after.contains(r)).map(|r| format!("- {}", r.label()));
+                let added = after.iter().filter(|r| !before.contains(r)).map(|r| format!("+ {}", r.label()));
+                removed.chain(added).collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub description: String,
+    pub changes: Vec<Change>,
+}
+
+// Undo and redo stacks, most recent edit last.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+    pub undo: Vec<HistoryEntry>,
+    pub redo: Vec<HistoryEntry>,
+}
+
+impl History {
+    // Record the edit that turned `before` into `after`. Edits that change no
+    // annotation or region are not recorded.
+    pub fn record(&mut self, description: &str, before: &DisassemblyInfo, after: &DisassemblyInfo) {
+        let changes = diff(before, after);
+        if changes.is_empty() {
+            return;
+        }
+        self.undo.push(HistoryEntry { description: description.to_string(), changes });
+        if self.undo.len() > MAX_ENTRIES {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn undo(&mut self, db: &mut DisassemblyInfo) -> bool {
+        let Some(entry) = self.undo.pop() else { return false };
+        for change in entry.changes.iter().rev() {
+            change.apply(db, false);
+        }
+        self.redo.push(entry);
+        true
+    }
+
+    pub fn redo(&mut self, db: &mut DisassemblyInfo) -> bool {
+        let Some(entry) = self.redo.pop() else { return false };
+        for change in &entry.changes {
+            change.apply(db, true);
+        }
+        self.undo.push(entry);
+        true
+    }
+
+    // Undo one earlier edit, leaving later ones in place. The revert is
+    // recorded as a new edit, so it can itself be undone.
+    pub fn revert(&mut self, index: usize, db: &mut DisassemblyInfo) -> bool {
+        let Some(entry) = self.undo.get(index).cloned() else { return false };
+        let before = db.clone();
+        for change in entry.changes.iter().rev() {
+            change.apply(db, false);
+        }
+        self.record(&format!("Revert: {}", entry.description), &before, db);
+        true
+    }
+}
+
+// Annotation and region differences between two versions of a database.
+pub fn diff(before: &DisassemblyInfo, after: &DisassemblyInfo) -> Vec<Change> {
+    let mut changes = diff_section(None, &before.global, &after.global);
+    let banks: BTreeSet<u8> = before.bank.keys().chain(after.bank.keys()).copied().collect();
+    let empty = BTreeMap::new();
+    for id in banks {
+        let (old, new) = (before.bank.get(&id), after.bank.get(&id));
+        let regions = |bank: Option<&BankInfo>| bank.map(|b| b.region.clone()).unwrap_or_default();
+        let (old_regions, new_regions) = (regions(old), regions(new));
+        if old_regions != new_regions {
+            changes.push(Change::Regions { bank: id, before: old_regions, after: new_regions });
+        }
+        let (old_section, new_section) = (old.map_or(&empty, |b| &b.address), new.map_or(&empty, |b| &b.address));
+        changes.extend(diff_section(Some(id), old_section, new_section));
+    }
+    changes
+}
+
+fn diff_section(bank: Option<u8>, before: &BTreeMap<u16, AnnotationInfo>, after: &BTreeMap<u16, AnnotationInfo>) -> Vec<Change> {
+    let addresses: BTreeSet<u16> = before.keys().chain(after.keys()).copied().collect();
+    addresses
+        .into_iter()
+        .filter(|addr| before.get(addr) != after.get(addr))
+        .map(|address| Change::Annotation { bank, address, before: before.get(&address).cloned(), after: after.get(&address).cloned() })
+        .collect()
+}
+
+fn bank_entry(db: &mut DisassemblyInfo, id: u8) -> &mut BankInfo {
+    db.bank.entry(id).or_insert_with(|| BankInfo {
+        title: None,
+        is_fixed: false,
+        region: Vec::new(),
+        address: BTreeMap::new(),
+        mapped_at: None,
+        entry_points: Vec::new(),
+    })
+}
+
+fn quoted(text: &Option<String>) -> String {
+    match text {
+        Some(text) if text.chars().count() > 40 => format!("\"{}...\"", text.chars().take(40).collect::<String>()),
+        Some(text) => format!("\"{}\"", text),
+        None => "(none)".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db() -> DisassemblyInfo {
+        serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {},
+            "bank": {"0": {"region": [], "address": {}}},
+            "mapper_window_size": 16,
+        }))
+        .unwrap()
+    }
+
+    // Edit `db` with `edit` and record it.
+    fn edit(history: &mut History, db: &mut DisassemblyInfo, description: &str, edit: impl FnOnce(&mut DisassemblyInfo)) {
+        let before = db.clone();
+        edit(db);
+        history.record(description, &before, db);
+    }
+
+    fn symbol(db: &mut DisassemblyInfo, address: u16, name: &str) {
+        db.bank.get_mut(&0).unwrap().address.entry(address).or_default().symbol = Some(name.to_string());
+    }
+
+    #[test]
+    fn undo_after_redo() {
+        let (mut history, mut db) = (History::default(), db());
+        let original = db.clone();
+        edit(&mut history, &mut db, "name", |db| symbol(db, 0x8000, "Reset"));
+        let named = db.clone();
+        edit(&mut history, &mut db, "rename", |db| symbol(db, 0x8000, "Start"));
+        let renamed = db.clone();
+
+        assert!(history.undo(&mut db));
+        assert!(diff(&db, &named).is_empty());
+        assert!(history.redo(&mut db));
+        assert!(diff(&db, &renamed).is_empty());
+        assert!(history.undo(&mut db) && history.undo(&mut db));
+        assert!(diff(&db, &original).is_empty());
+        assert!(!history.undo(&mut db));
+        assert!(history.redo(&mut db));
+        assert!(diff(&db, &named).is_empty());
+        assert_eq!(history.undo.len(), 1);
+        assert_eq!(history.redo.len(), 1);
+    }
+
+    #[test]
+    fn a_new_edit_discards_the_redo_branch() {
+        let (mut history, mut db) = (History::default(), db());
+        edit(&mut history, &mut db, "name", |db| symbol(db, 0x8000, "Reset"));
+        edit(&mut history, &mut db, "rename", |db| symbol(db, 0x8000, "Start"));
+        history.undo(&mut db);
+        edit(&mut history, &mut db, "other", |db| symbol(db, 0x8010, "Loop"));
+        assert!(history.redo.is_empty());
+        assert!(!history.redo(&mut db));
+        assert_eq!(history.undo.iter().map(|e| e.description.as_str()).collect::<Vec<_>>(), ["name", "other"]);
+
+        // An edit that changes nothing keeps the redo branch.
+        history.undo(&mut db);
+        edit(&mut history, &mut db, "nothing", |_| {});
+        assert_eq!(history.redo.len(), 1);
+    }
+
+    #[test]
+    fn one_edit_is_undone_as_a_group() {
+        let (mut history, mut db) = (History::default(), db());
+        let original = db.clone();
+        edit(&mut history, &mut db, "label and mark", |db| {
+            symbol(db, 0x8000, "Reset");
+            db.global.entry(0x2000).or_default().symbol = Some("PPUCTRL".to_string());
+            db.bank.get_mut(&0).unwrap().mark_region(RegionInfo::Code(0x8000..=0x80FF));
+        });
+        assert_eq!(history.undo.len(), 1);
+        assert_eq!(history.undo[0].changes.len(), 3);
+        assert_eq!(history.undo[0].changes.iter().map(Change::location).collect::<Vec<_>>(), ["$2000", "$00", "$00:$8000"]);
+        history.undo(&mut db);
+        assert!(diff(&db, &original).is_empty());
+    }
+
+    #[test]
+    fn revert_an_earlier_edit() {
+        let (mut history, mut db) = (History::default(), db());
+        edit(&mut history, &mut db, "first", |db| symbol(db, 0x8000, "Reset"));
+        edit(&mut history, &mut db, "second", |db| symbol(db, 0x8010, "Loop"));
+        assert!(history.revert(0, &mut db));
+        assert!(!db.bank[&0].address.contains_key(&0x8000));
+        assert!(db.bank[&0].address.contains_key(&0x8010));
+        assert_eq!(history.undo.last().unwrap().description, "Revert: first");
+        history.undo(&mut db);
+        assert!(db.bank[&0].address.contains_key(&0x8000));
+        assert!(!history.revert(5, &mut db));
+    }
+
+    #[test]
+    fn old_entries_are_dropped() {
+        let (mut history, mut db) = (History::default(), db());
+        for i in 0..MAX_ENTRIES as u16 + 5 {
+            edit(&mut history, &mut db, &i.to_string(), |db| symbol(db, 0x8000 + i, "L"));
+        }
+        assert_eq!(history.undo.len(), MAX_ENTRIES);
+        assert_eq!(history.undo[0].description, "5");
+    }
+}
@@ -0,0 +1,271 @@
+use regex::Regex;
+
+use super::Listing;
+use crate::models::{AnnotationInfo, DisassemblyInfo};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    // Drop decorative separator comments such as `; --------`.
+    pub skip_borders: bool,
+    // Drop register notes such as `A = 18` or `X -> 17`.
+    pub skip_register_notes: bool,
+}
+
+struct Patterns {
+    segment: Regex,
+    label: Regex,
+    equate: Regex,
+    auto_label: Regex,
+    border: Regex,
+    register_note: Regex,
+    // `; 0xOFFSET $ADDR ...` as written by `doctool export`.
+    offset_marker: Regex,
+    // `; ADDR XX XX ...` as written by da65 with --comments.
+    da65_marker: Regex,
+}
+
+impl Patterns {
+    fn new() -> Self {
+        Patterns {
+            segment: Regex::new(r#"^\.segment\s+"([^"]*)""#).unwrap(),
+            label: Regex::new(r"^([A-Za-z_@][A-Za-z0-9_]*):").unwrap(),
+            equate: Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*)\s*:?=\s*(\$[0-9A-Fa-f]+|%[01]+|[0-9]+)\s*(?:;(.*))?$").unwrap(),
+            auto_label: Regex::new(r"^L[0-9A-Fa-f]{4}$").unwrap(),
+            border: Regex::new(r"^-+\s*;?$").unwrap(),
+            register_note: Regex::new(r"(?i)^[A-Z]\s*(=|->)\s*[0-9A-F]{2}$").unwrap(),
+            offset_marker: Regex::new(r"^\s*0x[0-9A-Fa-f]+\s+\$([0-9A-Fa-f]{4})").unwrap(),
+            da65_marker: Regex::new(r"^\s*\$?([0-9A-Fa-f]{4})\s+[0-9A-Fa-f]{2}(\s|$)").unwrap(),
+        }
+    }
+}
+
+// Parse da65/ca65 sources into a listing. Banks come from `.segment
+// "PRGn"` directives, addresses from the marker comment on each line.
+// Labels and comment blocks attach to the next line with a marker;
+// equates become global symbols.
+pub fn import_sources(db: &DisassemblyInfo, sources: &[String], options: &ImportOptions) -> Listing {
+    let patterns = Patterns::new();
+    let mut listing = Listing::default();
+    let window_size = db.mapper_window_size as u32 * 1024;
+
+    for source in sources {
+        let mut current_bank: Option<u8> = None;
+        let mut pending_label: Option<String> = None;
+        let mut pending_block: Vec<String> = Vec::new();
+
+        for raw_line in source.lines() {
+            let line = raw_line.trim();
+            if line.is_empty() {
+                // A blank line detaches file headers and section banners from what follows.
+                pending_block.clear();
+                continue;
+            }
+
+            if let Some(caps) = patterns.segment.captures(line) {
+                current_bank = caps[1].strip_prefix("PRG").and_then(|n| n.parse().ok());
+                pending_label = None;
+                pending_block.clear();
+                continue;
+            }
+
+            let (code, comments) = match line.split_once(';') {
+                Some((code, rest)) => (code.trim(), Some(rest)),
+                None => (line, None),
+            };
+
+            if code.is_empty() {
+                // Comment-only line: part of a block comment unless it carries a marker.
+                let text = comments.unwrap_or_default();
+                if !is_marker(&patterns, text) {
+                    let text = clean_comment(text);
+                    if text.is_empty() {
+                        // Keep paragraph breaks inside a block comment.
+                        if !pending_block.is_empty() {
+                            pending_block.push(text);
+                        }
+                    } else if keep_comment(&patterns, options, &text) {
+                        pending_block.push(text);
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(caps) = patterns.equate.captures(line) {
+                let name = caps[1].to_string();
+                if let Some(address) = parse_number(&caps[2]).filter(|_| !patterns.auto_label.is_match(&name)) {
+                    let entry = listing.global.entry(address).or_default();
+                    let comment = caps.get(3).map(|m| clean_comment(m.as_str())).filter(|c| !c.is_empty());
+                    merge_entry(entry, Some(name), comment, &pending_block);
+                }
+                pending_label = None;
+                pending_block.clear();
+                continue;
+            }
+
+            if let Some(caps) = patterns.label.captures(code) {
+                let name = &caps[1];
+                if !patterns.auto_label.is_match(name) {
+                    pending_label = Some(name.to_string());
+                }
+            }
+
+            let Some(comments) = comments else { continue };
+            let mut parts = comments.split(';');
+            let marker = parts.next().unwrap_or_default();
+            let Some(address) = marker_address(&patterns, marker) else { continue };
+            let Some(bank_id) = current_bank else { continue };
+
+            let inline = clean_comment(&parts.collect::<Vec<_>>().join(";"));
+            let comment = Some(inline).filter(|c| !c.is_empty() && keep_comment(&patterns, options, c));
+
+            let base = ((address as u32 / window_size) * window_size) as u16;
+            let mapped_at = listing.mapped_at.entry(bank_id).or_insert(base);
+            *mapped_at = (*mapped_at).min(base);
+
+            let entry = listing.bank.entry(bank_id).or_default().entry(address).or_default();
+            merge_entry(entry, pending_label.take(), comment, &pending_block);
+            pending_block.clear();
+        }
+    }
+
+    drop_label_equates(db, &mut listing);
+    listing.global.retain(|_, anno| !anno.is_empty());
+    for section in listing.bank.values_mut() {
+        section.retain(|_, anno| !anno.is_empty());
+    }
+    listing
+}
+
+// Later files overwrite symbols and inline comments; block comments accumulate.
+fn merge_entry(entry: &mut AnnotationInfo, symbol: Option<String>, comment: Option<String>, block: &[String]) {
+    if symbol.is_some() {
+        entry.symbol = symbol;
+    }
+    if comment.is_some() {
+        entry.comment = comment;
+    }
+    let block = block.iter().rposition(|l| !l.is_empty()).map_or(&block[..0], |end| &block[..=end]);
+    if !block.is_empty() {
+        let block = block.join("\n");
+        entry.block_comment = Some(match entry.block_comment.take() {
+            Some(existing) => format!("{}\n{}", existing, block),
+            None => block,
+        });
+    }
+}
+
+// Exports define symbols from other banks as equates so each file assembles
+// on its own. Those are labels, not globals.
+fn drop_label_equates(db: &DisassemblyInfo, listing: &mut Listing) {
+    let is_label = |name: &str, address: u16| {
+        listing.bank.values().chain(db.bank.values().map(|b| &b.address)).any(|section| {
+            section.get(&address).and_then(|a| a.symbol.as_deref()) == Some(name)
+        })
+    };
+    let labels = listing
+        .global
+        .iter()
+        .filter(|(addr, anno)| anno.symbol.as_deref().is_some_and(|s| is_label(s, **addr)))
+        .map(|(addr, _)| *addr)
+        .collect::<Vec<_>>();
+    for addr in labels {
+        listing.global.remove(&addr);
+    }
+}
+
+fn is_marker(patterns: &Patterns, comment: &str) -> bool {
+    marker_address(patterns, comment.split(';').next().unwrap_or_default()).is_some()
+}
+
+fn marker_address(patterns: &Patterns, text: &str) -> Option<u16> {
+    let caps = patterns.offset_marker.captures(text).or_else(|| patterns.da65_marker.captures(text))?;
+    u16::from_str_radix(&caps[1], 16).ok()
+}
+
+fn keep_comment(patterns: &Patterns, options: &ImportOptions, text: &str) -> bool {
+    let border = options.skip_borders && patterns.border.is_match(text);
+    let register_note = options.skip_register_notes && patterns.register_note.is_match(text);
+    !border && !register_note
+}
+
+fn clean_comment(text: &str) -> String {
+    text.trim().trim_matches(|c: char| c == ';' || c.is_whitespace()).to_string()
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    if let Some(hex) = text.strip_prefix('$') {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix('%') {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db() -> DisassemblyInfo {
+        serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {},
+            "bank": {},
+            "mapper_window_size": 16,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn da65_listing() {
+        let source = r#"; da65 V2.18 - Git 1ae2b7c
+; Input file: game.prg
+
+PPUCTRL         := $2000                        ; control register
+Wait            = $8002
+
+        .segment "PRG0"
+
+L8000:  lda     #$00                            ; 8000 A9 00
+; --------------------------------
+; Waits for vblank
+;
+; A = 00
+Wait:   bit     $2002                           ; 8002 2C 02 20
+        bpl     Wait                            ; 8005 10 FB ; loop until set
+"#;
+        let listing = import_sources(&db(), &[source.to_string()], &ImportOptions::default());
+        assert_eq!(listing.global.len(), 1);
+        assert_eq!(listing.global[&0x2000].symbol.as_deref(), Some("PPUCTRL"));
+        assert_eq!(listing.global[&0x2000].comment.as_deref(), Some("control register"));
+        assert_eq!(listing.mapped_at[&0], 0x8000);
+        let bank = &listing.bank[&0];
+        assert!(!bank.contains_key(&0x8000));
+        assert_eq!(bank[&0x8002].symbol.as_deref(), Some("Wait"));
+        assert_eq!(bank[&0x8002].block_comment.as_deref(), Some("--------------------------------\nWaits for vblank\n\nA = 00"));
+        assert_eq!(bank[&0x8005].comment.as_deref(), Some("loop until set"));
+
+        let options = ImportOptions { skip_borders: true, skip_register_notes: true };
+        let listing = import_sources(&db(), &[source.to_string()], &options);
+        assert_eq!(listing.bank[&0][&0x8002].block_comment.as_deref(), Some("Waits for vblank"));
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let source = r#"    .segment "PRG1"
+TooBig := $12345
+Bad = $GG
+Label:  nop ; not a marker
+        nop                                     ; 0x04010 $C000 EA
+        .segment "CHR"
+Orphan: nop                                     ; C001 EA
+"#;
+        let listing = import_sources(&db(), &[source.to_string()], &ImportOptions::default());
+        assert!(listing.global.is_empty());
+        assert_eq!(listing.bank.len(), 1);
+        assert_eq!(listing.bank[&1].len(), 1);
+        assert_eq!(listing.bank[&1][&0xC000].symbol.as_deref(), Some("Label"));
+        assert_eq!(listing.mapped_at[&1], 0xC000);
+    }
+}
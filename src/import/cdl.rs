@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::disasm::prg_bank_count;
+use crate::mapper::MapperModel;
+use crate::models::{BankInfo, DisassemblyInfo, RegionInfo};
+use crate::rom::RomImage;
+
+// Flag bits shared by the FCEUX and Mesen PRG logs. FCEUX keeps the CPU
+// window of the access in bits 2-3 and Mesen other hints in bits 2, 3 and 7;
+// those are ignored.
+const CODE: u8 = 0x01;
+const DATA: u8 = 0x02;
+const INDIRECT_CODE: u8 = 0x10;
+const INDIRECT_DATA: u8 = 0x20;
+const PCM: u8 = 0x40;
+
+// What the CDL says a byte was used as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CdlKind {
+    Code,
+    Data,
+}
+
+impl CdlKind {
+    pub fn region(&self, range: RangeInclusive<u16>) -> RegionInfo {
+        match self {
+            CdlKind::Code => RegionInfo::Code(range),
+            CdlKind::Data => RegionInfo::Bytes(range),
+        }
+    }
+}
+
+// A run of bytes the CDL would mark differently from the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CdlChange {
+    pub bank: u8,
+    pub range: RangeInclusive<u16>,
+    pub kind: CdlKind,
+    // The region the CDL disagrees with, or None where no region covers
+    // the bytes yet.
+    pub replaces: Option<String>,
+}
+
+impl CdlChange {
+    pub fn label(&self) -> String {
+        let region = self.kind.region(self.range.clone()).label();
+        match &self.replaces {
+            Some(current) => format!("${:02X}: {} -> {}", self.bank, current, region),
+            None => format!("${:02X}: + {}", self.bank, region),
+        }
+    }
+}
+
+// How a CDL compares with the database's regions.
+#[derive(Debug, Clone, Default)]
+pub struct CdlDiff {
+    pub changes: Vec<CdlChange>,
+    // Logged bytes the database already marks the same way, and bytes the
+    // CDL has no record of.
+    pub agreeing: usize,
+    pub unlogged: usize,
+}
+
+// The PRG ROM part of an FCEUX or Mesen `.cdl` file: one flag byte per PRG
+// byte, followed by the CHR log. Mesen 2 files start with `CDLv2` and a
+// CRC32.
+pub fn parse_cdl(data: &[u8], prg_size: usize) -> Result<Vec<u8>, String> {
+    let body = match data.strip_prefix(b"CDLv2") {
+        Some(rest) => rest.get(4..).ok_or("truncated CDL header")?,
+        None => data,
+    };
+    if body.len() < prg_size {
+        return Err(format!("CDL covers {} bytes, but the ROM has {} bytes of PRG", body.len(), prg_size));
+    }
+    Ok(body[..prg_size].to_vec())
+}
+
+// Compare the CDL flags of every PRG bank with the database. Bytes logged as
+// code belong in Code regions, bytes only read as data in any other kind.
+pub fn diff(db: &DisassemblyInfo, rom: &RomImage, flags: &[u8]) -> CdlDiff {
+    let mapper = MapperModel::new(db);
+    let window = db.mapper_window_size as usize * 1024;
+    let no_regions = Vec::new();
+    let mut result = CdlDiff::default();
+
+    for bank_id in 0..prg_bank_count(db, rom) {
+        let base = mapper.base_address(bank_id) as u32;
+        let regions = db.bank.get(&bank_id).map_or(&no_regions, |b| &b.region);
+        let start = bank_id as usize * window;
+        let Some(bank_flags) = flags.get(start..(start + window).min(flags.len())) else { continue };
+
+        // The run of changed bytes being built: their kind, and the index of
+        // the region they are in.
+        let mut run: Option<(RangeInclusive<u16>, CdlKind, Option<usize>)> = None;
+        let mut changes = Vec::new();
+        for (i, &flag) in bank_flags.iter().enumerate() {
+            let Ok(addr) = u16::try_from(base + i as u32) else { break };
+            let kind = if flag & (CODE | INDIRECT_CODE) != 0 {
+                Some(CdlKind::Code)
+            } else if flag & (DATA | INDIRECT_DATA | PCM) != 0 {
+                Some(CdlKind::Data)
+            } else {
+                None
+            };
+            let region = regions.iter().position(|r| r.contains(addr));
+            let change = match kind {
+                None => {
+                    result.unlogged += 1;
+                    None
+                }
+                Some(kind) => {
+                    let is_code = region.is_some_and(|r| matches!(regions[r], RegionInfo::Code(_)));
+                    if region.is_some() && is_code == (kind == CdlKind::Code) {
+                        result.agreeing += 1;
+                        None
+                    } else {
+                        Some((kind, region))
+                    }
+                }
+            };
+            run = match (run, change) {
+                (Some((range, k, r)), Some((kind, region))) if (k, r) == (kind, region) && *range.end() as u32 + 1 == addr as u32 => {
+                    Some((*range.start()..=addr, k, r))
+                }
+                (previous, change) => {
+                    changes.extend(previous);
+                    change.map(|(kind, region)| (addr..=addr, kind, region))
+                }
+            };
+        }
+        changes.extend(run);
+        result.changes.extend(changes.into_iter().map(|(range, kind, region)| CdlChange {
+            bank: bank_id,
+            range,
+            kind,
+            replaces: region.map(|r| regions[r].label()),
+        }));
+    }
+    result
+}
+
+// Mark the chosen changes' bytes as Code or Bytes regions, cutting them out
+// of the regions they disagree with.
+pub fn apply(db: &mut DisassemblyInfo, changes: &[CdlChange]) {
+    let mapper = MapperModel::new(db);
+    for change in changes {
+        let bank = db.bank.entry(change.bank).or_insert_with(|| BankInfo {
+            title: None,
+            is_fixed: false,
+            region: Vec::new(),
+            address: BTreeMap::new(),
+            mapped_at: Some(mapper.base_address(change.bank)),
+            entry_points: Vec::new(),
+        });
+        bank.mark_region(change.kind.region(change.range.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A UxROM project with two 16K banks, bank 1 fixed at $C000.
+    fn setup() -> (DisassemblyInfo, RomImage) {
+        let db = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {},
+            "bank": {"0": {"region": [{"Code": {"start": 0x8000, "end": 0x8001}}, {"Bytes": {"start": 0x8002, "end": 0x8007}}], "address": {}}},
+            "mapper_window_size": 16,
+            "mapper": 2,
+            "prg_rom_size": 0x8000,
+        }))
+        .unwrap();
+        let mut data = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 0x8000 + 0x2000, 0);
+        (db, RomImage::parse(data).unwrap())
+    }
+
+    // PRG flags, then a CHR log to be ignored.
+    fn flags() -> Vec<u8> {
+        let mut flags = vec![0u8; 0x8000];
+        flags[..7].copy_from_slice(&[CODE, CODE, CODE, CODE | INDIRECT_CODE, DATA, INDIRECT_DATA, INDIRECT_CODE]);
+        // A PCM sample, and code logged with FCEUX's CPU window bits.
+        flags[0x4000] = PCM;
+        flags[0x4001] = CODE | 0x0C;
+        flags.extend([0xFF; 0x2000]);
+        flags
+    }
+
+    #[test]
+    fn parse_fceux_and_mesen() {
+        let fceux = flags();
+        assert_eq!(parse_cdl(&fceux, 0x8000).unwrap(), fceux[..0x8000]);
+
+        let mut mesen = b"CDLv2".to_vec();
+        mesen.extend([0x12, 0x34, 0x56, 0x78]);
+        mesen.extend(&fceux);
+        assert_eq!(parse_cdl(&mesen, 0x8000).unwrap(), fceux[..0x8000]);
+
+        assert_eq!(parse_cdl(b"CDLv2\x12", 0x8000), Err("truncated CDL header".to_string()));
+        assert_eq!(parse_cdl(&fceux[..0x100], 0x8000), Err("CDL covers 256 bytes, but the ROM has 32768 bytes of PRG".to_string()));
+    }
+
+    #[test]
+    fn diff_and_apply() {
+        let (mut db, rom) = setup();
+        let cdl = parse_cdl(&flags(), rom.prg_rom().len()).unwrap();
+        let result = diff(&db, &rom, &cdl);
+        assert_eq!(result.agreeing, 4);
+        assert_eq!(result.unlogged, 0x8000 - 9);
+        let labels: Vec<_> = result.changes.iter().map(CdlChange::label).collect();
+        assert_eq!(
+            labels,
+            [
+                "$00: Bytes $8002-$8007 -> Code $8002-$8003",
+                "$00: Bytes $8002-$8007 -> Code $8006-$8006",
+                "$01: + Bytes $C000-$C000",
+                "$01: + Code $C001-$C001",
+            ]
+        );
+
+        apply(&mut db, &result.changes);
+        assert_eq!(
+            db.bank[&0].region,
+            [
+                RegionInfo::Code(0x8000..=0x8003),
+                RegionInfo::Bytes(0x8004..=0x8005),
+                RegionInfo::Code(0x8006..=0x8006),
+                RegionInfo::Bytes(0x8007..=0x8007),
+            ]
+        );
+        assert_eq!(db.bank[&1].mapped_at, Some(0xC000));
+        let again = diff(&db, &rom, &cdl);
+        assert!(again.changes.is_empty());
+        assert_eq!(again.agreeing, 9);
+    }
+}
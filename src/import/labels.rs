@@ -0,0 +1,319 @@
+use super::Listing;
+use crate::mapper::MapperModel;
+use crate::models::{AnnotationInfo, DisassemblyInfo};
+
+// FCEUX name lists are split into 16K banks whatever the mapper's window.
+const FCEUX_BANK_SIZE: u32 = 0x4000;
+
+// Read Mesen `.mlb` and FCEUX `.nl` label files, by file name and text, into
+// a listing. PRG ROM labels go to the bank holding their offset, RAM and
+// register labels to the global section. Array sizes are dropped, since a
+// symbol names one address here.
+pub fn read_files(db: &DisassemblyInfo, files: &[(String, String)], warnings: &mut Vec<String>) -> Listing {
+    if db.prg_rom_size.is_none() {
+        warnings.push("the project does not record its PRG ROM size; labels past the end of the ROM are not caught".to_string());
+    }
+    let mut reader = Reader { db, mapper: MapperModel::new(db), listing: Listing::default(), warnings };
+    for (name, text) in files {
+        let lower = name.to_ascii_lowercase();
+        if lower.ends_with(".mlb") {
+            reader.read_mlb(name, text);
+        } else if lower.ends_with(".nl") {
+            reader.read_nl(name, text);
+        } else {
+            reader.warnings.push(format!("{}: not a .mlb or .nl file; skipped", name));
+        }
+    }
+    reader.listing
+}
+
+struct Reader<'a> {
+    db: &'a DisassemblyInfo,
+    mapper: MapperModel,
+    listing: Listing,
+    warnings: &'a mut Vec<String>,
+}
+
+impl Reader<'_> {
+    // Mesen labels: `P:offset:name:comment` with `P`, `R`, `S`, `W` or `G` (or
+    // Mesen 2's `NesPrgRom`, `NesInternalRam`, `NesSaveRam`, `NesWorkRam` and
+    // `NesMemory`), an optional `-end` after the offset, and `\n` for line
+    // breaks in the comment.
+    fn read_mlb(&mut self, file: &str, text: &str) {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let bad = |message: &str| format!("{}:{}: {}", file, number + 1, message);
+            let mut parts = line.splitn(4, ':');
+            let (Some(kind), Some(span), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+                self.warnings.push(bad("expected TYPE:ADDRESS:NAME[:COMMENT]"));
+                continue;
+            };
+            let comment = parts.next().unwrap_or("").replace("\\n", "\n");
+            let start = span.split('-').next().unwrap_or(span);
+            let Ok(value) = u32::from_str_radix(start, 16) else {
+                self.warnings.push(bad(&format!("bad address \"{}\"", span)));
+                continue;
+            };
+            let annotation = annotation(name, &comment);
+            match kind {
+                "P" | "NesPrgRom" => self.add_prg(value, annotation, || bad(&format!("PRG offset ${:X} is outside the ROM", value))),
+                "R" | "NesInternalRam" if value < 0x0800 => self.add_global(value as u16, annotation),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" if value < 0x2000 => self.add_global(0x6000 + value as u16, annotation),
+                "G" | "NesMemory" if value <= 0xFFFF => self.add_global(value as u16, annotation),
+                "R" | "S" | "W" | "G" | "NesInternalRam" | "NesSaveRam" | "NesWorkRam" | "NesMemory" => {
+                    self.warnings.push(bad(&format!("address ${:X} is out of range for {}", value, kind)))
+                }
+                _ => self.warnings.push(bad(&format!("{} labels are not imported", kind))),
+            }
+        }
+    }
+
+    // FCEUX name lists: `<rom>.ram.nl` for addresses below $8000 and
+    // `<rom>.<bank>.nl` per 16K of PRG ROM, with `$addr[/size]#name#comment`
+    // lines and `\` starting each further line of a comment.
+    fn read_nl(&mut self, file: &str, text: &str) {
+        let stem = file.rsplit(['/', '\\']).next().unwrap_or(file);
+        let stem = &stem[..stem.len() - 3];
+        let bank = match stem.rsplit('.').next() {
+            Some(part) if part.eq_ignore_ascii_case("ram") => None,
+            Some(part) => match u32::from_str_radix(part, 16) {
+                Ok(bank) => Some(bank),
+                Err(_) => {
+                    self.warnings.push(format!("{}: expected a name like game.nes.ram.nl or game.nes.0.nl; skipped", file));
+                    return;
+                }
+            },
+            None => return,
+        };
+
+        let mut entries: Vec<(usize, u16, String, String)> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if let Some(more) = line.strip_prefix('\\') {
+                match entries.last_mut() {
+                    Some(entry) => {
+                        entry.3.push('\n');
+                        entry.3.push_str(more);
+                    }
+                    None => self.warnings.push(format!("{}:{}: comment line without a label", file, number + 1)),
+                }
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(3, '#');
+            let (Some(address), Some(name)) = (parts.next(), parts.next()) else {
+                self.warnings.push(format!("{}:{}: expected $ADDRESS#NAME#COMMENT", file, number + 1));
+                continue;
+            };
+            let address = address.trim().trim_start_matches('$');
+            let address = address.split('/').next().unwrap_or(address);
+            let Ok(address) = u16::from_str_radix(address, 16) else {
+                self.warnings.push(format!("{}:{}: bad address \"{}\"", file, number + 1, address));
+                continue;
+            };
+            entries.push((number + 1, address, name.to_string(), parts.next().unwrap_or("").to_string()));
+        }
+
+        for (number, address, name, comment) in entries {
+            let annotation = annotation(&name, &comment);
+            let bad = || format!("{}:{}: ${:04X} is outside the file's bank", file, number, address);
+            match bank {
+                None if address < 0x8000 => self.add_global(address, annotation),
+                None => self.warnings.push(format!("{}:{}: ${:04X} is in ROM, which .ram.nl does not cover", file, number, address)),
+                Some(_) if address < 0x8000 => self.warnings.push(bad()),
+                Some(bank) => self.add_prg(self.fceux_offset(bank, address), annotation, bad),
+            }
+        }
+    }
+
+    // The PRG offset of a CPU address in a 16K FCEUX bank: in the part of the
+    // bank the database maps there, preferring banks it describes, or else
+    // counted from the start of the bank's 16K.
+    fn fceux_offset(&self, bank: u32, address: u16) -> u32 {
+        let window = self.db.mapper_window_size.max(1) as u32 * 1024;
+        let first = bank * FCEUX_BANK_SIZE / window;
+        let mapped: Vec<(u8, u32)> = (first..first + (FCEUX_BANK_SIZE / window).max(1))
+            .filter_map(|id| {
+                let id = u8::try_from(id).ok()?;
+                let offset = (address as u32).checked_sub(self.mapper.base_address(id) as u32).filter(|&o| o < window)?;
+                Some((id, id as u32 * window + offset))
+            })
+            .collect();
+        let described = mapped.iter().find(|(id, _)| self.db.bank.contains_key(id));
+        match described.or(mapped.first()) {
+            Some(&(_, offset)) => offset,
+            None => bank * FCEUX_BANK_SIZE + (address as u32 - 0x8000) % FCEUX_BANK_SIZE,
+        }
+    }
+
+    fn add_global(&mut self, address: u16, annotation: AnnotationInfo) {
+        let annotation = settle(self.db.global.get(&address), annotation);
+        if !annotation.is_empty() {
+            self.listing.global.insert(address, annotation);
+        }
+    }
+
+    // Place a label by PRG ROM offset, at the CPU address the database maps
+    // the offset's bank at.
+    fn add_prg(&mut self, offset: u32, annotation: AnnotationInfo, bad: impl FnOnce() -> String) {
+        let window = self.db.mapper_window_size as u32 * 1024;
+        let bank = offset / window;
+        if offset >= self.db.prg_rom_size.unwrap_or(u32::MAX) || bank > 0xFF {
+            self.warnings.push(bad());
+            return;
+        }
+        let bank = bank as u8;
+        let base = self.mapper.base_address(bank);
+        let address = base + (offset % window) as u16;
+        let annotation = settle(self.db.bank.get(&bank).and_then(|b| b.address.get(&address)), annotation);
+        if !annotation.is_empty() {
+            self.listing.mapped_at.insert(bank, base);
+            self.listing.bank.entry(bank).or_default().insert(address, annotation);
+        }
+    }
+}
+
+fn annotation(name: &str, comment: &str) -> AnnotationInfo {
+    let name = name.trim();
+    let comment = comment.trim_end();
+    AnnotationInfo {
+        symbol: (!name.is_empty()).then(|| name.to_string()),
+        comment: (!comment.is_empty()).then(|| comment.to_string()),
+        ..Default::default()
+    }
+}
+
+// Drop an incoming comment that is the database's block and line comments
+// joined, as `doctool labels` writes them, so exported labels come back
+// unchanged.
+fn settle(existing: Option<&AnnotationInfo>, mut annotation: AnnotationInfo) -> AnnotationInfo {
+    if existing.is_some_and(|e| e.block_comment.is_some() && e.joined_comment() == annotation.comment) {
+        annotation.comment = None;
+    }
+    annotation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A project with `banks` PRG banks of `window` K for `mapper`.
+    fn db(mapper: u16, window: u8, banks: u32) -> DisassemblyInfo {
+        serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {},
+            "bank": {"0": {"region": [], "address": {"32768": {"symbol": "Reset", "comment": "line", "block_comment": "Block"}}}},
+            "mapper_window_size": window,
+            "mapper": mapper,
+            "prg_rom_size": banks * window as u32 * 1024,
+        }))
+        .unwrap()
+    }
+
+    fn read(db: &DisassemblyInfo, files: &[(&str, &str)]) -> (Listing, Vec<String>) {
+        let files: Vec<_> = files.iter().map(|&(name, text)| (name.to_string(), text.to_string())).collect();
+        let mut warnings = Vec::new();
+        (read_files(db, &files, &mut warnings), warnings)
+    }
+
+    fn symbol(section: &crate::models::SectionInfo, address: u16) -> Option<&str> {
+        section.get(&address)?.symbol.as_deref()
+    }
+
+    #[test]
+    fn mesen() {
+        let db = db(2, 16, 4);
+        let (listing, warnings) = read(&db, &[("game.mlb", "\
+P:0000:Reset:Block\\nline
+P:4010-4013:Table:four\\nbytes
+NesPrgRom:C000:FixedStart
+R:0010:temp
+NesInternalRam:07FF:top
+S:0100:save
+NesWorkRam:1FFF:last
+G:2000:PPUCTRL
+
+NesInternalRam:0800:TooHigh
+G:10000:TooFar
+P:10000:Past
+X:0000:Foo
+P:zz:Bad
+Nonsense
+")]);
+        // The comment `doctool labels` wrote for Reset comes back as nothing new.
+        assert_eq!(listing.bank[&0][&0x8000].symbol.as_deref(), Some("Reset"));
+        assert_eq!(listing.bank[&0][&0x8000].comment, None);
+        assert_eq!(symbol(&listing.bank[&1], 0x8010), Some("Table"));
+        assert_eq!(listing.bank[&1][&0x8010].comment.as_deref(), Some("four\nbytes"));
+        assert_eq!(symbol(&listing.bank[&3], 0xC000), Some("FixedStart"));
+        assert_eq!(listing.mapped_at[&3], 0xC000);
+        let globals: Vec<_> = listing.global.iter().map(|(&a, anno)| (a, anno.symbol.as_deref().unwrap())).collect();
+        assert_eq!(globals, [(0x0010, "temp"), (0x07FF, "top"), (0x2000, "PPUCTRL"), (0x6100, "save"), (0x7FFF, "last")]);
+        assert_eq!(
+            warnings,
+            [
+                "game.mlb:10: address $800 is out of range for NesInternalRam",
+                "game.mlb:11: address $10000 is out of range for G",
+                "game.mlb:12: PRG offset $10000 is outside the ROM",
+                "game.mlb:13: X labels are not imported",
+                "game.mlb:14: bad address \"zz\"",
+                "game.mlb:15: expected TYPE:ADDRESS:NAME[:COMMENT]",
+            ]
+        );
+    }
+
+    #[test]
+    fn fceux() {
+        let db = db(2, 16, 4);
+        let (listing, warnings) = read(&db, &[
+            ("dir/game.nes.ram.nl", "$0010#temp#\n$8000#InRom#\n"),
+            ("game.nes.3.nl", "$C000#FixedStart#first\n\\second line\n$0100#Low#\n$C005/02#Arr#\r\nbroken\n"),
+            ("game.nes.1.nl", "$8010#Table#\n"),
+            ("game.nes.4.nl", "$8000#Past#\n"),
+            ("game.nes.x.nl", "$8000#Bad#\n"),
+            ("notes.txt", ""),
+        ]);
+        assert_eq!(symbol(&listing.global, 0x0010), Some("temp"));
+        assert_eq!(listing.bank[&3][&0xC000].comment.as_deref(), Some("first\nsecond line"));
+        assert_eq!(symbol(&listing.bank[&3], 0xC005), Some("Arr"));
+        assert_eq!(symbol(&listing.bank[&1], 0x8010), Some("Table"));
+        assert_eq!(
+            warnings,
+            [
+                "dir/game.nes.ram.nl:2: $8000 is in ROM, which .ram.nl does not cover",
+                "game.nes.3.nl:5: expected $ADDRESS#NAME#COMMENT",
+                "game.nes.3.nl:3: $0100 is outside the file's bank",
+                "game.nes.4.nl:1: $8000 is outside the file's bank",
+                "game.nes.x.nl: expected a name like game.nes.ram.nl or game.nes.0.nl; skipped",
+                "notes.txt: not a .mlb or .nl file; skipped",
+            ]
+        );
+    }
+
+    // FCEUX banks are 16K whatever the project's window; Mesen offsets are
+    // into PRG ROM.
+    #[test]
+    fn eight_k_banks() {
+        let db = db(4, 8, 8);
+        let (listing, warnings) = read(&db, &[("game.nes.1.nl", "$A123#Upper#\n$8001#Lower#\n"), ("game.mlb", "P:E000:Last\n")]);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(symbol(&listing.bank[&2], 0x8001), Some("Lower"));
+        assert_eq!(symbol(&listing.bank[&3], 0x8123), Some("Upper"));
+        assert_eq!(symbol(&listing.bank[&7], 0xE000), Some("Last"));
+    }
+
+    #[test]
+    fn unknown_rom_size() {
+        let mut db = db(2, 16, 4);
+        db.prg_rom_size = None;
+        let (listing, warnings) = read(&db, &[("game.mlb", "P:400000:Far\n")]);
+        assert_eq!(warnings, ["the project does not record its PRG ROM size; labels past the end of the ROM are not caught", "game.mlb:1: PRG offset $400000 is outside the ROM"]);
+        assert!(listing.is_empty());
+    }
+}
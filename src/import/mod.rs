@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use crate::disasm::validate;
+use crate::models::{AnnotationInfo, BankInfo, DisassemblyInfo, SectionInfo};
+
+pub mod ca65;
+pub mod cdl;
+pub mod labels;
+pub mod tracelog;
+
+// Annotations collected by an importer, waiting to be merged into a database.
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    pub global: SectionInfo,
+    pub bank: BTreeMap<u8, SectionInfo>,
+    // CPU address to use for banks that the database does not know yet.
+    pub mapped_at: BTreeMap<u8, u16>,
+}
+
+impl Listing {
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.bank.values().all(|s| s.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    KeepExisting,
+    Overwrite,
+}
+
+// One annotation field touched by a merge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    // None for the global section.
+    pub bank: Option<u8>,
+    pub address: u16,
+    pub field: &'static str,
+    pub existing: Option<String>,
+    pub incoming: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeReport {
+    pub added: Vec<FieldChange>,
+    // Fields where the database and the listing disagree.
+    pub conflicts: Vec<FieldChange>,
+    pub unchanged: usize,
+    pub new_banks: Vec<u8>,
+}
+
+// What merging a listing would do, worked out on a copy of the database.
+#[derive(Debug, Clone, Default)]
+pub struct MergePreview {
+    pub report: MergeReport,
+    // Incoming symbols whose name is also defined somewhere both can be seen,
+    // with that other place, once the listing's values are used.
+    pub duplicates: Vec<(FieldChange, String)>,
+}
+
+pub fn preview(db: &DisassemblyInfo, listing: &Listing) -> MergePreview {
+    let report = merge(&mut db.clone(), listing, ConflictPolicy::KeepExisting);
+    let mut merged = db.clone();
+    merge(&mut merged, listing, ConflictPolicy::Overwrite);
+    let duplicates = report
+        .added
+        .iter()
+        .chain(&report.conflicts)
+        .filter(|c| c.field == "symbol")
+        .filter_map(|c| Some((c.clone(), validate::symbol_clash(&merged, c.bank, c.address, &c.incoming)?)))
+        .collect();
+    MergePreview { report, duplicates }
+}
+
+// Merge a listing into the database. Conflicting fields are reported and
+// resolved according to `policy`.
+pub fn merge(db: &mut DisassemblyInfo, listing: &Listing, policy: ConflictPolicy) -> MergeReport {
+    let mut report = MergeReport::default();
+
+    for (&address, incoming) in &listing.global {
+        let section = db.global.entry(address).or_default();
+        merge_annotation(section, incoming, None, address, policy, &mut report);
+        if section.is_empty() {
+            db.global.remove(&address);
+        }
+    }
+
+    for (&bank_id, annotations) in &listing.bank {
+        if annotations.is_empty() {
+            continue;
+        }
+        let bank = db.bank.entry(bank_id).or_insert_with(|| {
+            report.new_banks.push(bank_id);
+            BankInfo {
+                title: None,
+                is_fixed: false,
+                region: Vec::new(),
+                address: BTreeMap::new(),
+                mapped_at: Some(listing.mapped_at.get(&bank_id).copied().unwrap_or(0x8000)),
+                entry_points: Vec::new(),
+            }
+        });
+        for (&address, incoming) in annotations {
+            let section = bank.address.entry(address).or_default();
+            merge_annotation(section, incoming, Some(bank_id), address, policy, &mut report);
+            if section.is_empty() {
+                bank.address.remove(&address);
+            }
+        }
+    }
+
+    report
+}
+
+fn merge_annotation(
+    existing: &mut AnnotationInfo,
+    incoming: &AnnotationInfo,
+    bank: Option<u8>,
+    address: u16,
+    policy: ConflictPolicy,
+    report: &mut MergeReport,
+) {
+    let fields = [
+        ("symbol", &mut existing.symbol, &incoming.symbol),
+        ("comment", &mut existing.comment, &incoming.comment),
+        ("block_comment", &mut existing.block_comment, &incoming.block_comment),
+    ];
+    for (field, current, new) in fields {
+        let Some(new) = new else { continue };
+        let change = FieldChange { bank, address, field, existing: current.clone(), incoming: new.clone() };
+        match current {
+            None => {
+                *current = Some(new.clone());
+                report.added.push(change);
+            }
+            Some(old) if old == new => report.unchanged += 1,
+            Some(_) => {
+                if policy == ConflictPolicy::Overwrite {
+                    *current = Some(new.clone());
+                }
+                report.conflicts.push(change);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anno(symbol: &str, comment: Option<&str>) -> AnnotationInfo {
+        serde_json::from_value(serde_json::json!({"symbol": symbol, "comment": comment})).unwrap()
+    }
+
+    fn setup() -> (DisassemblyInfo, Listing) {
+        let db: DisassemblyInfo = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {},
+            "bank": {"0": {
+                "region": [],
+                "address": {"32768": {"symbol": "Old", "comment": "same"}, "32800": {"symbol": "Dup"}},
+                "mapped_at": 0x8000,
+            }},
+            "mapper_window_size": 16,
+        }))
+        .unwrap();
+        let mut listing = Listing::default();
+        listing.global.insert(0x2000, anno("PPUCTRL", None));
+        let bank = listing.bank.entry(0).or_default();
+        bank.insert(0x8000, anno("New", Some("same")));
+        bank.insert(0x8030, anno("Dup", None));
+        listing.bank.entry(2).or_default().insert(0xC000, anno("Far", None));
+        listing.mapped_at.insert(2, 0xC000);
+        (db, listing)
+    }
+
+    #[test]
+    fn keep_existing() {
+        let (mut db, listing) = setup();
+        let report = merge(&mut db, &listing, ConflictPolicy::KeepExisting);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!((report.conflicts[0].existing.as_deref(), report.conflicts[0].incoming.as_str()), (Some("Old"), "New"));
+        assert_eq!(report.added.len(), 3);
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(report.new_banks, vec![2]);
+        assert_eq!(db.bank[&0].address[&0x8000].symbol.as_deref(), Some("Old"));
+        assert_eq!(db.bank[&2].mapped_at, Some(0xC000));
+        assert_eq!(db.global[&0x2000].symbol.as_deref(), Some("PPUCTRL"));
+    }
+
+    #[test]
+    fn overwrite() {
+        let (mut db, listing) = setup();
+        let report = merge(&mut db, &listing, ConflictPolicy::Overwrite);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(db.bank[&0].address[&0x8000].symbol.as_deref(), Some("New"));
+        assert_eq!(db.bank[&0].address[&0x8000].comment.as_deref(), Some("same"));
+    }
+
+    #[test]
+    fn preview_leaves_the_database_alone() {
+        let (db, listing) = setup();
+        let preview = preview(&db, &listing);
+        assert_eq!(preview.report.conflicts.len(), 1);
+        assert_eq!(preview.duplicates.len(), 1);
+        assert_eq!(preview.duplicates[0].0.address, 0x8030);
+        assert_eq!(preview.duplicates[0].1, "$00:$8020");
+        assert!(!db.bank.contains_key(&2));
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use regex::Regex;
+
+use crate::disasm::trace::{self, Proposal};
+use crate::disasm::{bank_data, prg_bank_count, OPCODES};
+use crate::mapper::MapperModel;
+use crate::models::{DisassemblyInfo, RegionInfo};
+use crate::rom::RomImage;
+
+// What a trace log says ran, by bank.
+#[derive(Debug, Clone, Default)]
+pub struct TraceLog {
+    // Executions of each instruction, by bank and address.
+    pub hits: BTreeMap<u8, BTreeMap<u16, u64>>,
+    // Code regions for executed bytes that no region covers yet.
+    pub proposals: Vec<Proposal>,
+    // Lines that could not be placed, and executed bytes in data regions.
+    pub warnings: Vec<String>,
+    // Instruction lines read, and how many of them were placed in a bank.
+    pub lines: u64,
+    pub placed: u64,
+}
+
+// Read an FCEUX or Mesen trace log. Each line holding an instruction starts
+// with its address, optionally with the bank (`$07:C000:A2 05 LDX #$05` or
+// `C000  A2 05  LDX #$05`), followed by the instruction's bytes; registers
+// and anything else on the line are ignored. The bank is taken from the log
+// when the bytes there match, and otherwise is the one bank the database
+// maps at that address whose bytes match, preferring the bank of the line
+// before. Lines without bytes can only be placed at addresses a single bank
+// can occupy.
+pub fn read_log(db: &DisassemblyInfo, rom: &RomImage, source: &str) -> TraceLog {
+    let line_re = Regex::new(r"(?i)(?:^|\s)\$?(?:([0-9A-F]{2}):)?([0-9A-F]{4})(?::\s*|\s+)(\$?[0-9A-F]{2}(?:\s\$?[0-9A-F]{2}){0,2})(?:\s|$)").unwrap();
+    let pc_re = Regex::new(r"(?i)^\s*\$?(?:([0-9A-F]{2}):)?([0-9A-F]{4})(?::|\s|$)").unwrap();
+    let mapper = MapperModel::new(db);
+    let window = db.mapper_window_size as u32 * 1024;
+    let banks: Vec<(u8, u32, &[u8])> =
+        (0..prg_bank_count(db, rom)).map(|id| (id, mapper.base_address(id) as u32, bank_data(db, rom, id))).collect();
+
+    let mut log = TraceLog::default();
+    let mut code: BTreeMap<u8, BTreeSet<u16>> = BTreeMap::new();
+    let mut outside = 0u64;
+    let mut ambiguous: BTreeMap<u16, (u64, Vec<u8>)> = BTreeMap::new();
+    let mut mismatched: BTreeMap<u16, u64> = BTreeMap::new();
+    let mut previous: Option<u8> = None;
+
+    for line in source.lines() {
+        let (bank, pc, bytes) = match line_re.captures(line) {
+            Some(caps) => {
+                let bytes = caps[3].split_whitespace().filter_map(|b| u8::from_str_radix(b.trim_start_matches('$'), 16).ok()).collect();
+                (caps.get(1).map(|m| m.as_str().to_string()), caps[2].to_string(), bytes)
+            }
+            None => match pc_re.captures(line) {
+                Some(caps) => (caps.get(1).map(|m| m.as_str().to_string()), caps[2].to_string(), Vec::new()),
+                None => continue,
+            },
+        };
+        let bank = bank.and_then(|b| u8::from_str_radix(&b, 16).ok());
+        let Ok(pc) = u16::from_str_radix(&pc, 16) else { continue };
+        log.lines += 1;
+        if pc < 0x8000 {
+            outside += 1;
+            continue;
+        }
+
+        // Banks mapped at `pc` whose bytes there match the log.
+        let fits = |&&(_, base, data): &&(u8, u32, &[u8])| {
+            let offset = (pc as u32).wrapping_sub(base) as usize;
+            (pc as u32) >= base && (pc as u32) < base + window && offset < data.len() && data[offset..].starts_with(&bytes[..bytes.len().min(data.len() - offset)])
+        };
+        let matches: Vec<u8> = banks.iter().filter(fits).map(|&(id, _, _)| id).collect();
+        let chosen = if bank.is_some_and(|b| matches.contains(&b)) {
+            bank
+        } else if matches.len() == 1 {
+            Some(matches[0])
+        } else if previous.is_some_and(|p| matches.contains(&p)) {
+            previous
+        } else {
+            None
+        };
+        let Some(bank_id) = chosen else {
+            if matches.is_empty() {
+                *mismatched.entry(pc).or_default() += 1;
+            } else {
+                let entry = ambiguous.entry(pc).or_insert_with(|| (0, matches));
+                entry.0 += 1;
+            }
+            continue;
+        };
+        previous = Some(bank_id);
+
+        let (_, base, data) = banks[bank_id as usize];
+        let offset = (pc as u32 - base) as usize;
+        let Some(instr) = &OPCODES[data[offset] as usize] else {
+            *mismatched.entry(pc).or_default() += 1;
+            continue;
+        };
+        log.placed += 1;
+        *log.hits.entry(bank_id).or_default().entry(pc).or_default() += 1;
+        let end = (pc as u32 + instr.mode.operand_length() as u32).min(base + data.len() as u32 - 1);
+        code.entry(bank_id).or_default().extend((pc as u32..=end).map(|a| a as u16));
+    }
+
+    if outside > 0 {
+        log.warnings.push(format!("{} line(s) ran outside PRG ROM (RAM or registers) and were skipped", outside));
+    }
+    for (pc, (count, candidates)) in ambiguous {
+        let candidates = candidates.iter().map(|b| format!("${:02X}", b)).collect::<Vec<_>>().join(", ");
+        log.warnings.push(format!("${:04X}: {} line(s) could be in bank {}; skipped", pc, count, candidates));
+    }
+    for (pc, count) in mismatched {
+        log.warnings.push(format!("${:04X}: {} line(s) match no bank's bytes; skipped", pc, count));
+    }
+
+    // Executed bytes in a data region are reported rather than proposed.
+    for (&bank_id, bytes) in code.iter_mut() {
+        let Some(bank) = db.bank.get(&bank_id) else { continue };
+        for region in bank.region.iter().filter(|r| !matches!(r, RegionInfo::Code(_))) {
+            if let Some(&addr) = bytes.iter().find(|&&a| region.contains(a)) {
+                log.warnings.push(format!("${:02X}:${:04X}: executed, but inside {}", bank_id, addr, region.label()));
+                bytes.retain(|&a| !region.contains(a));
+            }
+        }
+    }
+    log.proposals = trace::propose(db, code);
+    log
+}
+
+// Add a log's hit counts to the database's coverage.
+pub fn record_hits(db: &mut DisassemblyInfo, hits: &BTreeMap<u8, BTreeMap<u16, u64>>) {
+    for (&bank_id, counts) in hits {
+        let coverage = db.coverage.entry(bank_id).or_default();
+        for (&addr, &count) in counts {
+            *coverage.entry(addr).or_default() += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A UxROM project with four 16K banks: `LDA #$01` at $8000 of banks 0
+    // and 2, `LDA #$02` at $8000 of bank 1, and `LDX #$05 / DEX` at $C000
+    // of the fixed bank 3.
+    fn setup() -> (DisassemblyInfo, RomImage) {
+        let db = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {},
+            "bank": {"3": {"region": [{"Bytes": {"start": 0xC010, "end": 0xC01F}}], "address": {}}},
+            "mapper_window_size": 16,
+            "mapper": 2,
+            "prg_rom_size": 0x10000,
+        }))
+        .unwrap();
+        let mut data = vec![b'N', b'E', b'S', 0x1A, 4, 0, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 0x10000, 0xFF);
+        for (bank, code) in [(0, &[0xA9, 0x01][..]), (1, &[0xA9, 0x02]), (2, &[0xA9, 0x01]), (3, &[0xA2, 0x05, 0xCA])] {
+            data[16 + bank * 0x4000..][..code.len()].copy_from_slice(code);
+        }
+        data[16 + 3 * 0x4000 + 0x10] = 0xEA;
+        (db, RomImage::parse(data).unwrap())
+    }
+
+    #[test]
+    fn fceux_log() {
+        let (db, rom) = setup();
+        let log = read_log(&db, &rom, "\
+$03:C000:A2 05     LDX #$05                    A:00 X:00 Y:00 S:FD P:nvubdIzc
+$03:C002:CA        DEX                         A:00 X:05 Y:00 S:FD P:nvubdIzc
+$00:8000:A9 01     LDA #$01                    A:00 X:04 Y:00 S:FD P:nvubdIzc
+$02:8000:A9 01     LDA #$01                    A:01 X:04 Y:00 S:FD P:nvubdIzc
+$01:8000:A9 01     LDA #$01                    A:01 X:04 Y:00 S:FD P:nvubdIzc
+");
+        assert_eq!((log.lines, log.placed), (5, 5));
+        assert_eq!(log.hits[&3], BTreeMap::from([(0xC000, 1), (0xC002, 1)]));
+        assert_eq!(log.hits[&0][&0x8000], 1);
+        // Bank 1 does not hold those bytes, so the last line goes to the
+        // bank of the line before.
+        assert_eq!(log.hits[&2][&0x8000], 2);
+        assert!(!log.hits.contains_key(&1));
+        assert!(log.warnings.is_empty());
+        let proposals: Vec<_> = log.proposals.iter().map(|p| (p.bank, p.range.clone())).collect();
+        assert_eq!(proposals, [(0, 0x8000..=0x8001), (2, 0x8000..=0x8001), (3, 0xC000..=0xC002)]);
+    }
+
+    #[test]
+    fn mesen_log() {
+        let (db, rom) = setup();
+        let log = read_log(&db, &rom, "\
+8000  A9 02     LDA #$02                        A:00 X:00 Y:00 P:24 SP:FD
+C000  $A2 $05   LDX #$05                        A:02 X:00 Y:00 P:24 SP:FD
+C002
+8000  A9 01     LDA #$01                        A:02 X:05 Y:00 P:24 SP:FD
+C010  EA        NOP                             A:02 X:04 Y:00 P:24 SP:FD
+0300  A9 00     LDA #$00                        A:02 X:04 Y:00 P:24 SP:FD
+9000  12 34     KIL
+");
+        assert_eq!((log.lines, log.placed), (7, 4));
+        // Bare addresses are placed where only one bank can be.
+        assert_eq!(log.hits[&3].keys().copied().collect::<Vec<_>>(), [0xC000, 0xC002, 0xC010]);
+        assert_eq!(log.hits[&1][&0x8000], 1);
+        assert_eq!(
+            log.warnings,
+            [
+                "1 line(s) ran outside PRG ROM (RAM or registers) and were skipped",
+                "$8000: 1 line(s) could be in bank $00, $02; skipped",
+                "$9000: 1 line(s) match no bank's bytes; skipped",
+                "$03:$C010: executed, but inside Bytes $C010-$C01F",
+            ]
+        );
+        assert!(log.proposals.iter().all(|p| !p.range.contains(&0xC010)));
+    }
+
+    #[test]
+    fn previous_bank_breaks_ties() {
+        let (db, rom) = setup();
+        let log = read_log(&db, &rom, "$02:8000:A9 01\n8000  A9 01\n");
+        assert_eq!(log.hits[&2][&0x8000], 2);
+        assert!(log.warnings.is_empty());
+    }
+
+    #[test]
+    fn malformed_lines_are_ignored() {
+        let (db, rom) = setup();
+        let log = read_log(&db, &rom, "FCEUX 2.6.4 - Trace Log File\n\n$ZZ:C0\n----\nframe 12\n\u{e9}\u{e9}\u{e9}\n$FF:C000:A2 05\n");
+        // Only the last line has an address; its bank does not exist, so the
+        // bytes place it.
+        assert_eq!((log.lines, log.placed), (1, 1));
+        assert_eq!(log.hits[&3][&0xC000], 1);
+
+        let mut db = db;
+        record_hits(&mut db, &log.hits);
+        record_hits(&mut db, &log.hits);
+        assert_eq!(db.coverage[&3][&0xC000], 2);
+    }
+}
@@ -0,0 +1,11 @@
+pub mod models;
+pub mod mapper;
+pub mod rom;
+pub mod disasm;
+pub mod emu;
+pub mod database;
+pub mod export;
+pub mod import;
+pub mod search;
+pub mod history;
+pub mod app;
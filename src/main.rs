@@ -1,11 +1,6 @@
 use leptos::prelude::*;
 
-mod models;
-mod disasm;
-mod database;
-mod app;
-
-use crate::app::App;
+use docassembler::app::App;
 
 fn main() {
     console_error_panic_hook::set_once();
@@ -0,0 +1,431 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::models::DisassemblyInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowKind {
+    // Any bank may be switched in.
+    Switchable,
+    // Hardwired to a window-sized bank counted from the start (>= 0) or the
+    // end (< 0) of PRG ROM.
+    Fixed(i16),
+}
+
+// A range of CPU address space that the mapper fills with PRG ROM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrgWindow {
+    pub range: RangeInclusive<u16>,
+    pub kind: WindowKind,
+}
+
+impl PrgWindow {
+    fn new(start: u16, end: u16, kind: WindowKind) -> Self {
+        PrgWindow { range: start..=end, kind }
+    }
+
+    fn size(&self) -> u32 {
+        *self.range.end() as u32 - *self.range.start() as u32 + 1
+    }
+}
+
+// Where an address referenced from one bank lives.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    // A bank that is guaranteed to be mapped alongside the referencing bank.
+    Bank(u8),
+    // A switchable window: any of these banks may be mapped there.
+    Switchable(Vec<u8>),
+    // RAM, registers, or a window with no known bank.
+    NotRom,
+}
+
+impl Location {
+    pub fn banks(&self) -> &[u8] {
+        match self {
+            Location::Bank(id) => std::slice::from_ref(id),
+            Location::Switchable(ids) => ids,
+            Location::NotRom => &[],
+        }
+    }
+
+    // The bank holding the address, if only one can.
+    pub fn unique_bank(&self) -> Option<u8> {
+        match self.banks() {
+            [id] => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+// PRG bank-switching layout of a mapper, used to decide which banks can see
+// each other's addresses.
+#[derive(Debug, Clone)]
+pub struct MapperModel {
+    pub name: &'static str,
+    pub windows: Vec<PrgWindow>,
+    // Database banks switched in together as one unit (e.g. AxROM's 32K banks
+    // with a 16K mapper window size).
+    pub group_size: u8,
+    window_size: u32,
+    prg_rom_size: Option<u32>,
+    // CPU addresses each database bank occupies while it is mapped.
+    spans: BTreeMap<u8, RangeInclusive<u32>>,
+    fixed: Vec<u8>,
+}
+
+impl MapperModel {
+    pub fn new(db: &DisassemblyInfo) -> Self {
+        let window_size = db.mapper_window_size.max(1) as u32 * 1024;
+        let mode = db.mapper_prg_mode;
+        let (name, windows, unit) = match db.mapper {
+            Some(0) => ("NROM", nrom_windows(), 0),
+            Some(1) => match mode.unwrap_or(3) {
+                0 | 1 => ("MMC1 (32K)", vec![PrgWindow::new(0x8000, 0xFFFF, WindowKind::Switchable)], 32 * 1024),
+                2 => (
+                    "MMC1 (fixed $8000)",
+                    vec![
+                        PrgWindow::new(0x8000, 0xBFFF, WindowKind::Fixed(0)),
+                        PrgWindow::new(0xC000, 0xFFFF, WindowKind::Switchable),
+                    ],
+                    0,
+                ),
+                _ => ("MMC1 (fixed $C000)", uxrom_windows(), 0),
+            },
+            Some(2) => ("UxROM", uxrom_windows(), 0),
+            Some(3) => ("CNROM", nrom_windows(), 0),
+            Some(4) => match mode.unwrap_or(0) {
+                0 => (
+                    "MMC3",
+                    vec![
+                        PrgWindow::new(0x8000, 0x9FFF, WindowKind::Switchable),
+                        PrgWindow::new(0xA000, 0xBFFF, WindowKind::Switchable),
+                        PrgWindow::new(0xC000, 0xDFFF, WindowKind::Fixed(-2)),
+                        PrgWindow::new(0xE000, 0xFFFF, WindowKind::Fixed(-1)),
+                    ],
+                    0,
+                ),
+                _ => (
+                    "MMC3 (swapped)",
+                    vec![
+                        PrgWindow::new(0x8000, 0x9FFF, WindowKind::Fixed(-2)),
+                        PrgWindow::new(0xA000, 0xBFFF, WindowKind::Switchable),
+                        PrgWindow::new(0xC000, 0xDFFF, WindowKind::Switchable),
+                        PrgWindow::new(0xE000, 0xFFFF, WindowKind::Fixed(-1)),
+                    ],
+                    0,
+                ),
+            },
+            Some(7) => ("AxROM", vec![PrgWindow::new(0x8000, 0xFFFF, WindowKind::Switchable)], 32 * 1024),
+            _ => ("Generic", legacy_windows(db), 0),
+        };
+
+        let mut model = MapperModel {
+            name,
+            windows,
+            group_size: (unit / window_size).max(1) as u8,
+            window_size,
+            prg_rom_size: db.prg_rom_size,
+            spans: BTreeMap::new(),
+            fixed: Vec::new(),
+        };
+        for (&id, bank) in &db.bank {
+            let base = model.default_base(db, id);
+            model.spans.insert(id, base as u32..=base as u32 + window_size - 1);
+            // A bank placed in a fixed window must be the bank hardwired there.
+            let in_fixed_window = model.windows.iter().any(|w| w.range.contains(&base) && matches!(w.kind, WindowKind::Fixed(_)));
+            if bank.is_fixed || in_fixed_window || model.computed_fixed(id) {
+                model.fixed.push(id);
+            }
+        }
+        model
+    }
+
+    // The CPU address a bank is mapped at.
+    pub fn base_address(&self, bank_id: u8) -> u16 {
+        match self.spans.get(&bank_id) {
+            Some(span) => *span.start() as u16,
+            None => self.fixed_base(bank_id).unwrap_or(0x8000),
+        }
+    }
+
+    pub fn is_fixed(&self, bank_id: u8) -> bool {
+        self.fixed.contains(&bank_id)
+    }
+
+    // Find the bank holding `address` as seen from code running in `bank_id`.
+    pub fn locate(&self, bank_id: u8, address: u16) -> Location {
+        let address32 = address as u32;
+        if self.spans.get(&bank_id).is_some_and(|span| span.contains(&address32)) {
+            return Location::Bank(bank_id);
+        }
+        if let Some(id) = self.group(bank_id).find(|id| self.spans.get(id).is_some_and(|span| span.contains(&address32))) {
+            return Location::Bank(id);
+        }
+        let Some(window) = self.windows.iter().find(|w| w.range.contains(&address)) else {
+            return Location::NotRom;
+        };
+        match window.kind {
+            WindowKind::Fixed(_) => match self.fixed_bank_at(address) {
+                Some(id) => Location::Bank(id),
+                None => Location::NotRom,
+            },
+            WindowKind::Switchable => Location::Switchable(
+                self.spans
+                    .iter()
+                    .filter(|&(&id, span)| id != bank_id && !self.is_fixed(id) && span.contains(&address32))
+                    .map(|(&id, _)| id)
+                    .collect(),
+            ),
+        }
+    }
+
+    // Whether `other` can be mapped at the same time as `bank_id`.
+    pub fn can_coexist(&self, bank_id: u8, other: u8) -> bool {
+        if bank_id == other {
+            return false;
+        }
+        if self.group(bank_id).any(|id| id == other) || self.is_fixed(other) || self.is_fixed(bank_id) {
+            return true;
+        }
+        // Grouped banks are switched in as one unit, so banks of different
+        // groups never meet.
+        if self.group_size > 1 {
+            return false;
+        }
+        match (self.spans.get(&bank_id), self.spans.get(&other)) {
+            (Some(a), Some(b)) => a.end() < b.start() || b.end() < a.start(),
+            _ => false,
+        }
+    }
+
+    fn group(&self, bank_id: u8) -> impl Iterator<Item = u8> + use<> {
+        let size = self.group_size as u16;
+        let first = bank_id as u16 / size * size;
+        (first..(first + size).min(256)).map(|id| id as u8).filter(move |&id| id != bank_id)
+    }
+
+    fn fixed_bank_at(&self, address: u16) -> Option<u8> {
+        let address32 = address as u32;
+        let flagged = self.fixed.iter().find(|id| self.spans.get(id).is_some_and(|span| span.contains(&address32)));
+        if let Some(&id) = flagged {
+            return Some(id);
+        }
+        let window = self.windows.iter().find(|w| w.range.contains(&address))?;
+        let offset = self.window_offset(window)? + (address32 - *window.range.start() as u32);
+        u8::try_from(offset / self.window_size).ok()
+    }
+
+    // PRG ROM offset of the bank hardwired into a fixed window.
+    fn window_offset(&self, window: &PrgWindow) -> Option<u32> {
+        match window.kind {
+            WindowKind::Fixed(n) if n >= 0 => Some(n as u32 * window.size()),
+            WindowKind::Fixed(n) => self.prg_rom_size?.checked_sub(n.unsigned_abs() as u32 * window.size()),
+            WindowKind::Switchable => None,
+        }
+    }
+
+    // Where a fixed window holds this bank, its CPU address. NROM-128 mirrors
+    // its only bank into both windows; the last one holds the vectors.
+    fn fixed_base(&self, bank_id: u8) -> Option<u16> {
+        let start = bank_id as u32 * self.window_size;
+        self.windows.iter().rev().find_map(|w| {
+            let offset = self.window_offset(w)?;
+            (start >= offset && start < offset + w.size()).then(|| (*w.range.start() as u32 + start - offset) as u16)
+        })
+    }
+
+    fn computed_fixed(&self, bank_id: u8) -> bool {
+        self.fixed_base(bank_id).is_some()
+    }
+
+    fn default_base(&self, db: &DisassemblyInfo, bank_id: u8) -> u16 {
+        let Some(bank) = db.bank.get(&bank_id) else {
+            return 0x8000;
+        };
+        if let Some(addr) = bank.mapped_at {
+            return addr;
+        }
+        if bank.is_fixed {
+            if let Some(fixed_range) = &db.mapper_fixed_range {
+                return *fixed_range.start();
+            }
+            let last_fixed = self.windows.iter().rev().find(|w| matches!(w.kind, WindowKind::Fixed(_)));
+            if let Some(window) = last_fixed {
+                return *window.range.start();
+            }
+        }
+        if let Some(base) = self.fixed_base(bank_id) {
+            return base;
+        }
+        if self.group_size > 1
+            && let Some(window) = self.windows.first()
+        {
+            let index = bank_id as u32 % self.group_size as u32;
+            return (*window.range.start() as u32 + index * self.window_size) as u16;
+        }
+        // The banks hardwired into fixed windows were placed above, so any
+        // other bank goes in a switchable window.
+        let switchable = |addr: u16| self.windows.iter().find(|w| w.range.contains(&addr)).is_none_or(|w| w.kind == WindowKind::Switchable);
+        let first_switchable = self.windows.iter().find(|w| w.kind == WindowKind::Switchable).map_or(0x8000, |w| *w.range.start());
+        match bank.region.iter().map(|r| *r.range().start()).min() {
+            Some(start) => {
+                let base = ((start as u32 / self.window_size) * self.window_size) as u16;
+                if switchable(base) { base } else { first_switchable }
+            }
+            None => first_switchable,
+        }
+    }
+}
+
+fn nrom_windows() -> Vec<PrgWindow> {
+    vec![
+        PrgWindow::new(0x8000, 0xBFFF, WindowKind::Fixed(0)),
+        PrgWindow::new(0xC000, 0xFFFF, WindowKind::Fixed(-1)),
+    ]
+}
+
+fn uxrom_windows() -> Vec<PrgWindow> {
+    vec![
+        PrgWindow::new(0x8000, 0xBFFF, WindowKind::Switchable),
+        PrgWindow::new(0xC000, 0xFFFF, WindowKind::Fixed(-1)),
+    ]
+}
+
+// Without a known mapper: `mapper_fixed_range` holds the fixed bank and the
+// rest of $8000-$FFFF is switchable.
+fn legacy_windows(db: &DisassemblyInfo) -> Vec<PrgWindow> {
+    let Some(fixed) = db.mapper_fixed_range.clone() else {
+        return vec![PrgWindow::new(0x8000, 0xFFFF, WindowKind::Switchable)];
+    };
+    let mut windows = Vec::new();
+    if *fixed.start() > 0x8000 {
+        windows.push(PrgWindow::new(0x8000, fixed.start() - 1, WindowKind::Switchable));
+    }
+    windows.push(PrgWindow { range: fixed.clone(), kind: WindowKind::Fixed(-1) });
+    if *fixed.end() < 0xFFFF {
+        windows.push(PrgWindow::new(fixed.end() + 1, 0xFFFF, WindowKind::Switchable));
+    }
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A project for `mapper` with `banks` empty banks of `window_size` K,
+    // with `extra` fields set by JSON pointer.
+    fn model(mapper: u16, mode: Option<u8>, window_size: u8, banks: u8, extra: serde_json::Value) -> MapperModel {
+        let mut db = serde_json::json!({
+            "name": "test",
+            "title": "Test",
+            "global": {},
+            "bank": (0..banks).map(|id| (id.to_string(), serde_json::json!({"region": [], "address": {}}))).collect::<serde_json::Map<_, _>>(),
+            "mapper_window_size": window_size,
+            "mapper": mapper,
+            "mapper_prg_mode": mode,
+            "prg_rom_size": banks as u32 * window_size as u32 * 1024,
+        });
+        for (key, value) in extra.as_object().unwrap() {
+            let (parent, field) = key.rsplit_once('/').unwrap();
+            db.pointer_mut(parent).unwrap().as_object_mut().unwrap().insert(field.to_string(), value.clone());
+        }
+        MapperModel::new(&serde_json::from_value(db).unwrap())
+    }
+
+    #[test]
+    fn nrom() {
+        for mapper in [0, 3] {
+            let small = model(mapper, None, 16, 1, serde_json::json!({}));
+            assert_eq!(small.base_address(0), 0xC000);
+            assert!(small.is_fixed(0));
+            assert_eq!(small.locate(0, 0xFFFC), Location::Bank(0));
+            assert_eq!(small.locate(0, 0x8000), Location::Bank(0));
+            assert_eq!(small.locate(0, 0x6000), Location::NotRom);
+
+            let large = model(mapper, None, 16, 2, serde_json::json!({}));
+            assert_eq!((large.base_address(0), large.base_address(1)), (0x8000, 0xC000));
+            assert_eq!(large.locate(0, 0xC000), Location::Bank(1));
+            assert_eq!(large.locate(1, 0xBFFF), Location::Bank(0));
+            assert!(large.can_coexist(0, 1));
+        }
+    }
+
+    #[test]
+    fn uxrom_and_mmc1_fixed_c000() {
+        for (mapper, mode) in [(2, None), (1, None), (1, Some(3))] {
+            let m = model(mapper, mode, 16, 8, serde_json::json!({}));
+            assert_eq!((m.base_address(0), m.base_address(7)), (0x8000, 0xC000));
+            assert!(m.is_fixed(7) && !m.is_fixed(0));
+            assert_eq!(m.locate(0, 0x9000), Location::Bank(0));
+            assert_eq!(m.locate(0, 0xC123), Location::Bank(7));
+            assert_eq!(m.locate(7, 0x8000), Location::Switchable((0..7).collect()));
+            assert_eq!(m.locate(0, 0x8000), Location::Bank(0));
+            assert!(m.can_coexist(0, 7) && m.can_coexist(7, 3));
+            assert!(!m.can_coexist(0, 1) && !m.can_coexist(0, 0));
+        }
+    }
+
+    #[test]
+    fn mmc1_fixed_8000() {
+        let m = model(1, Some(2), 16, 4, serde_json::json!({
+            "/bank/1/region": [{"Code": {"start": 0xC000, "end": 0xC0FF}}],
+            "/bank/2/region": [{"Code": {"start": 0xC000, "end": 0xC0FF}}],
+        }));
+        assert_eq!(m.name, "MMC1 (fixed $8000)");
+        assert_eq!((m.base_address(0), m.base_address(1)), (0x8000, 0xC000));
+        assert!(m.is_fixed(0) && !m.is_fixed(1));
+        assert_eq!(m.locate(1, 0x8000), Location::Bank(0));
+        assert_eq!(m.base_address(3), 0xC000);
+        assert_eq!(m.locate(0, 0xC000), Location::Switchable(vec![1, 2, 3]));
+        assert!(m.can_coexist(1, 0) && !m.can_coexist(1, 2));
+    }
+
+    #[test]
+    fn mmc1_32k_and_axrom() {
+        for (mapper, mode) in [(1, Some(0)), (7, None)] {
+            let m = model(mapper, mode, 16, 4, serde_json::json!({}));
+            assert_eq!(m.group_size, 2);
+            assert_eq!((m.base_address(2), m.base_address(3)), (0x8000, 0xC000));
+            assert!(!(0..4).any(|id| m.is_fixed(id)));
+            assert_eq!(m.locate(2, 0xC000), Location::Bank(3));
+            assert_eq!(m.locate(3, 0x8000), Location::Bank(2));
+            assert!(m.can_coexist(2, 3) && !m.can_coexist(1, 2));
+        }
+    }
+
+    #[test]
+    fn mmc3() {
+        let region = serde_json::json!({"/bank/1/region": [{"Code": {"start": 0xA000, "end": 0xA0FF}}]});
+        let m = model(4, None, 8, 16, region.clone());
+        assert_eq!(m.name, "MMC3");
+        assert_eq!((m.base_address(14), m.base_address(15)), (0xC000, 0xE000));
+        assert!(m.is_fixed(14) && m.is_fixed(15) && !m.is_fixed(0));
+        assert_eq!(m.locate(0, 0xC000), Location::Bank(14));
+        assert_eq!(m.locate(0, 0xFFFA), Location::Bank(15));
+        assert_eq!(m.locate(0, 0xA000), Location::Switchable(vec![1]));
+        assert!(m.can_coexist(0, 1) && !m.can_coexist(0, 2));
+
+        let swapped = model(4, Some(1), 8, 16, region);
+        assert_eq!(swapped.name, "MMC3 (swapped)");
+        assert_eq!((swapped.base_address(14), swapped.base_address(15)), (0x8000, 0xE000));
+        assert_eq!(swapped.base_address(0), 0xA000);
+        assert!(!swapped.is_fixed(0));
+        assert_eq!(swapped.locate(0, 0x8000), Location::Bank(14));
+        assert_eq!(swapped.locate(14, 0xC000), Location::Switchable(Vec::new()));
+    }
+
+    #[test]
+    fn generic_fixed_range() {
+        let m = model(999, None, 16, 4, serde_json::json!({
+            "/mapper_fixed_range": {"start": 0xC000, "end": 0xFFFF},
+            "/bank/3/is_fixed": true,
+        }));
+        assert_eq!(m.name, "Generic");
+        assert_eq!(m.base_address(3), 0xC000);
+        assert!(m.is_fixed(3));
+        assert_eq!(m.locate(0, 0xC000), Location::Bank(3));
+        assert_eq!(m.locate(3, 0x8000), Location::Switchable(vec![0, 1, 2]));
+        assert!(m.can_coexist(0, 3) && !m.can_coexist(0, 1));
+    }
+}
@@ -2,14 +2,156 @@ use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::ops::RangeInclusive;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum RegionInfo {
     Code(RangeInclusive<u16>),
     Bytes(RangeInclusive<u16>),
     Words(RangeInclusive<u16>),
+    // Strings decoded through DisassemblyInfo::text_table.
+    Text(RangeInclusive<u16>),
+    // Consecutive records laid out as DisassemblyInfo::structs[name].
+    Struct {
+        range: RangeInclusive<u16>,
+        name: String,
+    },
+    // Pointer table stored as parallel arrays of low and high bytes. Entry i
+    // is `lo[i] | hi[i] << 8`, and `offset` is added to get the target (1 for
+    // tables entered with RTS).
+    SplitPointers {
+        lo: RangeInclusive<u16>,
+        hi: RangeInclusive<u16>,
+        #[serde(default)]
+        offset: i16,
+    },
+}
+
+impl RegionInfo {
+    // The range the region starts with (the low bytes of a split table).
+    pub fn range(&self) -> &RangeInclusive<u16> {
+        match self {
+            RegionInfo::Code(r) | RegionInfo::Bytes(r) | RegionInfo::Words(r) | RegionInfo::Text(r) => r,
+            RegionInfo::Struct { range, .. } => range,
+            RegionInfo::SplitPointers { lo, .. } => lo,
+        }
+    }
+
+    // Every range the region covers.
+    pub fn ranges(&self) -> Vec<&RangeInclusive<u16>> {
+        match self {
+            RegionInfo::SplitPointers { lo, hi, .. } => vec![lo, hi],
+            other => vec![other.range()],
+        }
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.ranges().iter().any(|r| r.contains(&address))
+    }
+
+    // The same kind of region over another range. Only kinds that cover a
+    // single range with no layout of their own can be cut this way.
+    pub fn with_range(&self, range: RangeInclusive<u16>) -> Option<RegionInfo> {
+        match self {
+            RegionInfo::Code(_) => Some(RegionInfo::Code(range)),
+            RegionInfo::Bytes(_) => Some(RegionInfo::Bytes(range)),
+            RegionInfo::Words(_) => Some(RegionInfo::Words(range)),
+            RegionInfo::Text(_) => Some(RegionInfo::Text(range)),
+            RegionInfo::Struct { .. } | RegionInfo::SplitPointers { .. } => None,
+        }
+    }
+
+    // Short description for the UI, e.g. `Code $8000-$80FF`.
+    pub fn label(&self) -> String {
+        let span = |r: &RangeInclusive<u16>| format!("${:04X}-${:04X}", r.start(), r.end());
+        match self {
+            RegionInfo::Code(r) => format!("Code {}", span(r)),
+            RegionInfo::Bytes(r) => format!("Bytes {}", span(r)),
+            RegionInfo::Words(r) => format!("Words {}", span(r)),
+            RegionInfo::Text(r) => format!("Text {}", span(r)),
+            RegionInfo::Struct { range, name } => format!("Struct {} {}", name, span(range)),
+            RegionInfo::SplitPointers { lo, hi, .. } => format!("SplitPointers {} / {}", span(lo), span(hi)),
+        }
+    }
+}
+
+// Record layout for Struct regions. Each record is written as one line,
+// an invocation of a macro with the struct's name.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct StructDef {
+    pub fields: Vec<StructField>,
+}
+
+impl StructDef {
+    // Record size in bytes.
+    pub fn size(&self) -> u16 {
+        self.fields.iter().map(|f| f.kind.size()).sum()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StructField {
+    pub name: String,
+    pub kind: FieldKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum FieldKind {
+    Byte,
+    Word,
+    // A word naming a label.
+    Pointer,
+    // A byte with named values, exported as constants.
+    Enum(BTreeMap<u8, String>),
+}
+
+impl FieldKind {
+    pub fn size(&self) -> u16 {
+        match self {
+            FieldKind::Byte | FieldKind::Enum(_) => 1,
+            FieldKind::Word | FieldKind::Pointer => 2,
+        }
+    }
+}
+
+// Character table (TBL) for Text regions: the text each byte stands for.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TextTable {
+    pub chars: BTreeMap<u8, String>,
+    // Bytes that end a string (`/HH=` entries in a TBL file).
+    #[serde(default)]
+    pub terminators: Vec<u8>,
+}
+
+// Layout of the pointer table that follows a `JSR` to a dispatcher routine.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JumpTableInfo {
+    // Bytes per entry: the pointer, then any argument bytes.
+    #[serde(default = "default_entry_width")]
+    pub entry_width: u8,
+    // All low bytes first, then all high bytes (needs `count`).
+    #[serde(default)]
+    pub split: bool,
+    // Number of entries. Without it the table ends at the next label or at a
+    // pointer outside ROM.
+    #[serde(default)]
+    pub count: Option<u16>,
+    // Added to each stored pointer to get the target (1 for RTS-style tables).
+    #[serde(default)]
+    pub offset: i16,
+}
+
+fn default_entry_width() -> u8 {
+    2
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+impl Default for JumpTableInfo {
+    fn default() -> Self {
+        JumpTableInfo { entry_width: 2, split: false, count: None, offset: 0 }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct AnnotationInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub symbol: Option<String>,
@@ -17,16 +159,51 @@ pub struct AnnotationInfo {
     pub comment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub block_comment: Option<String>,
+    // Set when the routine here is a jump-table dispatcher.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispatcher: Option<JumpTableInfo>,
 }
 
 impl AnnotationInfo {
     pub fn is_empty(&self) -> bool {
-        self.symbol.is_none() && self.comment.is_none() && self.block_comment.is_none()
+        self.symbol.is_none() && self.comment.is_none() && self.block_comment.is_none() && self.dispatcher.is_none()
+    }
+
+    // The block comment and the line comment as one text, the way emulator
+    // debuggers keep a single comment per address.
+    pub fn joined_comment(&self) -> Option<String> {
+        let text = [&self.block_comment, &self.comment].into_iter().flatten().map(|c| c.trim_end()).collect::<Vec<_>>().join("\n");
+        (!text.is_empty()).then_some(text)
     }
 }
 
 pub type SectionInfo = BTreeMap<u16, AnnotationInfo>;
 
+// The assembler a project targets, which decides what a valid symbol name is.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum Assembler {
+    #[default]
+    Ca65,
+    Asm6,
+    Nesasm,
+}
+
+impl Assembler {
+    pub const ALL: [Assembler; 3] = [Assembler::Ca65, Assembler::Asm6, Assembler::Nesasm];
+
+    pub fn is_ca65(&self) -> bool {
+        *self == Assembler::Ca65
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Assembler::Ca65 => "ca65",
+            Assembler::Asm6 => "asm6",
+            Assembler::Nesasm => "NESASM",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BankInfo {
     // Optional title for the bank (e.g. "West Hyrule")
@@ -40,6 +217,63 @@ pub struct BankInfo {
     pub address: SectionInfo,
     // The CPU address where this bank is typically mapped.
     pub mapped_at: Option<u16>,
+    // Extra addresses where the code tracer starts, besides the vectors.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entry_points: Vec<u16>,
+}
+
+impl BankInfo {
+    // Mark the range(s) of `region` as that region: they are cut out of the
+    // regions already there, and the new region is merged with neighbours of
+    // the same kind.
+    pub fn mark_region(&mut self, region: RegionInfo) {
+        for range in region.ranges().into_iter().cloned().collect::<Vec<_>>() {
+            self.clear_region(range);
+        }
+        self.region.push(region);
+        self.region.sort_by_key(|r| *r.range().start());
+        self.merge_regions();
+    }
+
+    // Remove a range from the bank's regions, leaving it to be disassembled
+    // as bytes. Code, Bytes, Words and Text regions are split around it;
+    // Struct and SplitPointers regions that overlap it are removed whole.
+    pub fn clear_region(&mut self, range: RangeInclusive<u16>) {
+        let (start, end) = (*range.start(), *range.end());
+        let mut kept = Vec::new();
+        for region in self.region.drain(..) {
+            if !region.ranges().iter().any(|r| *r.start() <= end && start <= *r.end()) {
+                kept.push(region);
+                continue;
+            }
+            let r = region.range().clone();
+            if *r.start() < start {
+                kept.extend(region.with_range(*r.start()..=start - 1));
+            }
+            if *r.end() > end {
+                kept.extend(region.with_range(end + 1..=*r.end()));
+            }
+        }
+        self.region = kept;
+        self.region.sort_by_key(|r| *r.range().start());
+    }
+
+    // Join adjacent or overlapping regions of the same single-range kind.
+    fn merge_regions(&mut self) {
+        let mut merged: Vec<RegionInfo> = Vec::new();
+        for region in self.region.drain(..) {
+            if let Some(last) = merged.last_mut()
+                && std::mem::discriminant(last) == std::mem::discriminant(&region)
+                && *last.range().end() as u32 + 1 >= *region.range().start() as u32
+                && let Some(joined) = last.with_range(*last.range().start()..=*last.range().end().max(region.range().end()))
+            {
+                *last = joined;
+                continue;
+            }
+            merged.push(region);
+        }
+        self.region = merged;
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -57,6 +291,37 @@ pub struct DisassemblyInfo {
     pub mapper_window_size: u8,
     // The CPU address where this bank is mapped (e.g. 0xC000..=0xFFFF)
     pub mapper_fixed_range: Option<RangeInclusive<u16>>,
+    // iNES mapper number the project was made for, checked when a ROM is loaded.
+    // Selects the bank-switching model (NROM, MMC1, UxROM, CNROM, MMC3, AxROM);
+    // other mappers fall back to mapper_fixed_range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapper: Option<u16>,
+    // PRG banking mode for mappers that have one: MMC1 control bits 2-3
+    // (0/1: 32K, 2: $8000 fixed, 3: $C000 fixed) or MMC3 bit 6.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapper_prg_mode: Option<u8>,
+    // Expected PRG ROM size in bytes, checked when a ROM is loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prg_rom_size: Option<u32>,
+    // CRC32 and SHA-1 (hex) of the ROM this project was made for, excluding
+    // the 16-byte header. Recorded the first time a ROM is opened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rom_crc32: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rom_sha1: Option<String>,
+    // Character table used to render Text regions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_table: Option<TextTable>,
+    // Record layouts used by Struct regions, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub structs: BTreeMap<String, StructDef>,
+    // Assembler whose identifier rules symbol names are checked against.
+    #[serde(default, skip_serializing_if = "Assembler::is_ca65")]
+    pub assembler: Assembler,
+    // How often the instruction at each address of a bank ran, summed over
+    // the imported emulator trace logs.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub coverage: BTreeMap<u8, BTreeMap<u16, u64>>,
 }
 
 impl DisassemblyInfo {
@@ -103,4 +368,77 @@ pub struct DisassemblyLine {
     pub block_comment: Option<String>,
     pub target_bank: Option<u8>,
     pub target_address: Option<u16>,
+    // Decoded text of a Text region line, or the named fields of a Struct
+    // record, for display and search.
+    pub text: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RegionInfo::*;
+
+    fn bank(region: Vec<RegionInfo>) -> BankInfo {
+        BankInfo { title: None, is_fixed: false, region, address: BTreeMap::new(), mapped_at: None, entry_points: Vec::new() }
+    }
+
+    #[test]
+    fn clear_partial_overlaps() {
+        let mut b = bank(vec![Code(0x8000..=0x80FF), Bytes(0x8100..=0x81FF)]);
+        b.clear_region(0x80F0..=0x810F);
+        assert_eq!(b.region, [Code(0x8000..=0x80EF), Bytes(0x8110..=0x81FF)]);
+
+        // Overlapping the start and the end of a region.
+        let mut b = bank(vec![Text(0x8010..=0x801F)]);
+        b.clear_region(0x8000..=0x8013);
+        assert_eq!(b.region, [Text(0x8014..=0x801F)]);
+        b.clear_region(0x801C..=0x8FFF);
+        assert_eq!(b.region, [Text(0x8014..=0x801B)]);
+
+        // At the ends of the address space.
+        let mut b = bank(vec![Words(0x0000..=0xFFFF)]);
+        b.clear_region(0x0000..=0x0001);
+        b.clear_region(0xFFFE..=0xFFFF);
+        assert_eq!(b.region, [Words(0x0002..=0xFFFD)]);
+    }
+
+    #[test]
+    fn clear_inside_and_around() {
+        let mut b = bank(vec![Code(0x8000..=0x80FF)]);
+        b.clear_region(0x8010..=0x801F);
+        assert_eq!(b.region, [Code(0x8000..=0x800F), Code(0x8020..=0x80FF)]);
+        b.clear_region(0x7000..=0x9000);
+        assert!(b.region.is_empty());
+
+        // Regions with a layout go whole, including a split table touched
+        // only by its high bytes.
+        let mut b = bank(vec![
+            Struct { range: 0x8000..=0x800F, name: "Enemy".to_string() },
+            SplitPointers { lo: 0x8100..=0x8103, hi: 0x8200..=0x8203, offset: 1 },
+            Bytes(0x8300..=0x830F),
+        ]);
+        b.clear_region(0x800F..=0x800F);
+        b.clear_region(0x8203..=0x8203);
+        assert_eq!(b.region, [Bytes(0x8300..=0x830F)]);
+    }
+
+    #[test]
+    fn mark_merges_same_kind_neighbours() {
+        let mut b = bank(vec![Code(0x8000..=0x800F), Bytes(0x8020..=0x802F)]);
+        b.mark_region(Code(0x8010..=0x801F));
+        assert_eq!(b.region, [Code(0x8000..=0x801F), Bytes(0x8020..=0x802F)]);
+
+        // A different kind in between is cut out and both sides stay apart.
+        b.mark_region(Bytes(0x8008..=0x8017));
+        assert_eq!(b.region, [Code(0x8000..=0x8007), Bytes(0x8008..=0x8017), Code(0x8018..=0x801F), Bytes(0x8020..=0x802F)]);
+
+        // Marking over the gap joins the same kind again.
+        b.mark_region(Code(0x8004..=0x801F));
+        assert_eq!(b.region, [Code(0x8000..=0x801F), Bytes(0x8020..=0x802F)]);
+
+        // Struct records never merge.
+        b.mark_region(Struct { range: 0x8030..=0x8033, name: "S".to_string() });
+        b.mark_region(Struct { range: 0x8034..=0x8037, name: "S".to_string() });
+        assert_eq!(b.region.len(), 4);
+    }
 }
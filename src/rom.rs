@@ -0,0 +1,456 @@
+use crate::mapper::MapperModel;
+use crate::models::DisassemblyInfo;
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+// Bank ids are a u8, and the app shows global symbols as bank $FF.
+pub const MAX_BANKS: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderFormat {
+    INes,
+    Nes20,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // NES 2.0 extended console type (byte 13, low nibble).
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    // Sizes in bytes.
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub has_trainer: bool,
+    pub mirroring: Mirroring,
+    pub has_battery: bool,
+    pub console_type: ConsoleType,
+}
+
+impl RomHeader {
+    pub fn parse(header: &[u8]) -> Result<Self, String> {
+        if header.len() < HEADER_SIZE || &header[0..4] != b"NES\x1A" {
+            return Err("not an iNES ROM image (missing NES<EOF> header)".to_string());
+        }
+        let format = if header[7] & 0x0C == 0x08 { HeaderFormat::Nes20 } else { HeaderFormat::INes };
+
+        let (prg_rom_size, chr_rom_size, mapper, submapper) = match format {
+            HeaderFormat::Nes20 => (
+                nes20_rom_size(header[4], header[9] & 0x0F, 16 * 1024)?,
+                nes20_rom_size(header[5], header[9] >> 4, 8 * 1024)?,
+                (header[6] >> 4) as u16 | (header[7] & 0xF0) as u16 | ((header[8] & 0x0F) as u16) << 8,
+                header[8] >> 4,
+            ),
+            HeaderFormat::INes => {
+                // Old dumpers wrote signatures such as "DiskDude!" into bytes 7-15,
+                // which corrupts the upper mapper nibble.
+                let upper = if header[12..16].iter().all(|&b| b == 0) { header[7] & 0xF0 } else { 0 };
+                (
+                    header[4] as usize * 16 * 1024,
+                    header[5] as usize * 8 * 1024,
+                    (header[6] >> 4 | upper) as u16,
+                    0,
+                )
+            }
+        };
+
+        let mirroring = if header[6] & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if header[6] & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let console_type = match header[7] & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ if format == HeaderFormat::Nes20 => ConsoleType::Extended(header[13] & 0x0F),
+            _ => ConsoleType::Nes,
+        };
+
+        Ok(RomHeader {
+            format,
+            prg_rom_size,
+            chr_rom_size,
+            mapper,
+            submapper,
+            has_trainer: header[6] & 0x04 != 0,
+            mirroring,
+            has_battery: header[6] & 0x02 != 0,
+            console_type,
+        })
+    }
+}
+
+// NES 2.0 sizes: a 12-bit unit count, or `2^E * (MM*2+1)` bytes when the
+// upper nibble is $F.
+fn nes20_rom_size(lsb: u8, msb: u8, unit: usize) -> Result<usize, String> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .ok_or_else(|| format!("NES 2.0 ROM size 2^{} * {} is too large", exponent, multiplier))
+    } else {
+        Ok((((msb as usize) << 8) | lsb as usize) * unit)
+    }
+}
+
+// Hashes identifying a ROM revision. They skip the 16-byte header, like
+// No-Intro, so a repaired header does not change the identity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomHashes {
+    pub crc32: String,
+    pub sha1: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdentityCheck {
+    // The database has no recorded hashes yet.
+    Unbound,
+    Match,
+    // Description of the differing hashes.
+    Mismatch(String),
+}
+
+// A loaded ROM file with its header decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomImage {
+    pub header: RomHeader,
+    pub data: Vec<u8>,
+}
+
+impl RomImage {
+    pub fn parse(data: Vec<u8>) -> Result<Self, String> {
+        let header = RomHeader::parse(&data)?;
+        if header.prg_rom_size == 0 {
+            return Err("ROM header declares no PRG ROM".to_string());
+        }
+        let image = RomImage { header, data };
+        // NES 2.0 exponent sizes can add up past usize on 32-bit targets.
+        let Some(expected) = image.prg_offset().checked_add(image.header.prg_rom_size).and_then(|o| o.checked_add(image.header.chr_rom_size)) else {
+            return Err("ROM header declares sizes too large to load".to_string());
+        };
+        if image.data.len() < expected {
+            return Err(format!(
+                "ROM file is truncated: header declares {} bytes but the file has {}",
+                expected,
+                image.data.len()
+            ));
+        }
+        Ok(image)
+    }
+
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.data[0..HEADER_SIZE]
+    }
+
+    pub fn trainer(&self) -> Option<&[u8]> {
+        self.header.has_trainer.then(|| &self.data[HEADER_SIZE..HEADER_SIZE + TRAINER_SIZE])
+    }
+
+    // File offset of the first PRG ROM byte.
+    pub fn prg_offset(&self) -> usize {
+        HEADER_SIZE + if self.header.has_trainer { TRAINER_SIZE } else { 0 }
+    }
+
+    pub fn chr_offset(&self) -> usize {
+        self.prg_offset() + self.header.prg_rom_size
+    }
+
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.data[self.prg_offset()..self.chr_offset()]
+    }
+
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.data[self.chr_offset()..self.chr_offset() + self.header.chr_rom_size]
+    }
+
+    // Anything after CHR ROM (e.g. PlayChoice INST-ROM).
+    pub fn misc_rom(&self) -> &[u8] {
+        &self.data[self.chr_offset() + self.header.chr_rom_size..]
+    }
+
+    // Number of PRG banks at the given window size in KB.
+    pub fn prg_bank_count(&self, window_size: u8) -> usize {
+        let window = window_size as usize * 1024;
+        if window == 0 {
+            return 0;
+        }
+        self.header.prg_rom_size / window
+    }
+
+    // File offset of a PRG bank.
+    pub fn prg_bank_offset(&self, window_size: u8, bank_id: u8) -> usize {
+        self.prg_offset() + bank_id as usize * window_size as usize * 1024
+    }
+
+    pub fn prg_bank(&self, window_size: u8, bank_id: u8) -> &[u8] {
+        let window = window_size as usize * 1024;
+        let prg = self.prg_rom();
+        let start = bank_id as usize * window;
+        if start < prg.len() { &prg[start..(start + window).min(prg.len())] } else { &[] }
+    }
+
+    pub fn hashes(&self) -> RomHashes {
+        let body = &self.data[HEADER_SIZE..];
+        RomHashes {
+            crc32: format!("{:08X}", crc32fast::hash(body)),
+            sha1: sha1_smol::Sha1::from(body).digest().to_string().to_uppercase(),
+        }
+    }
+
+    // Compare the ROM with the hashes recorded in the database.
+    pub fn check_identity(&self, db: &DisassemblyInfo) -> IdentityCheck {
+        if db.rom_crc32.is_none() && db.rom_sha1.is_none() {
+            return IdentityCheck::Unbound;
+        }
+        let hashes = self.hashes();
+        let mut differences = Vec::new();
+        for (name, expected, actual) in [("CRC32", &db.rom_crc32, &hashes.crc32), ("SHA-1", &db.rom_sha1, &hashes.sha1)] {
+            if let Some(expected) = expected
+                && !expected.eq_ignore_ascii_case(actual)
+            {
+                differences.push(format!("{} is {}, expected {}", name, actual, expected));
+            }
+        }
+        if differences.is_empty() {
+            IdentityCheck::Match
+        } else {
+            IdentityCheck::Mismatch(format!("ROM does not match the project: {}", differences.join("; ")))
+        }
+    }
+
+    // Bind the database to this ROM revision.
+    pub fn record_identity(&self, db: &mut DisassemblyInfo) {
+        let hashes = self.hashes();
+        db.rom_crc32 = Some(hashes.crc32);
+        db.rom_sha1 = Some(hashes.sha1);
+    }
+
+    // Take the mapper number and PRG ROM size from the header where the
+    // database leaves them unset, so the mapper model and size checks apply.
+    // Returns what was filled in, and whether that changed the mapper model
+    // banks are placed by, for the user to review and save.
+    pub fn fill_unset(&self, db: &mut DisassemblyInfo) -> Option<String> {
+        let before = MapperModel::new(db).name;
+        let mut filled = Vec::new();
+        if db.mapper.is_none() {
+            db.mapper = Some(self.header.mapper);
+            filled.push(format!("mapper {}", self.header.mapper));
+        }
+        if db.prg_rom_size.is_none() {
+            db.prg_rom_size = u32::try_from(self.header.prg_rom_size).ok();
+            filled.push(format!("{}K of PRG ROM", self.header.prg_rom_size / 1024));
+        }
+        if filled.is_empty() {
+            return None;
+        }
+        let mut note = format!("took {} from the ROM header", filled.join(" and "));
+        let after = MapperModel::new(db).name;
+        if after != before {
+            note.push_str(&format!("; banks are now placed by the {} model instead of {}", after, before));
+        }
+        Some(note)
+    }
+
+    // Check that the database describes this ROM.
+    pub fn validate(&self, db: &DisassemblyInfo) -> Result<(), String> {
+        if db.mapper_window_size != 8 && db.mapper_window_size != 16 {
+            return Err(format!("mapper window size must be 8 or 16, not {}", db.mapper_window_size));
+        }
+        if let Some(mapper) = db.mapper
+            && mapper != self.header.mapper
+        {
+            return Err(format!("database is for mapper {} but the ROM uses mapper {}", mapper, self.header.mapper));
+        }
+        if let Some(size) = db.prg_rom_size
+            && size as usize != self.header.prg_rom_size
+        {
+            return Err(format!(
+                "database expects {}K of PRG ROM but the ROM has {}K",
+                size / 1024,
+                self.header.prg_rom_size / 1024
+            ));
+        }
+        let window = db.mapper_window_size as usize * 1024;
+        if !self.header.prg_rom_size.is_multiple_of(window) {
+            return Err(format!(
+                "PRG ROM size ({} bytes) is not a multiple of the {}K mapper window",
+                self.header.prg_rom_size, db.mapper_window_size
+            ));
+        }
+        let bank_count = self.prg_bank_count(db.mapper_window_size);
+        if bank_count > MAX_BANKS {
+            return Err(format!(
+                "the ROM has {} {}K banks but a database can describe at most {}",
+                bank_count, db.mapper_window_size, MAX_BANKS
+            ));
+        }
+        if let Some(&last) = db.bank.keys().next_back()
+            && last as usize >= bank_count
+        {
+            return Err(format!(
+                "database describes bank ${:02X} but the ROM only has {} {}K banks",
+                last, bank_count, db.mapper_window_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: [u8; 12]) -> Vec<u8> {
+        let mut data = b"NES\x1A".to_vec();
+        data.extend(bytes);
+        data
+    }
+
+    // A ROM with `prg` 16K and `chr` 8K units, each byte its offset into
+    // the file body, low byte only.
+    fn image(mut data: Vec<u8>, prg: usize, chr: usize) -> Vec<u8> {
+        let body = if data[6] & 0x04 != 0 { TRAINER_SIZE } else { 0 } + prg * 0x4000 + chr * 0x2000;
+        data.extend((0..body).map(|i| i as u8));
+        data
+    }
+
+    #[test]
+    fn ines() {
+        let rom = RomImage::parse(image(header([2, 1, 0x11, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]), 2, 1)).unwrap();
+        let h = &rom.header;
+        assert_eq!(h.format, HeaderFormat::INes);
+        assert_eq!((h.prg_rom_size, h.chr_rom_size), (0x8000, 0x2000));
+        assert_eq!(h.mapper, 0x41);
+        assert_eq!(h.mirroring, Mirroring::Vertical);
+        assert!(!h.has_trainer && !h.has_battery);
+        assert_eq!(h.console_type, ConsoleType::Nes);
+        assert_eq!(rom.prg_offset(), 16);
+        assert_eq!(rom.chr_offset(), 16 + 0x8000);
+        assert_eq!(rom.chr_rom().len(), 0x2000);
+        assert!(rom.misc_rom().is_empty());
+        assert_eq!(rom.prg_bank_count(8), 4);
+        assert_eq!(rom.prg_bank(16, 1)[0], 0x00);
+        assert_eq!(rom.prg_bank(16, 1)[1], 0x01);
+        assert!(rom.prg_bank(16, 2).is_empty());
+    }
+
+    #[test]
+    fn diskdude_header_ignores_upper_mapper_nibble() {
+        let mut data = header([1, 0, 0x12, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data[7..16].copy_from_slice(b"DiskDude!");
+        let rom = RomImage::parse(image(data, 1, 0)).unwrap();
+        assert_eq!(rom.header.format, HeaderFormat::INes);
+        assert_eq!(rom.header.mapper, 1);
+        assert!(rom.header.has_battery);
+    }
+
+    #[test]
+    fn trainer_offsets() {
+        let rom = RomImage::parse(image(header([1, 0, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0]), 1, 0)).unwrap();
+        assert!(rom.header.has_trainer);
+        assert_eq!(rom.trainer().map(<[u8]>::len), Some(TRAINER_SIZE));
+        assert_eq!(rom.prg_offset(), 16 + TRAINER_SIZE);
+        assert_eq!(rom.prg_rom().len(), 0x4000);
+        assert_eq!(rom.prg_bank_offset(8, 1), 16 + TRAINER_SIZE + 0x2000);
+        // The trainer is 512 bytes, so PRG ROM starts at body offset $200.
+        assert_eq!(rom.prg_rom()[1], 0x01);
+    }
+
+    #[test]
+    fn nes20_unit_counts() {
+        // 0x102 PRG units (high nibble of byte 9), mapper 0x234, submapper 5.
+        let data = header([0x02, 0x01, 0x40, 0x38, 0x52, 0x01, 0, 0, 0, 0, 0, 0]);
+        let h = RomHeader::parse(&data).unwrap();
+        assert_eq!(h.format, HeaderFormat::Nes20);
+        assert_eq!(h.prg_rom_size, 0x102 * 0x4000);
+        assert_eq!(h.chr_rom_size, 0x2000);
+        assert_eq!(h.mapper, 0x234);
+        assert_eq!(h.submapper, 5);
+    }
+
+    #[test]
+    fn nes20_exponent_sizes() {
+        // 2^14 * 3 bytes of PRG ROM, 2^13 * 1 of CHR ROM.
+        let data = header([(14 << 2) | 1, 13 << 2, 0, 0x08, 0, 0xFF, 0, 0, 0, 0, 0, 0]);
+        let h = RomHeader::parse(&data).unwrap();
+        assert_eq!((h.prg_rom_size, h.chr_rom_size), (3 << 14, 1 << 13));
+        assert_eq!(nes20_rom_size(0x02, 0x01, 8), Ok(0x102 * 8));
+        assert!(nes20_rom_size((63 << 2) | 3, 0x0F, 1).is_err());
+    }
+
+    #[test]
+    fn oversized_and_truncated() {
+        // 2^63 bytes of both PRG and CHR ROM add up past usize.
+        let data = header([63 << 2, 63 << 2, 0, 0x08, 0, 0xFF, 0, 0, 0, 0, 0, 0]);
+        assert!(RomImage::parse(data).is_err());
+
+        let mut data = image(header([2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), 2, 1);
+        data.pop();
+        assert!(RomImage::parse(data).unwrap_err().contains("truncated"));
+        assert!(RomImage::parse(header([0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])).is_err());
+        assert!(RomImage::parse(b"NES".to_vec()).is_err());
+    }
+
+    #[test]
+    fn bank_limit() {
+        let db: DisassemblyInfo = serde_json::from_value(serde_json::json!({
+            "name": "test", "title": "Test", "global": {}, "bank": {}, "mapper_window_size": 16,
+        }))
+        .unwrap();
+        let rom = |banks: u8| RomImage { header: RomHeader::parse(&header([banks, 0, 0, 0x08, 0, if banks == 0 { 1 } else { 0 }, 0, 0, 0, 0, 0, 0])).unwrap(), data: Vec::new() };
+        assert_eq!(rom(255).validate(&db), Ok(()));
+        // 256 banks: 0 in the low byte, 1 in the NES 2.0 high nibble.
+        assert_eq!(rom(0).prg_bank_count(16), 256);
+        assert!(rom(0).validate(&db).is_err());
+    }
+
+    #[test]
+    fn fill_unset() {
+        let mut db: DisassemblyInfo = serde_json::from_value(serde_json::json!({
+            "name": "test", "title": "Test", "global": {}, "bank": {}, "mapper_window_size": 16,
+            "mapper_fixed_range": {"start": 0xC000, "end": 0xFFFF},
+        }))
+        .unwrap();
+        let rom = RomImage::parse(image(header([2, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0]), 2, 0)).unwrap();
+        let note = rom.fill_unset(&mut db).unwrap();
+        assert!(note.contains("mapper 1 and 32K of PRG ROM"), "{}", note);
+        assert!(note.contains("MMC1 (fixed $C000) model instead of Generic"), "{}", note);
+        assert_eq!((db.mapper, db.prg_rom_size), (Some(1), Some(0x8000)));
+        assert_eq!(rom.fill_unset(&mut db), None);
+    }
+
+    #[test]
+    fn hashes_skip_the_header() {
+        let a = RomImage::parse(image(header([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), 1, 0)).unwrap();
+        let mut b = a.data.clone();
+        b[10] = 0x55;
+        let b = RomImage::parse(b).unwrap();
+        assert_eq!(a.hashes(), b.hashes());
+        assert_eq!(a.hashes().crc32, format!("{:08X}", crc32fast::hash(&a.data[16..])));
+        assert_eq!(a.hashes().sha1.len(), 40);
+
+        let mut c = a.data.clone();
+        c[16] ^= 1;
+        assert_ne!(a.hashes(), RomImage::parse(c).unwrap().hashes());
+    }
+}
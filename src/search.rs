@@ -0,0 +1,335 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::models::DisassemblyLine;
+
+// The part of a line a search term looks at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    // Symbol, operand, decoded text and comments.
+    Any,
+    Symbol,
+    Opcode,
+    Operand,
+    Text,
+    // Comment or block comment.
+    Comment,
+    Bytes,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sym" | "symbol" => Some(Field::Symbol),
+            "op" | "opcode" => Some(Field::Opcode),
+            "operand" => Some(Field::Operand),
+            "text" => Some(Field::Text),
+            "comment" => Some(Field::Comment),
+            "bytes" => Some(Field::Bytes),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    // Lowercase text, matched case-insensitively.
+    Text(String),
+    Regex(Regex),
+    // Byte signature; `None` matches any byte.
+    Bytes(Vec<Option<u8>>),
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Pattern::Text(a), Pattern::Text(b)) => a == b,
+            (Pattern::Regex(a), Pattern::Regex(b)) => a.as_str() == b.as_str(),
+            (Pattern::Bytes(a), Pattern::Bytes(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Term {
+    pub field: Field,
+    pub pattern: Pattern,
+}
+
+// A parsed search: every term has to match.
+//
+//   ldx #$07          plain text in the symbol, operand, text or comments
+//   sym:/^bank7_/     a regex (`/.../i` ignores case)
+//   op:STA            one field: sym, op, operand, text, comment or bytes
+//   comment:"TO DO"   quotes allow spaces
+//   bytes:"A9 ?? 8D"  a byte signature, which may span several lines
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub terms: Vec<Term>,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut query = Query::default();
+        let mut rest = source.trim_start();
+        // Whether the last term is unquoted plain text that following words
+        // extend, so `foo bar` still finds "foo bar".
+        let mut open_phrase = false;
+        while !rest.is_empty() {
+            let (field, value) = match rest.split_once(':') {
+                Some((name, value)) if !name.contains(char::is_whitespace) => match Field::from_name(&name.to_lowercase()) {
+                    Some(field) => (Some(field), value),
+                    None => (None, rest),
+                },
+                _ => (None, rest),
+            };
+            let (pattern, raw, remaining) = parse_value(value, field == Some(Field::Bytes))?;
+            match (field, raw) {
+                (None, true) if open_phrase => {
+                    if let Some(Term { pattern: Pattern::Text(text), .. }) = query.terms.last_mut()
+                        && let Pattern::Text(word) = &pattern
+                    {
+                        text.push(' ');
+                        text.push_str(word);
+                    }
+                }
+                _ => query.terms.push(Term { field: field.unwrap_or(Field::Any), pattern }),
+            }
+            open_phrase = field.is_none() && raw;
+            rest = remaining.trim_start();
+        }
+        Ok(query)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    // Indices of the lines that match. Byte signatures match at the line
+    // holding their first byte.
+    pub fn matching_lines(&self, lines: &[DisassemblyLine]) -> Vec<usize> {
+        if self.terms.is_empty() {
+            return Vec::new();
+        }
+        let mut hits = vec![true; lines.len()];
+        for term in &self.terms {
+            if let Pattern::Bytes(signature) = &term.pattern {
+                let starts = signature_starts(lines, signature);
+                hits.iter_mut().zip(starts).for_each(|(hit, start)| *hit &= start);
+            } else {
+                hits.iter_mut().zip(lines).for_each(|(hit, line)| *hit = *hit && term.matches(line));
+            }
+        }
+        hits.iter().enumerate().filter(|&(_, &hit)| hit).map(|(i, _)| i).collect()
+    }
+
+    // Text to highlight in the grid: the first plain-text term.
+    pub fn highlight(&self) -> String {
+        self.terms
+            .iter()
+            .find_map(|term| match &term.pattern {
+                Pattern::Text(text) => Some(text.clone()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Term {
+    fn matches(&self, line: &DisassemblyLine) -> bool {
+        let operand = format!("{}{}{}", line.operand_prefix, line.operand_main, line.operand_suffix);
+        let fields: Vec<&str> = match self.field {
+            Field::Any => vec![
+                line.symbol.as_deref().unwrap_or_default(),
+                &line.operand_main,
+                line.text.as_deref().unwrap_or_default(),
+                line.comment.as_deref().unwrap_or_default(),
+                line.block_comment.as_deref().unwrap_or_default(),
+            ],
+            Field::Symbol => vec![line.symbol.as_deref().unwrap_or_default()],
+            Field::Opcode => vec![&line.opcode],
+            Field::Operand => vec![&operand],
+            Field::Text => vec![line.text.as_deref().unwrap_or_default()],
+            Field::Comment => vec![line.comment.as_deref().unwrap_or_default(), line.block_comment.as_deref().unwrap_or_default()],
+            Field::Bytes => vec![&line.bytes],
+        };
+        fields.into_iter().any(|field| match &self.pattern {
+            Pattern::Text(text) => !field.is_empty() && field.to_lowercase().contains(text),
+            Pattern::Regex(re) => !field.is_empty() && re.is_match(field),
+            Pattern::Bytes(_) => false,
+        })
+    }
+}
+
+// Parse one value: `/regex/`, `"quoted"` or a bare word. Returns the pattern,
+// whether it was a bare word, and the text after it.
+fn parse_value(value: &str, bytes: bool) -> Result<(Pattern, bool, &str), String> {
+    if let Some(body) = value.strip_prefix('/') {
+        let end = regex_end(body).ok_or_else(|| format!("unterminated regex /{}", body))?;
+        let (source, mut rest) = (&body[..end], &body[end + 1..]);
+        let ignore_case = rest.strip_prefix('i').is_some_and(|r| r.is_empty() || r.starts_with(char::is_whitespace));
+        if ignore_case {
+            rest = &rest[1..];
+        }
+        let re = RegexBuilder::new(&source.replace("\\/", "/"))
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| format!("bad regex /{}/: {}", source, e))?;
+        return Ok((Pattern::Regex(re), false, rest));
+    }
+    let (text, raw, rest) = match value.strip_prefix('"') {
+        Some(body) => {
+            let end = body.find('"').ok_or_else(|| format!("unterminated quote \"{}", body))?;
+            (&body[..end], false, &body[end + 1..])
+        }
+        None => {
+            let end = value.find(char::is_whitespace).unwrap_or(value.len());
+            (&value[..end], true, &value[end..])
+        }
+    };
+    let pattern = if bytes { Pattern::Bytes(parse_signature(text)?) } else { Pattern::Text(text.to_lowercase()) };
+    Ok((pattern, raw, rest))
+}
+
+// Position of the `/` closing a regex, skipping `\/`.
+fn regex_end(body: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '/' if !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+// `A9 ?? 8D` or `A9??8D`: hex bytes with `??` as a wildcard.
+fn parse_signature(text: &str) -> Result<Vec<Option<u8>>, String> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(c) = digits.iter().find(|&&c| !c.is_ascii_hexdigit() && c != '?') {
+        return Err(format!("bad character {:?} in signature", c));
+    }
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("byte signature \"{}\" needs whole bytes", text));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| match pair {
+            ['?', '?'] => Ok(None),
+            _ => {
+                let hex: String = pair.iter().collect();
+                u8::from_str_radix(&hex, 16).map(Some).map_err(|_| format!("bad byte {} in signature", hex))
+            }
+        })
+        .collect()
+}
+
+// For each line, whether the signature starts at one of its bytes. The bytes
+// of consecutive lines form one stream as long as their addresses follow on.
+fn signature_starts(lines: &[DisassemblyLine], signature: &[Option<u8>]) -> Vec<bool> {
+    let mut starts = vec![false; lines.len()];
+    let mut stream: Vec<(u8, usize)> = Vec::new();
+    let mut next: Option<(i16, u32)> = None;
+    let mut scan = |stream: &mut Vec<(u8, usize)>| {
+        for window in stream.windows(signature.len()) {
+            if window.iter().zip(signature).all(|(&(byte, _), want)| want.is_none_or(|w| w == byte)) {
+                starts[window[0].1] = true;
+            }
+        }
+        stream.clear();
+    };
+    for (i, line) in lines.iter().enumerate() {
+        let bytes: Vec<u8> = line.bytes.split_whitespace().filter_map(|b| u8::from_str_radix(b, 16).ok()).collect();
+        if bytes.is_empty() {
+            continue;
+        }
+        if next != Some((line.bank, line.address as u32)) {
+            scan(&mut stream);
+        }
+        stream.extend(bytes.iter().map(|&b| (b, i)));
+        next = Some((line.bank, line.address as u32 + bytes.len() as u32));
+    }
+    scan(&mut stream);
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(address: u16, bytes: &str, opcode: &str, operand: &str, symbol: Option<&str>, comment: Option<&str>) -> DisassemblyLine {
+        DisassemblyLine {
+            address_label: format!("$00:${:04X}", address),
+            address,
+            bank: 0,
+            bytes: bytes.to_string(),
+            opcode: opcode.to_string(),
+            operand_prefix: String::new(),
+            operand_main: operand.to_string(),
+            operand_suffix: String::new(),
+            operand_is_symbol: false,
+            symbol: symbol.map(str::to_string),
+            comment: comment.map(str::to_string),
+            block_comment: None,
+            target_bank: None,
+            target_address: None,
+            text: None,
+        }
+    }
+
+    fn lines() -> Vec<DisassemblyLine> {
+        vec![
+            line(0x8000, "A9 07", "LDA", "#$07", Some("bank7_Reset"), Some("To do: check")),
+            line(0x8002, "8D 00 20", "STA", "PPUCTRL", None, None),
+            line(0x8005, "A9 07", "LDA", "#$07", None, None),
+            // Not contiguous with the line before.
+            line(0x8010, "8D 01 20", "STA", "PPUMASK", Some("Store"), None),
+        ]
+    }
+
+    fn find(query: &str) -> Vec<usize> {
+        Query::parse(query).unwrap().matching_lines(&lines())
+    }
+
+    #[test]
+    fn field_prefixes() {
+        assert_eq!(find("lda #$07"), Vec::<usize>::new());
+        assert_eq!(find("#$07"), vec![0, 2]);
+        assert_eq!(find("op:sta"), vec![1, 3]);
+        assert_eq!(find("Opcode:STA sym:store"), vec![3]);
+        assert_eq!(find("comment:\"to do\""), vec![0]);
+        assert_eq!(find("to do"), vec![0]);
+        assert_eq!(find("operand:PPU"), vec![1, 3]);
+        // An unknown prefix is part of the text.
+        assert_eq!(find("to:"), Vec::<usize>::new());
+        assert_eq!(find("do: check"), vec![0]);
+    }
+
+    #[test]
+    fn regex() {
+        assert_eq!(find("sym:/^bank7_/"), vec![0]);
+        assert_eq!(find("sym:/^BANK7_/"), Vec::<usize>::new());
+        assert_eq!(find("sym:/^BANK7_/i"), vec![0]);
+        assert_eq!(find(r"operand:/PPU(CTRL|MASK)\/?/ op:STA"), vec![1, 3]);
+        assert!(Query::parse("sym:/unterminated").is_err());
+        assert!(Query::parse("sym:/(/").is_err());
+    }
+
+    #[test]
+    fn byte_signatures() {
+        assert_eq!(find("bytes:\"A9 07 8D\""), vec![0]);
+        assert_eq!(find("bytes:A9??8D"), vec![0]);
+        assert_eq!(find("bytes:\"8D ?? 20\""), vec![1, 3]);
+        // $8005 and $8010 are not contiguous.
+        assert_eq!(find("bytes:\"07 8D 01\""), Vec::<usize>::new());
+        assert_eq!(find("bytes:07 op:lda"), vec![0, 2]);
+    }
+
+    #[test]
+    fn bad_signatures_are_errors() {
+        for query in ["bytes:+1", "bytes:é9", "bytes:\"A9 ?\"", "bytes:A", "bytes:\"\"", "bytes:G0", "bytes:?A"] {
+            assert!(Query::parse(query).is_err(), "{}", query);
+        }
+    }
+}